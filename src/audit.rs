@@ -0,0 +1,68 @@
+use crate::exec::{Execution, Invocation};
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::sync::Mutex;
+
+/// Bytes of the output and of the error output kept in each entry.
+const OUTPUT_BYTES: usize = 1024;
+
+/// A file with a JSON line per command launched, shared by the workers.
+pub struct AuditLog {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl AuditLog {
+    /// Creates the file at `path`, or appends to it when a run is resumed.
+    pub fn open(path: &str, resumed: bool) -> Result<AuditLog> {
+        let file = match resumed {
+            true => OpenOptions::new().append(true).create(true).open(path),
+            false => File::create(path),
+        }
+        .context(format!("Failed to create {}", path))?;
+        Ok(AuditLog {
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    /// Writes the entry of `invocation`, for the record at `index`.
+    pub fn write(
+        &self,
+        index: usize,
+        invocation: &Invocation,
+        execution: &Result<Execution>,
+    ) -> Result<()> {
+        let mut argv = vec![invocation.command.clone()];
+        argv.extend(invocation.args.iter().cloned());
+        let mut entry = serde_json::json!({
+            "row": index + 1,
+            "argv": argv,
+        });
+        match execution {
+            Ok(execution) => {
+                entry["exit_code"] = serde_json::json!(execution.exit_code());
+                entry["started"] = execution
+                    .started
+                    .to_rfc3339_opts(SecondsFormat::Millis, true)
+                    .into();
+                entry["duration_ms"] = (execution.duration.as_millis() as u64).into();
+                entry["attempts"] = execution.attempts.into();
+                entry["stdout"] = truncated(&execution.stdout).into();
+                entry["stderr"] = truncated(&execution.stderr).into();
+            }
+            Err(error) => entry["error"] = format!("{:#}", error).into(),
+        }
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(&line)
+            .context("Failed to write the audit log")
+    }
+}
+
+fn truncated(output: &[u8]) -> String {
+    String::from_utf8_lossy(&output[..output.len().min(OUTPUT_BYTES)]).into_owned()
+}
@@ -0,0 +1,79 @@
+use std::sync::Mutex;
+
+/// Aborts the run when too many rows failed, counted by all the workers together as soon as
+/// their commands complete, so that the queued rows are not launched meanwhile.
+pub struct Breaker {
+    fail_fast: bool,
+    abort_after_failures: Option<u64>,
+    max_failure_rate: Option<f64>,
+    /// Number of rows before the rate is checked
+    failure_rate_warm_up: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    rows: u64,
+    failed_rows: u64,
+    consecutive_failures: u64,
+    /// Why the run is aborted
+    reason: Option<String>,
+}
+
+impl Breaker {
+    pub fn new(
+        fail_fast: bool,
+        abort_after_failures: Option<u64>,
+        max_failure_rate: Option<f64>,
+        failure_rate_warm_up: u64,
+    ) -> Breaker {
+        Breaker {
+            fail_fast,
+            abort_after_failures,
+            max_failure_rate,
+            failure_rate_warm_up,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Counts the completed row of the record at `index`, and returns whether the run is
+    /// aborted.
+    pub fn add(&self, index: usize, failed: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.reason.is_some() {
+            return true;
+        }
+        state.rows += 1;
+        if failed {
+            state.failed_rows += 1;
+        }
+        state.consecutive_failures = if failed {
+            state.consecutive_failures + 1
+        } else {
+            0
+        };
+        if failed && self.fail_fast {
+            state.reason = Some(format!("Stopped at the failure of record {}", index + 1));
+        } else if self.abort_after_failures == Some(state.consecutive_failures) {
+            state.reason = Some(format!(
+                "Stopped after {} consecutive failures, at record {}",
+                state.consecutive_failures,
+                index + 1
+            ));
+        } else if let Some(rate) = self.max_failure_rate.filter(|&rate| {
+            state.rows >= self.failure_rate_warm_up.max(1)
+                && state.failed_rows as f64 > rate * state.rows as f64
+        }) {
+            state.reason = Some(format!(
+                "Stopped as {} of the {} rows written failed, more than the maximum rate of {}",
+                state.failed_rows, state.rows, rate
+            ));
+        }
+        state.reason.is_some()
+    }
+
+    /// Why the run is aborted, if it is.
+    pub fn reason(&self) -> Option<String> {
+        self.state.lock().unwrap().reason.clone()
+    }
+}
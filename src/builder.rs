@@ -0,0 +1,690 @@
+use crate::{
+    input, run_with, BatchInput, Config, InputFormat, OnError, OutputEncoding, OutputFormat,
+    OutputOverflow, QuoteStyle, Ragged, SplitPolicy, StdinRecord, Step, Terminator, Trim,
+};
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Runs commands, or expressions, on each record of a CSV input, and writes the records with
+/// their results, like the csv-exec command.
+///
+/// ```no_run
+/// use csv_exec::CsvExec;
+///
+/// let csv_exec = CsvExec::builder()
+///     .command("curl -s https://$Dir")
+///     .new_column_name("Page")
+///     .jobs(4)
+///     .build()?;
+/// let mut output = vec![];
+/// csv_exec.run(std::fs::File::open("test.csv")?, &mut output)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct CsvExec {
+    config: Config,
+}
+
+/// Options of a `CsvExec`, which are the ones of the command line. The columns are given by
+/// name or by position, beginning at 1, as on the command line.
+pub struct Builder {
+    config: Config,
+}
+
+impl CsvExec {
+    pub fn builder() -> Builder {
+        Builder {
+            config: Config::default(),
+        }
+    }
+
+    /// Reads the records from `reader`, unless input files are given, and writes them with their
+    /// results to `writer`, unless an output file is given. Returns the exit status the command
+    /// line would have.
+    pub fn run(&self, reader: impl Read, writer: impl Write + Send) -> Result<i32> {
+        let stdin = input::Stdin::new(Box::new(reader));
+        run_with(self.config.clone(), stdin, Box::new(writer))
+    }
+}
+
+impl Builder {
+    /// Adds an input file, glob pattern or http(s) URL, as --input.
+    pub fn input_path(mut self, path: impl Into<String>) -> Builder {
+        self.config.input_paths.push(path.into());
+        self
+    }
+
+    pub fn input_format(mut self, format: InputFormat) -> Builder {
+        self.config.input_format = format;
+        self
+    }
+
+    /// Sheet of the xlsx input, instead of the first one.
+    pub fn sheet(mut self, sheet: impl Into<String>) -> Builder {
+        self.config.sheet = Some(sheet.into());
+        self
+    }
+
+    /// Reads the input from the result of `query` on this SQLite database.
+    pub fn input_sqlite(mut self, path: impl Into<String>, query: impl Into<String>) -> Builder {
+        self.config.input_sqlite = Some(path.into());
+        self.config.query = Some(query.into());
+        self
+    }
+
+    /// Adds a column with this name to the input, with the path of the file of each record.
+    pub fn add_filename_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.filename_column = Some(name.into());
+        self
+    }
+
+    pub fn output_path(mut self, path: impl Into<String>) -> Builder {
+        self.config.output_path = Some(path.into());
+        self
+    }
+
+    /// Writes each row to the file given by the template, as --output-template.
+    pub fn output_template(mut self, template: impl Into<String>) -> Builder {
+        self.config.output_template = Some(template.into());
+        self
+    }
+
+    /// Inserts the output rows into this table of a SQLite database, instead of writing them.
+    pub fn output_sqlite(mut self, path: impl Into<String>, table: impl Into<String>) -> Builder {
+        self.config.output_sqlite = Some(path.into());
+        self.config.table = Some(table.into());
+        self
+    }
+
+    pub fn output_format(mut self, format: OutputFormat) -> Builder {
+        self.config.output_format = format;
+        self
+    }
+
+    pub fn append(mut self, append: bool) -> Builder {
+        self.config.append = append;
+        self
+    }
+
+    pub fn no_clobber(mut self, no_clobber: bool) -> Builder {
+        self.config.no_clobber = no_clobber;
+        self
+    }
+
+    /// Adds a command, after the previous commands and expressions, as --exec.
+    pub fn command(mut self, command: impl Into<String>) -> Builder {
+        self.config.exec.push(Step::Command(command.into()));
+        self
+    }
+
+    /// Adds an expression, after the previous commands and expressions, as --expr.
+    pub fn expression(mut self, expression: impl Into<String>) -> Builder {
+        self.config.exec.push(Step::Expression(expression.into()));
+        self
+    }
+
+    pub fn no_headers(mut self, no_headers: bool) -> Builder {
+        self.config.no_headers = no_headers;
+        self
+    }
+
+    /// Of one or several characters, for both the input and the output.
+    pub fn delimiter(mut self, delimiter: impl Into<String>) -> Builder {
+        self.config.delimiter = delimiter.into();
+        self
+    }
+
+    pub fn out_delimiter(mut self, delimiter: impl Into<String>) -> Builder {
+        self.config.out_delimiter = Some(delimiter.into());
+        self
+    }
+
+    /// An ASCII character, for both the input and the output.
+    pub fn quote(mut self, quote: u8) -> Builder {
+        self.config.quote = quote;
+        self
+    }
+
+    /// Without quotes, which are characters like the others.
+    pub fn quoting(mut self, quoting: bool) -> Builder {
+        self.config.quoting = quoting;
+        self
+    }
+
+    /// Escapes the quotes in the quoted fields with this ASCII character, instead of doubling
+    /// them.
+    pub fn escape(mut self, escape: u8) -> Builder {
+        self.config.escape = Some(escape);
+        self
+    }
+
+    /// Skips the input lines starting with this ASCII character.
+    pub fn comment(mut self, comment: u8) -> Builder {
+        self.config.comment = Some(comment);
+        self
+    }
+
+    /// Skips the first lines of each input file, before its header line.
+    pub fn skip_lines(mut self, lines: usize) -> Builder {
+        self.config.skip_lines = lines;
+        self
+    }
+
+    /// What is done with the records which have not the number of fields of the header line.
+    pub fn flexible(mut self, ragged: Ragged) -> Builder {
+        self.config.flexible = ragged;
+        self
+    }
+
+    pub fn trim(mut self, trim: Trim) -> Builder {
+        self.config.trim = trim;
+        self
+    }
+
+    /// Reads the fields as bytes, which may not be UTF-8, as --bytes.
+    pub fn bytes(mut self, bytes: bool) -> Builder {
+        self.config.bytes = bytes;
+        self
+    }
+
+    pub fn out_quote(mut self, quote: u8) -> Builder {
+        self.config.out_quote = Some(quote);
+        self
+    }
+
+    /// Of the input, and of the output unless `out_terminator` is given.
+    pub fn terminator(mut self, terminator: Terminator) -> Builder {
+        self.config.terminator = Some(terminator);
+        self
+    }
+
+    pub fn out_terminator(mut self, terminator: Terminator) -> Builder {
+        self.config.out_terminator = Some(terminator);
+        self
+    }
+
+    /// Label of the character encoding of the input and the output, e.g. latin1.
+    pub fn encoding(mut self, label: impl Into<String>) -> Builder {
+        self.config.encoding = Some(label.into());
+        self
+    }
+
+    pub fn out_encoding(mut self, label: impl Into<String>) -> Builder {
+        self.config.out_encoding = Some(label.into());
+        self
+    }
+
+    pub fn quote_style(mut self, quote_style: QuoteStyle) -> Builder {
+        self.config.quote_style = Some(quote_style);
+        self
+    }
+
+    /// Guesses the delimiter and whether there is a header line from the beginning of the input.
+    pub fn sniff(mut self, sniff: bool) -> Builder {
+        self.config.sniff = sniff;
+        self
+    }
+
+    /// Regex of the references in the commands, as --arg-regex.
+    pub fn arg_regex(mut self, regex: impl Into<String>) -> Builder {
+        self.config.arg_regex = regex.into();
+        self
+    }
+
+    /// Fails on the unknown references, instead of leaving them untouched.
+    pub fn strict_refs(mut self, strict_refs: bool) -> Builder {
+        self.config.strict_refs = strict_refs;
+        self
+    }
+
+    /// Checks the input and the references without running the commands, as --check.
+    pub fn check(mut self, check: bool) -> Builder {
+        self.config.check = check;
+        self
+    }
+
+    /// Adds the name of a new column: the first one is the column of the first command or
+    /// expression, and so on.
+    pub fn new_column_name(mut self, name: impl Into<String>) -> Builder {
+        self.config.new_column_name.push(name.into());
+        self
+    }
+
+    /// Writes the results in this column, instead of a new column.
+    pub fn replace_column(mut self, column: impl Into<String>) -> Builder {
+        self.config.replace_column = Some(column.into());
+        self
+    }
+
+    /// Replaces the fields of this column by the results, the commands referencing them as
+    /// $CELL.
+    pub fn map(mut self, column: impl Into<String>) -> Builder {
+        let column = column.into();
+        self.config.replace_column = Some(column.clone());
+        self.config.map = Some(column);
+        self
+    }
+
+    /// Does not run the commands for the records which already have a result, as
+    /// --skip-populated.
+    pub fn skip_populated(mut self, skip_populated: bool) -> Builder {
+        self.config.skip_populated = skip_populated;
+        self
+    }
+
+    /// Runs the commands only for the records for which this expression is true, as --where.
+    pub fn condition(mut self, condition: impl Into<String>) -> Builder {
+        self.config.condition = Some(condition.into());
+        self
+    }
+
+    /// Result of the records which do not match the condition, instead of an empty one.
+    pub fn otherwise(mut self, result: impl Into<String>) -> Builder {
+        self.config.otherwise = Some(result.into());
+        self
+    }
+
+    pub fn no_trim(mut self, no_trim: bool) -> Builder {
+        self.config.no_trim = no_trim;
+        self
+    }
+
+    pub fn first_line_only(mut self, first_line_only: bool) -> Builder {
+        self.config.first_line_only = first_line_only;
+        self
+    }
+
+    /// Joins the lines of the output of the command with this separator.
+    pub fn join_lines(mut self, separator: impl Into<String>) -> Builder {
+        self.config.join_lines = Some(separator.into());
+        self
+    }
+
+    /// Writes the record once for each line of the output of the command.
+    pub fn explode(mut self, explode: bool) -> Builder {
+        self.config.explode = explode;
+        self
+    }
+
+    /// Runs the commands once for each group of records with the same value in this column.
+    pub fn group_by(mut self, column: impl Into<String>) -> Builder {
+        self.config.group_by = Some(column.into());
+        self
+    }
+
+    /// Writes only the first record of each group.
+    pub fn summarize(mut self, summarize: bool) -> Builder {
+        self.config.summarize = summarize;
+        self
+    }
+
+    /// Runs this command once at the end, with the whole output on its input, as --finally.
+    pub fn finally(mut self, command: impl Into<String>) -> Builder {
+        self.config.finally = Some(command.into());
+        self
+    }
+
+    /// Writes the output of the --finally command to this file, instead of a last record.
+    pub fn finally_output(mut self, path: impl Into<String>) -> Builder {
+        self.config.finally_output = Some(path.into());
+        self
+    }
+
+    pub fn output_encoding(mut self, encoding: OutputEncoding) -> Builder {
+        self.config.output_encoding = encoding;
+        self
+    }
+
+    /// Splits the result into the columns of `result_headers`, at each ASCII `separator`.
+    pub fn split_output(mut self, separator: u8) -> Builder {
+        self.config.split_output = Some(separator);
+        self
+    }
+
+    /// Extracts these JSON paths of the result into columns, as --json-output.
+    pub fn json_output(mut self, paths: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.json_output = Some(strings(paths));
+        self
+    }
+
+    /// Extracts the capturing groups of this regex from the result into columns.
+    pub fn capture(mut self, regex: impl Into<String>) -> Builder {
+        self.config.capture = Some(regex.into());
+        self
+    }
+
+    /// Names of the columns extracted from the result.
+    pub fn result_headers(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.result_headers = Some(strings(names));
+        self
+    }
+
+    pub fn split_policy(mut self, policy: SplitPolicy) -> Builder {
+        self.config.split_policy = policy;
+        self
+    }
+
+    /// Inserts the new columns at this position, beginning at 1, instead of the end.
+    pub fn column_position(mut self, position: usize) -> Builder {
+        self.config.column_position = Some(position);
+        self
+    }
+
+    pub fn before(mut self, column: impl Into<String>) -> Builder {
+        self.config.before = Some(column.into());
+        self
+    }
+
+    pub fn after(mut self, column: impl Into<String>) -> Builder {
+        self.config.after = Some(column.into());
+        self
+    }
+
+    /// Writes only these columns of the input, and the new columns.
+    pub fn select(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.select = Some(strings(columns));
+        self
+    }
+
+    pub fn drop(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.drop = Some(strings(columns));
+        self
+    }
+
+    /// Writes these columns of the output first, in this order.
+    pub fn order(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.order = Some(strings(columns));
+        self
+    }
+
+    pub fn drop_unlisted(mut self, drop_unlisted: bool) -> Builder {
+        self.config.drop_unlisted = drop_unlisted;
+        self
+    }
+
+    /// Writes only these key columns of the input and the new columns, or the number of the
+    /// record without any key column, as --only-result.
+    pub fn only_result(mut self, columns: impl IntoIterator<Item = impl Into<String>>) -> Builder {
+        self.config.only_result = Some(strings(columns));
+        self
+    }
+
+    /// Renames a column in the header line of the output, including the new ones.
+    pub fn rename(mut self, old: impl Into<String>, new: impl Into<String>) -> Builder {
+        self.config.rename.push((old.into(), new.into()));
+        self
+    }
+
+    pub fn exit_code_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.exit_code_column = Some(name.into());
+        self
+    }
+
+    pub fn stderr_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.stderr_column = Some(name.into());
+        self
+    }
+
+    pub fn duration_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.duration_column = Some(name.into());
+        self
+    }
+
+    pub fn started_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.started_column = Some(name.into());
+        self
+    }
+
+    pub fn attempts_column(mut self, name: impl Into<String>) -> Builder {
+        self.config.attempts_column = Some(name.into());
+        self
+    }
+
+    /// Number of commands run in parallel.
+    pub fn jobs(mut self, jobs: usize) -> Builder {
+        self.config.jobs = jobs;
+        self
+    }
+
+    /// Writes the records as soon as their commands complete, instead of in input order.
+    pub fn unordered(mut self, unordered: bool) -> Builder {
+        self.config.unordered = unordered;
+        self
+    }
+
+    /// Runs the commands with `sh -c`, after the substitution of the references.
+    pub fn shell(mut self, shell: bool) -> Builder {
+        self.config.shell = shell;
+        self
+    }
+
+    /// Shell running the commands, with its arguments, e.g. `bash -O extglob`.
+    pub fn shell_path(mut self, shell: impl Into<String>) -> Builder {
+        self.config.shell_path = Some(shell.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Builder {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    /// Stops launching commands after this duration, as --max-runtime.
+    pub fn max_runtime(mut self, max_runtime: Duration) -> Builder {
+        self.config.max_runtime = Some(max_runtime);
+        self
+    }
+
+    /// Writes the records as CSV lines to a single instance of the command, as --coprocess.
+    pub fn coprocess(mut self, coprocess: bool) -> Builder {
+        self.config.coprocess = coprocess;
+        self
+    }
+
+    /// Runs the command once for this number of records, as --batch-size.
+    pub fn batch_size(mut self, size: usize) -> Builder {
+        self.config.batch_size = Some(size);
+        self
+    }
+
+    pub fn batch_input(mut self, batch_input: BatchInput) -> Builder {
+        self.config.batch_input = batch_input;
+        self
+    }
+
+    /// Writes the record to the input of its command.
+    pub fn stdin_record(mut self, format: StdinRecord) -> Builder {
+        self.config.stdin_record = Some(format);
+        self
+    }
+
+    /// Writes the value of this column to the input of the command.
+    pub fn stdin_field(mut self, column: impl Into<String>) -> Builder {
+        self.config.stdin_field = Some(column.into());
+        self
+    }
+
+    /// Gives the fields of the record to its command as environment variables.
+    pub fn field_env(mut self, field_env: bool) -> Builder {
+        self.config.field_env = field_env;
+        self
+    }
+
+    /// Sets an environment variable of the commands, whose value is substituted.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Builder {
+        self.config.env.push((key.into(), value.into()));
+        self
+    }
+
+    /// Does not give the environment variables to the commands, except the ones of `pass_env`.
+    pub fn clean_env(mut self, clean_env: bool) -> Builder {
+        self.config.clean_env = clean_env;
+        self
+    }
+
+    pub fn pass_env(mut self, name: impl Into<String>) -> Builder {
+        self.config.pass_env.push(name.into());
+        self
+    }
+
+    /// Working directory of the commands, whose references are substituted.
+    pub fn cwd(mut self, cwd: impl Into<String>) -> Builder {
+        self.config.cwd = Some(cwd.into());
+        self
+    }
+
+    /// Keeps at most this number of bytes of the output, and of the error output, of each command.
+    pub fn max_output_bytes(mut self, bytes: u64) -> Builder {
+        self.config.max_output_bytes = Some(bytes);
+        self
+    }
+
+    pub fn output_overflow(mut self, overflow: OutputOverflow) -> Builder {
+        self.config.output_overflow = overflow;
+        self
+    }
+
+    /// Draws the progress on the error output when it is a terminal, which is not done by
+    /// default.
+    pub fn progress(mut self, progress: bool) -> Builder {
+        self.config.progress = progress;
+        self
+    }
+
+    /// Gives a summary of the run to `on_message`.
+    pub fn summary(mut self, summary: bool) -> Builder {
+        self.config.summary = summary;
+        self
+    }
+
+    /// Receives the warnings, the errors of the records skipped by `on_error` and the summary,
+    /// which are dropped by default.
+    pub fn on_message(mut self, on_message: impl Fn(&str) + Send + Sync + 'static) -> Builder {
+        self.config.on_message = Some(Arc::new(on_message));
+        self
+    }
+
+    /// Writes the summary of the run to this file, as JSON.
+    pub fn summary_json(mut self, path: impl Into<String>) -> Builder {
+        self.config.summary_json = Some(path.into());
+        self
+    }
+
+    /// Appends a line of JSON to this file for each command run.
+    pub fn audit_log(mut self, path: impl Into<String>) -> Builder {
+        self.config.audit_log = Some(path.into());
+        self
+    }
+
+    /// Writes only the records whose commands exit with 0, as they are.
+    pub fn filter(mut self, filter: bool) -> Builder {
+        self.config.filter = filter;
+        self
+    }
+
+    /// Writes the command lines, once substituted, instead of running them.
+    pub fn dry_run(mut self, dry_run: bool) -> Builder {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Runs identical commands only once, and reuses the output of the first successful run.
+    pub fn memoize(mut self, memoize: bool) -> Builder {
+        self.config.memoize = memoize;
+        self
+    }
+
+    /// Runs identical commands exactly once, whether they succeed or not.
+    pub fn unique(mut self, unique: bool) -> Builder {
+        self.config.unique = unique;
+        self
+    }
+
+    pub fn on_error(mut self, policy: OnError) -> Builder {
+        self.config.on_error = Some(policy);
+        self
+    }
+
+    /// Returns 123 if a command failed or exited with a non-zero status.
+    pub fn strict_exit(mut self, strict_exit: bool) -> Builder {
+        self.config.strict_exit = strict_exit;
+        self
+    }
+
+    pub fn fail_fast(mut self, fail_fast: bool) -> Builder {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// Stops after this positive number of consecutive failed rows.
+    pub fn abort_after_failures(mut self, failures: u64) -> Builder {
+        self.config.abort_after_failures = Some(failures);
+        self
+    }
+
+    /// Stops when more than this fraction of the rows failed, once `failure_rate_warm_up` rows
+    /// are written.
+    pub fn max_failure_rate(mut self, rate: f64) -> Builder {
+        self.config.max_failure_rate = Some(rate);
+        self
+    }
+
+    pub fn failure_rate_warm_up(mut self, rows: u64) -> Builder {
+        self.config.failure_rate_warm_up = rows;
+        self
+    }
+
+    /// Saves the position of the run in this file, to continue it with `resume`.
+    pub fn checkpoint(mut self, path: impl Into<String>) -> Builder {
+        self.config.checkpoint = Some(path.into());
+        self
+    }
+
+    pub fn resume(mut self, resume: bool) -> Builder {
+        self.config.resume = resume;
+        self
+    }
+
+    pub fn retries(mut self, retries: u32) -> Builder {
+        self.config.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, randomly reduced by up to a half.
+    pub fn retry_delay(mut self, delay: Duration) -> Builder {
+        self.config.retry_delay = delay;
+        self
+    }
+
+    /// Factor applied to the delay after each retry, at least 1.
+    pub fn retry_backoff(mut self, factor: f64) -> Builder {
+        self.config.retry_backoff = factor;
+        self
+    }
+
+    /// Retries only the commands exiting with these codes, besides the ones which timed out or
+    /// failed to start.
+    pub fn retry_on_exit_codes(mut self, codes: impl IntoIterator<Item = i32>) -> Builder {
+        self.config.retry_on_exit_codes = Some(codes.into_iter().collect());
+        self
+    }
+
+    /// Maximum number of commands launched per second, whatever the number of jobs.
+    pub fn rate(mut self, per_second: f64) -> Builder {
+        self.config.rate = Some(per_second);
+        self
+    }
+
+    /// Checks the options which do not depend on the input, which is read by `run`.
+    pub fn build(self) -> Result<CsvExec> {
+        self.config.check()?;
+        Ok(CsvExec {
+            config: self.config,
+        })
+    }
+}
+
+fn strings(values: impl IntoIterator<Item = impl Into<String>>) -> Vec<String> {
+    values.into_iter().map(Into::into).collect()
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use encoding_rs::{EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, Read, Write};
+
+/// The encoding of a label such as `latin1`, `windows-1252` or `utf-16le`, or `None` for UTF-8,
+/// which needs no conversion.
+pub fn from_label(label: &str) -> Result<Option<&'static Encoding>> {
+    match Encoding::for_label(label.as_bytes()) {
+        Some(encoding) if encoding == UTF_8 => Ok(None),
+        Some(encoding) => Ok(Some(encoding)),
+        None => Err(anyhow!("Unknown encoding {}", label)),
+    }
+}
+
+/// Converts the text read in `encoding` to UTF-8. The invalid sequences are replaced by U+FFFD,
+/// and a byte order mark takes precedence over `encoding`.
+pub fn decoder<'a>(reader: Box<dyn Read + 'a>, encoding: &'static Encoding) -> Box<dyn Read + 'a> {
+    Box::new(
+        DecodeReaderBytesBuilder::new()
+            .encoding(Some(encoding))
+            .build(reader),
+    )
+}
+
+/// Converts the UTF-8 text written to it to an encoding, or else writes it as is.
+pub struct Encoder<W: Write> {
+    inner: W,
+    encoding: Option<&'static Encoding>,
+    encoder: Option<encoding_rs::Encoder>,
+    /// Beginning of a character whose end is not written yet
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, encoding: Option<&'static Encoding>) -> Encoder<W> {
+        Encoder {
+            inner,
+            encoding,
+            // Note: encoding_rs only decodes UTF-16, which is encoded here
+            encoder: encoding
+                .filter(|&encoding| encoding != UTF_16LE && encoding != UTF_16BE)
+                .map(Encoding::new_encoder),
+            pending: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Ends the encoded text, and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.pending.is_empty() {
+            return Err(invalid_data(
+                "Incomplete UTF-8 character at the end".to_string(),
+            ));
+        }
+        self.encode("", true)?;
+        Ok(self.inner)
+    }
+
+    fn encode(&mut self, text: &str, last: bool) -> io::Result<()> {
+        let bytes = match (&mut self.encoder, self.encoding) {
+            (_, Some(encoding)) if encoding == UTF_16LE => {
+                text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+            }
+            (_, Some(encoding)) if encoding == UTF_16BE => {
+                text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+            }
+            (Some(encoder), Some(encoding)) => {
+                let length = encoder
+                    .max_buffer_length_from_utf8_without_replacement(text.len())
+                    .unwrap_or(text.len() * 4 + 16);
+                let mut bytes = vec![0; length];
+                let (result, read, written) =
+                    encoder.encode_from_utf8_without_replacement(text, &mut bytes, last);
+                if let EncoderResult::Unmappable(character) = result {
+                    return Err(invalid_data(format!(
+                        "Character {} cannot be written in {}",
+                        character,
+                        encoding.name()
+                    )));
+                }
+                debug_assert_eq!(read, text.len());
+                bytes.truncate(written);
+                bytes
+            }
+            _ => return self.inner.write_all(text.as_bytes()),
+        };
+        self.inner.write_all(&bytes)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding.is_none() {
+            return self.inner.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            // The rest is the beginning of a character
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => return Err(invalid_data("Invalid UTF-8 output".to_string())),
+        };
+        let pending: Vec<u8> = self.pending.drain(..valid).collect();
+        self.encode(std::str::from_utf8(&pending).unwrap(), false)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
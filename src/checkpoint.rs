@@ -0,0 +1,96 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Minimum interval between two saves of the checkpoint during a run.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far a run went: the records done from the start of the input, whether their row was
+/// written or skipped, and the bytes of output written until then.
+#[derive(Clone, Copy, Default)]
+pub struct Position {
+    pub rows: u64,
+    pub offset: u64,
+}
+
+/// A file where the position of the run is saved, to resume it.
+pub struct Checkpoint {
+    path: String,
+    last_save: Instant,
+}
+
+impl Checkpoint {
+    pub fn new(path: &str) -> Checkpoint {
+        Checkpoint {
+            path: path.to_string(),
+            last_save: Instant::now(),
+        }
+    }
+
+    /// Returns the saved position, or `None` if no checkpoint was saved yet.
+    pub fn read(&self) -> Result<Option<Position>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error).context(format!("Failed to read {}", self.path)),
+        };
+        let json: serde_json::Value = serde_json::from_str(&content)
+            .context(format!("Invalid checkpoint in {}", self.path))?;
+        match (json["rows"].as_u64(), json["offset"].as_u64()) {
+            (Some(rows), Some(offset)) => Ok(Some(Position { rows, offset })),
+            _ => Err(anyhow!("Invalid checkpoint in {}", self.path)),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_save.elapsed() >= SAVE_INTERVAL
+    }
+
+    /// Replaces the saved position. The file is renamed into place, so that it is never left
+    /// half written.
+    pub fn save(&mut self, position: Position) -> Result<()> {
+        let json = serde_json::json!({
+            "rows": position.rows,
+            "offset": position.offset,
+        });
+        let temporary = format!("{}.tmp", self.path);
+        fs::write(&temporary, json.to_string() + "\n")
+            .and_then(|_| fs::rename(&temporary, &self.path))
+            .context(format!("Failed to write {}", self.path))?;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+/// Counts the bytes written through it, from an initial offset.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W, count: u64) -> CountingWriter<W> {
+        CountingWriter { inner, count }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,307 @@
+use crate::digest;
+use crate::exec::Execution;
+use crate::raw;
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use chrono::SecondsFormat;
+use csv::StringRecord;
+use regex::Regex;
+
+/// Columns added to each record, filled from the execution of its commands.
+/// Most kinds are a single column, while the fields extracted from a result can be several.
+pub struct Column {
+    pub names: Vec<String>,
+    pub kind: Kind,
+}
+
+pub enum Kind {
+    /// Standard output of the command at this index
+    Result(usize, ResultFormat),
+    /// Fields extracted from the result of the single command, one per name
+    Fields(Extract, ResultFormat),
+    /// Exit code, or the negated signal number if the command was killed by a signal
+    ExitCode,
+    /// Trimmed standard error, invalid UTF-8 sequences being replaced
+    Stderr,
+    /// Duration of the execution, in milliseconds
+    Duration,
+    /// ISO-8601 date and time of the launch of the command
+    Started,
+    Attempts,
+}
+
+impl Column {
+    pub fn new(name: &str, kind: Kind) -> Column {
+        Column {
+            names: vec![name.to_string()],
+            kind,
+        }
+    }
+
+    pub fn with_names(names: Vec<String>, kind: Kind) -> Column {
+        Column { names, kind }
+    }
+
+    /// Values from the executions of the commands of a record, which must all have completed.
+    pub fn values(&self, executions: &[Execution]) -> Result<Vec<String>> {
+        match self.execution(executions, None) {
+            Some(execution) => self.execution_values(execution),
+            None => Ok(vec![String::new(); self.names.len()]),
+        }
+    }
+
+    /// Values used when a command failed: the results of this command and of the next ones are
+    /// replaced by `result`, and the other columns are filled only if the failed command ran.
+    pub fn fallback_values(
+        &self,
+        executions: &[Execution],
+        failed: Option<&Execution>,
+        result: &str,
+    ) -> Vec<String> {
+        let is_result = matches!(self.kind, Kind::Result(..) | Kind::Fields(..));
+        let fallback = if is_result { result } else { "" };
+        self.execution(executions, failed)
+            .and_then(|execution| self.execution_values(execution).ok())
+            .unwrap_or_else(|| vec![fallback.to_string(); self.names.len()])
+    }
+
+    /// The execution described by the column: the one of its command for a result,
+    /// or the last one which ran for the other columns.
+    fn execution<'a>(
+        &self,
+        executions: &'a [Execution],
+        failed: Option<&'a Execution>,
+    ) -> Option<&'a Execution> {
+        match self.kind {
+            Kind::Result(index, _) => executions.get(index),
+            Kind::Fields(..) => executions.first(),
+            _ => failed.or_else(|| executions.last()),
+        }
+    }
+
+    fn execution_values(&self, execution: &Execution) -> Result<Vec<String>> {
+        let value = match &self.kind {
+            Kind::Result(_, format) => format.apply(&execution.stdout)?,
+            Kind::Fields(extract, format) => {
+                return extract.fields(&format.apply(&execution.stdout)?)
+            }
+            Kind::ExitCode => execution
+                .exit_code()
+                .map(|code| code.to_string())
+                .unwrap_or_default(),
+            Kind::Stderr => String::from_utf8_lossy(&execution.stderr)
+                .trim()
+                .to_string(),
+            Kind::Duration => execution.duration.as_millis().to_string(),
+            Kind::Started => execution
+                .started
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            Kind::Attempts => execution.attempts.to_string(),
+        };
+        Ok(vec![value])
+    }
+}
+
+/// How the standard output of a command becomes its result.
+#[derive(Clone)]
+pub struct ResultFormat {
+    /// Whether the leading and trailing whitespace is removed, from each line if they are
+    /// processed. Otherwise, only the final line break is removed.
+    pub trim: bool,
+    pub lines: Lines,
+    pub encoding: Encoding,
+}
+
+/// How the output is converted to text.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Invalid UTF-8 is a failure
+    Strict,
+    /// Invalid UTF-8 sequences are replaced
+    Lossy,
+    /// The bytes which are not UTF-8 are escaped by `raw::escape`
+    Raw,
+    /// The whole output is encoded, without processing its whitespace or lines
+    Base64,
+    Hex,
+}
+
+#[derive(Clone)]
+pub enum Lines {
+    All,
+    First,
+    /// The lines are joined with this separator
+    Join(String),
+}
+
+impl ResultFormat {
+    pub fn apply(&self, stdout: &[u8]) -> Result<String> {
+        let converted;
+        let text = match self.encoding {
+            Encoding::Strict => std::str::from_utf8(stdout)?,
+            Encoding::Lossy => {
+                converted = String::from_utf8_lossy(stdout);
+                &converted
+            }
+            Encoding::Raw => {
+                converted = raw::escape(stdout).into();
+                &converted
+            }
+            Encoding::Base64 => return Ok(BASE64_STANDARD.encode(stdout)),
+            Encoding::Hex => return Ok(digest::hex(stdout)),
+        };
+        let text = if self.trim {
+            text.trim()
+        } else {
+            match text.strip_suffix('\n') {
+                Some(text) => text.strip_suffix('\r').unwrap_or(text),
+                None => text,
+            }
+        };
+        let lines = text
+            .lines()
+            .map(|line| if self.trim { line.trim() } else { line });
+        Ok(match &self.lines {
+            Lines::All => text.to_string(),
+            Lines::First => lines.take(1).collect(),
+            Lines::Join(separator) => lines.collect::<Vec<_>>().join(separator),
+        })
+    }
+}
+
+/// How the fields of a result are extracted.
+pub enum Extract {
+    Split(Split),
+    /// The result is a JSON document, and the fields are at these JSON pointers
+    Json(Vec<String>),
+    /// The fields are the capture groups of the first match of the regex
+    Capture(Regex),
+}
+
+impl Extract {
+    fn fields(&self, result: &str) -> Result<Vec<String>> {
+        match self {
+            Extract::Split(split) => split.fields(result),
+            Extract::Json(pointers) => {
+                let document: serde_json::Value = serde_json::from_str(result)
+                    .map_err(|error| anyhow!("Invalid JSON in the result: {}", error))?;
+                Ok(pointers
+                    .iter()
+                    .map(|pointer| match document.pointer(pointer) {
+                        None | Some(serde_json::Value::Null) => String::new(),
+                        Some(serde_json::Value::String(string)) => string.clone(),
+                        Some(value) => value.to_string(),
+                    })
+                    .collect())
+            }
+            Extract::Capture(regex) => {
+                let caps = regex
+                    .captures(result)
+                    .ok_or_else(|| anyhow!("The result does not match {}", regex))?;
+                Ok(caps
+                    .iter()
+                    .skip(1)
+                    .map(|group| group.map_or("", |m| m.as_str()).to_string())
+                    .collect())
+            }
+        }
+    }
+}
+
+/// Converts a path of --json-output to a JSON pointer.
+/// The path is either a JSON pointer already, or keys and array indexes separated by dots.
+pub fn json_pointer(path: &str) -> String {
+    if path.is_empty() || path.starts_with('/') {
+        return path.to_string();
+    }
+    path.split('.')
+        .map(|key| format!("/{}", key.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+/// How a result is split into several fields.
+pub struct Split {
+    pub separator: char,
+    /// Number of columns
+    pub count: usize,
+    pub policy: SplitPolicy,
+}
+
+/// What to do when a result does not have the expected number of fields.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SplitPolicy {
+    /// The result is a failure
+    Strict,
+    /// Missing fields are empty
+    Pad,
+    /// Extra fields are dropped
+    Truncate,
+    /// Both `Pad` and `Truncate`
+    PadTruncate,
+}
+
+impl Split {
+    /// Returns exactly `count` fields, or fails according to the policy.
+    pub fn fields(&self, result: &str) -> Result<Vec<String>> {
+        let mut fields: Vec<String> = result.split(self.separator).map(String::from).collect();
+        let pad = matches!(self.policy, SplitPolicy::Pad | SplitPolicy::PadTruncate);
+        let truncate = matches!(
+            self.policy,
+            SplitPolicy::Truncate | SplitPolicy::PadTruncate
+        );
+        if (fields.len() < self.count && !pad) || (fields.len() > self.count && !truncate) {
+            return Err(anyhow!(
+                "Expected {} fields in the result, got {}",
+                self.count,
+                fields.len()
+            ));
+        }
+        fields.resize(self.count, String::new());
+        Ok(fields)
+    }
+}
+
+/// Where the result goes in the output records.
+#[derive(Clone, Copy)]
+pub enum Placement {
+    /// After the fields of the record, followed by the other columns
+    Append,
+    /// Instead of the field at this index, the other columns being appended
+    Replace(usize),
+    /// Before the field at this index, followed by the other columns
+    Insert(usize),
+}
+
+impl Placement {
+    /// Adds `values`, which begin with the result, to the fields of `record`.
+    pub fn apply(&self, record: &StringRecord, values: Vec<String>) -> StringRecord {
+        let fields = record.iter().map(String::from).collect();
+        self.place(fields, values, |_| String::new())
+            .into_iter()
+            .collect()
+    }
+
+    /// For each field of the output records, the index of the field of the record it comes from
+    /// or `None` for the values, like `apply` with a record of `width` fields and `count` values.
+    pub fn sources(&self, width: usize, count: usize) -> Vec<Option<usize>> {
+        self.place((0..width).map(Some).collect(), vec![None; count], Some)
+    }
+
+    /// `pad` gives the missing fields before a replaced one, from their index.
+    fn place<T>(&self, mut fields: Vec<T>, values: Vec<T>, pad: impl Fn(usize) -> T) -> Vec<T> {
+        let mut values = values.into_iter();
+        if let Placement::Replace(index) = *self {
+            while fields.len() <= index {
+                fields.push(pad(fields.len()));
+            }
+            fields[index] = values.next().unwrap_or_else(|| pad(index));
+        }
+        match *self {
+            Placement::Insert(index) if index < fields.len() => {
+                fields.splice(index..index, values);
+            }
+            _ => fields.extend(values),
+        }
+        fields
+    }
+}
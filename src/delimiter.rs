@@ -0,0 +1,241 @@
+use crate::raw;
+use csv::StringRecord;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Byte which stands for a delimiter of several characters for the CSV reader and writer, as
+/// they only support delimiters of one byte.
+pub const SEPARATOR: u8 = 0x1f;
+
+/// Size of the chunks read from the input.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Replaces the delimiter by `SEPARATOR` in the input, except between quotes.
+pub struct Splitter<R: Read> {
+    inner: R,
+    delimiter: Vec<u8>,
+    quote: Option<u8>,
+    /// Before a quote which does not end the field
+    escape: Option<u8>,
+    in_quotes: bool,
+    /// Read from the input, but not replaced yet, as it may be the beginning of a delimiter
+    unprocessed: Vec<u8>,
+    processed: Vec<u8>,
+    /// Position of the next byte of `processed` to return
+    position: usize,
+    end: bool,
+}
+
+impl<R: Read> Splitter<R> {
+    /// With a `quote`, the delimiters between two quotes are not replaced.
+    pub fn new(inner: R, delimiter: &[u8], quote: Option<u8>, escape: Option<u8>) -> Splitter<R> {
+        Splitter {
+            inner,
+            delimiter: delimiter.to_vec(),
+            quote,
+            escape,
+            in_quotes: false,
+            unprocessed: vec![],
+            processed: vec![],
+            position: 0,
+            end: false,
+        }
+    }
+
+    fn process(&mut self) -> io::Result<()> {
+        self.processed.clear();
+        self.position = 0;
+        let mut i = 0;
+        // The last bytes may be the beginning of a delimiter, until the end of the input
+        while i < self.unprocessed.len()
+            && (self.end || i + self.delimiter.len() <= self.unprocessed.len())
+        {
+            let byte = self.unprocessed[i];
+            // It would be read as a delimiter
+            if !self.in_quotes && byte == SEPARATOR {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The byte 0x1F cannot be read out of quotes with a delimiter of several characters",
+                ));
+            }
+            if !self.in_quotes && self.unprocessed[i..].starts_with(&self.delimiter) {
+                self.processed.push(SEPARATOR);
+                i += self.delimiter.len();
+                continue;
+            }
+            if self.in_quotes && Some(byte) == self.escape {
+                // The escaped byte may not be read yet
+                if i + 1 == self.unprocessed.len() && !self.end {
+                    break;
+                }
+                self.processed
+                    .extend(&self.unprocessed[i..(i + 2).min(self.unprocessed.len())]);
+                i += 2;
+                continue;
+            }
+            // A doubled quote in a quoted field ends and begins it again
+            if Some(byte) == self.quote {
+                self.in_quotes = !self.in_quotes;
+            }
+            self.processed.push(byte);
+            i += 1;
+        }
+        self.unprocessed.drain(..i.min(self.unprocessed.len()));
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Splitter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.processed.len() {
+            if self.end && self.unprocessed.is_empty() {
+                return Ok(0);
+            }
+            let length = self.unprocessed.len();
+            self.unprocessed.resize(length + CHUNK_SIZE, 0);
+            let read = self.inner.read(&mut self.unprocessed[length..]);
+            let read = read.inspect_err(|_| self.unprocessed.truncate(length))?;
+            self.unprocessed.truncate(length + read);
+            self.end = read == 0;
+            self.process()?;
+        }
+        let length = buf.len().min(self.processed.len() - self.position);
+        buf[..length].copy_from_slice(&self.processed[self.position..][..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+/// A CSV writer which also supports a delimiter of several characters. It writes `SEPARATOR`
+/// as the delimiter, and then replaces it by the delimiter in the output. A field with a byte of
+/// the delimiter begins with a `SEPARATOR` too, so that it is quoted like with a single byte, and
+/// that one is removed from the output.
+pub struct Writer<W: Write> {
+    writer: csv::Writer<Joiner<W>>,
+    delimiter: Option<Vec<u8>>,
+    /// What the joiner does with the next `SEPARATOR` bytes of the output
+    separators: Arc<Mutex<VecDeque<Separator>>>,
+    /// Whether the bytes escaped by `raw::escape` are given back
+    raw: bool,
+}
+
+impl<W: Write> Writer<W> {
+    /// With a `delimiter`, the delimiter of `builder` must be `SEPARATOR`.
+    pub fn new(
+        builder: &csv::WriterBuilder,
+        delimiter: Option<&str>,
+        raw: bool,
+        inner: W,
+    ) -> Writer<W> {
+        let separators = Arc::new(Mutex::new(VecDeque::new()));
+        let joiner = Joiner {
+            inner,
+            delimiter: delimiter.map(|delimiter| delimiter.as_bytes().to_vec()),
+            separators: Arc::clone(&separators),
+        };
+        Writer {
+            writer: builder.from_writer(joiner),
+            delimiter: delimiter.map(|delimiter| delimiter.as_bytes().to_vec()),
+            separators,
+            raw,
+        }
+    }
+
+    pub fn write_record(&mut self, record: &StringRecord) -> csv::Result<()> {
+        let raw = self.raw;
+        let fields = record.iter().map(|field| match raw {
+            true => raw::unescape(field).into_owned(),
+            false => field.as_bytes().to_vec(),
+        });
+        let delimiter = match &self.delimiter {
+            Some(delimiter) => delimiter,
+            None => return self.writer.write_record(fields),
+        };
+        let mut separators = vec![];
+        let fields = fields
+            .enumerate()
+            .map(|(i, field)| {
+                if i > 0 {
+                    separators.push(Separator::Delimiter);
+                }
+                // The field could not be told apart from the delimiters next to it
+                if !field
+                    .iter()
+                    .any(|byte| delimiter.contains(byte) || *byte == SEPARATOR)
+                {
+                    return field;
+                }
+                separators.push(Separator::Removed);
+                let count = field.iter().filter(|&&byte| byte == SEPARATOR).count();
+                separators.extend(std::iter::repeat_n(Separator::Kept, count));
+                [&[SEPARATOR], &field[..]].concat()
+            })
+            .collect::<Vec<_>>();
+        lock(&self.separators).extend(separators);
+        self.writer.write_record(fields)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer.get_ref().inner
+    }
+
+    pub fn into_inner(self) -> io::Result<W> {
+        let joiner = self
+            .writer
+            .into_inner()
+            .map_err(|error| error.into_error())?;
+        Ok(joiner.inner)
+    }
+}
+
+/// What is written for a `SEPARATOR` byte in the output.
+#[derive(Clone, Copy)]
+enum Separator {
+    Delimiter,
+    /// The byte which gets a field quoted
+    Removed,
+    /// A byte of a field
+    Kept,
+}
+
+fn lock(separators: &Mutex<VecDeque<Separator>>) -> std::sync::MutexGuard<'_, VecDeque<Separator>> {
+    separators.lock().unwrap_or_else(|error| error.into_inner())
+}
+
+/// Replaces `SEPARATOR` by the delimiter in the output, as told by the writer, or else writes it
+/// as is.
+struct Joiner<W: Write> {
+    inner: W,
+    delimiter: Option<Vec<u8>>,
+    separators: Arc<Mutex<VecDeque<Separator>>>,
+}
+
+impl<W: Write> Write for Joiner<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let delimiter = match &self.delimiter {
+            Some(delimiter) => delimiter,
+            None => return self.inner.write(buf),
+        };
+        let mut separators = lock(&self.separators);
+        let mut parts = buf.split(|&byte| byte == SEPARATOR);
+        if let Some(part) = parts.next() {
+            self.inner.write_all(part)?;
+        }
+        for part in parts {
+            match separators.pop_front().unwrap_or(Separator::Delimiter) {
+                Separator::Delimiter => self.inner.write_all(delimiter)?,
+                Separator::Removed => {}
+                Separator::Kept => self.inner.write_all(&[SEPARATOR])?,
+            }
+            self.inner.write_all(part)?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,404 @@
+use crate::rate::RateLimiter;
+use crate::raw;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{self, Child, ChildStdin, ChildStdout, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use wait_timeout::ChildExt;
+
+/// Delay given to a coprocess to exit once its input is closed, before it is killed.
+const COPROCESS_EXIT_DELAY: Duration = Duration::from_secs(2);
+
+/// Settings shared by all the executions.
+pub struct Options {
+    pub timeout: Option<Duration>,
+    pub retries: u32,
+    /// Delay before the first retry
+    pub retry_delay: Duration,
+    /// Factor applied to the delay after each retry
+    pub retry_backoff: f64,
+    /// If set, only the commands exiting with one of these codes are retried
+    pub retry_on_exit_codes: Option<Vec<i32>>,
+    /// Applied to each launch, including the retries
+    pub rate_limiter: Option<RateLimiter>,
+    /// If set, the commands do not inherit the environment of csv-exec, except these variables
+    pub pass_env: Option<Vec<String>>,
+    /// Size of the output, and of the error output, kept in memory, the rest being discarded
+    pub max_output_bytes: Option<u64>,
+    /// Whether an output larger than `max_output_bytes` is truncated, instead of a failure
+    pub truncate_output: bool,
+    /// Whether the bytes escaped by `raw::escape` in the arguments and the environment variables
+    /// are given back
+    pub raw: bool,
+}
+
+impl Options {
+    fn command(&self, invocation: &Invocation) -> process::Command {
+        let mut command = process::Command::new(&invocation.command);
+        if self.raw {
+            command.args(invocation.args.iter().map(|arg| raw::os_string(arg)));
+        } else {
+            command.args(&invocation.args);
+        }
+        if let Some(pass_env) = &self.pass_env {
+            command.env_clear();
+            for key in pass_env {
+                if let Some(value) = std::env::var_os(key) {
+                    command.env(key, value);
+                }
+            }
+        }
+        if self.raw {
+            let env = invocation.env.iter();
+            command.envs(env.map(|(key, value)| (raw::os_string(key), raw::os_string(value))));
+        } else {
+            command.envs(invocation.env.iter().map(|(key, value)| (key, value)));
+        }
+        if let Some(cwd) = &invocation.cwd {
+            command.current_dir(cwd);
+        }
+        command
+    }
+}
+
+/// What a command produced.
+#[derive(Clone)]
+pub struct Execution {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub status: ExitStatus,
+    pub started: DateTime<Utc>,
+    /// From the launch of the command until its exit
+    pub duration: Duration,
+    /// Number of times the command was launched
+    pub attempts: u32,
+}
+
+impl Execution {
+    /// Of an expression evaluated by csv-exec, whose output is `value`.
+    pub fn computed(value: String) -> Execution {
+        Execution {
+            stdout: value.into_bytes(),
+            stderr: vec![],
+            status: ExitStatus::default(),
+            started: Utc::now(),
+            duration: Duration::from_secs(0),
+            attempts: 0,
+        }
+    }
+
+    /// Exit code, or the negated signal number if the command was killed by a signal.
+    #[cfg(unix)]
+    pub fn exit_code(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt;
+        self.status
+            .code()
+            .or_else(|| self.status.signal().map(|signal| -signal))
+    }
+
+    #[cfg(not(unix))]
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.code()
+    }
+}
+
+/// A command with its arguments, once expanded for a record.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Invocation {
+    pub command: String,
+    pub args: Vec<String>,
+    /// Written to the input of the command, which is empty otherwise
+    pub stdin: Option<Vec<u8>>,
+    /// Environment variables added to the ones of csv-exec
+    pub env: Vec<(String, String)>,
+    /// Working directory, which is the one of csv-exec otherwise
+    pub cwd: Option<String>,
+}
+
+impl Invocation {
+    /// An execution which did not run the command, its output being the command line,
+    /// once per line in `lines`.
+    pub fn dry_run(&self, lines: usize) -> Execution {
+        let mut line = shell_words::join(std::iter::once(&self.command).chain(&self.args));
+        line.push('\n');
+        Execution {
+            stdout: line.repeat(lines).into_bytes(),
+            stderr: vec![],
+            status: ExitStatus::default(),
+            started: Utc::now(),
+            duration: Duration::from_secs(0),
+            attempts: 0,
+        }
+    }
+
+    /// Runs the command, and runs it again if it failed, up to the configured retries.
+    /// Fails if the last attempt cannot be launched or does not complete in time.
+    pub fn run(&self, options: &Options) -> Result<Execution> {
+        let started = Utc::now();
+        let mut delay = options.retry_delay;
+        let mut attempts = 1;
+        loop {
+            let result = self.run_once(options);
+            if attempts > options.retries || !should_retry(&result, options) {
+                return result.map(|execution| Execution {
+                    started,
+                    attempts,
+                    ..execution
+                });
+            }
+            thread::sleep(with_jitter(delay));
+            // Capped at the largest duration, once the backoff overflows it
+            delay = Duration::try_from_secs_f64(delay.as_secs_f64() * options.retry_backoff)
+                .unwrap_or(Duration::MAX);
+            attempts += 1;
+        }
+    }
+
+    /// Runs the command until it exits.
+    /// Fails if the command cannot be launched or does not complete in time.
+    fn run_once(&self, options: &Options) -> Result<Execution> {
+        if let Some(rate_limiter) = &options.rate_limiter {
+            rate_limiter.acquire();
+        }
+        let started = Utc::now();
+        let start = Instant::now();
+        let mut child = options
+            .command(self)
+            .stdin(if self.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context(self.failure_message())?;
+        if let (Some(mut pipe), Some(input)) = (child.stdin.take(), self.stdin.clone()) {
+            // The command may exit without reading its input, so failures are ignored
+            thread::spawn(move || pipe.write_all(&input));
+        }
+        let stdout = read_in_background(child.stdout.take(), options.max_output_bytes);
+        let stderr = read_in_background(child.stderr.take(), options.max_output_bytes);
+        let status = match options.timeout {
+            None => Some(child.wait()),
+            Some(timeout) => child.wait_timeout(timeout).transpose(),
+        };
+        match status {
+            Some(status) => {
+                let status = status.context(self.failure_message())?;
+                let duration = start.elapsed();
+                let (mut stdout, stdout_overflowed) = self.join_reader(stdout)?;
+                let (mut stderr, stderr_overflowed) = self.join_reader(stderr)?;
+                if (stdout_overflowed || stderr_overflowed) && !options.truncate_output {
+                    return Err(anyhow!(
+                        "Command {} with args {:?} wrote more than {} bytes",
+                        self.command,
+                        self.args,
+                        options.max_output_bytes.unwrap_or_default()
+                    ));
+                }
+                for (output, overflowed) in [
+                    (&mut stdout, stdout_overflowed),
+                    (&mut stderr, stderr_overflowed),
+                ] {
+                    // A character cut by the truncation is dropped
+                    if let (true, Err(error)) = (overflowed, std::str::from_utf8(output)) {
+                        if error.error_len().is_none() {
+                            output.truncate(error.valid_up_to());
+                        }
+                    }
+                }
+                Ok(Execution {
+                    stdout,
+                    stderr,
+                    status,
+                    started,
+                    duration,
+                    attempts: 1,
+                })
+            }
+            None => {
+                // The readers are not joined: the pipes may be kept open by the child's children
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(anyhow!(
+                    "Command {} with args {:?} timed out after {:?}",
+                    self.command,
+                    self.args,
+                    options.timeout.unwrap_or_default()
+                ))
+            }
+        }
+    }
+
+    fn join_reader(&self, reader: thread::JoinHandle<std::io::Result<Output>>) -> Result<Output> {
+        reader
+            .join()
+            .map_err(|_| anyhow!("Failed to read the output of {}", self.command))?
+            .context(self.failure_message())
+    }
+
+    fn failure_message(&self) -> String {
+        match &self.cwd {
+            None => format!(
+                "Failed to execute command {} with args {:?}",
+                self.command, self.args
+            ),
+            Some(cwd) => format!(
+                "Failed to execute command {} with args {:?} in {}",
+                self.command, self.args, cwd
+            ),
+        }
+    }
+}
+
+/// Long-lived commands, each one reading a line per record on its input,
+/// and answering with a line on its output.
+/// A process is spawned for each worker which needs one, and replaced if it fails.
+pub struct Coprocesses {
+    invocation: Invocation,
+    idle: Mutex<Vec<Coprocess>>,
+}
+
+struct Coprocess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Coprocesses {
+    pub fn new(invocation: Invocation) -> Coprocesses {
+        Coprocesses {
+            invocation,
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    /// Sends `line` to an idle process, and returns its answer as the output of the execution.
+    pub fn exchange(&self, line: &[u8], options: &Options) -> Result<Execution> {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut coprocess = match idle {
+            Some(coprocess) => coprocess,
+            None => self.spawn(options)?,
+        };
+        let started = Utc::now();
+        let start = Instant::now();
+        let stdout = coprocess.exchange(line).context(format!(
+            "Failed to communicate with command {} with args {:?}",
+            self.invocation.command, self.invocation.args
+        ))?;
+        self.idle.lock().unwrap().push(coprocess);
+        Ok(Execution {
+            stdout,
+            stderr: vec![],
+            status: ExitStatus::default(),
+            started,
+            duration: start.elapsed(),
+            attempts: 1,
+        })
+    }
+
+    fn spawn(&self, options: &Options) -> Result<Coprocess> {
+        let mut child = options
+            .command(&self.invocation)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .context(self.invocation.failure_message())?;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().map(BufReader::new);
+        match stdout {
+            Some(stdout) => Ok(Coprocess {
+                child,
+                stdin,
+                stdout,
+            }),
+            None => Err(anyhow!(self.invocation.failure_message())),
+        }
+    }
+}
+
+impl Coprocess {
+    fn exchange(&mut self, line: &[u8]) -> Result<Vec<u8>> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| anyhow!("Input is closed"))?;
+        stdin.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            stdin.write_all(b"\n")?;
+        }
+        stdin.flush()?;
+        let mut answer = vec![];
+        if self.stdout.read_until(b'\n', &mut answer)? == 0 {
+            return Err(anyhow!("The command closed its output"));
+        }
+        if answer.ends_with(b"\n") {
+            answer.pop();
+        }
+        Ok(answer)
+    }
+}
+
+impl Drop for Coprocess {
+    fn drop(&mut self) {
+        // Closing the input lets the process exit
+        self.stdin = None;
+        if let Ok(Some(_)) = self.child.wait_timeout(COPROCESS_EXIT_DELAY) {
+            return;
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn should_retry(result: &Result<Execution>, options: &Options) -> bool {
+    match (result, &options.retry_on_exit_codes) {
+        (Ok(execution), _) if execution.status.success() => false,
+        (Ok(execution), Some(codes)) => execution
+            .status
+            .code()
+            .is_some_and(|code| codes.contains(&code)),
+        // The timeouts and the commands which failed to start have no exit code
+        _ => true,
+    }
+}
+
+/// Returns a random duration between the half of `delay` and `delay`,
+/// so that the retries of parallel commands are spread over time.
+fn with_jitter(delay: Duration) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    delay.mul_f64(0.5 + 0.5 * (random as f64 / u64::MAX as f64))
+}
+
+/// What was read from a pipe, and whether the rest was discarded.
+type Output = (Vec<u8>, bool);
+
+/// Reads `pipe` until its end, keeping at most `limit` bytes.
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    limit: Option<u64>,
+) -> thread::JoinHandle<std::io::Result<Output>> {
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        let mut overflowed = false;
+        if let Some(mut pipe) = pipe {
+            match limit {
+                None => {
+                    pipe.read_to_end(&mut buffer)?;
+                }
+                Some(limit) => {
+                    (&mut pipe).take(limit).read_to_end(&mut buffer)?;
+                    // The rest is still read, so that the command is not blocked
+                    overflowed = std::io::copy(&mut pipe, &mut std::io::sink())? > 0;
+                }
+            }
+        }
+        Ok((buffer, overflowed))
+    })
+}
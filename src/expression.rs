@@ -0,0 +1,665 @@
+use crate::digest;
+use crate::template::{self, Scope, Template};
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use csv::StringRecord;
+use regex::Regex;
+use std::cmp::Ordering;
+
+/// The longest first, so that `<=` is not read as `<`.
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "=~", "!~", "&&", "||", "<", ">", "+", "-", "*", "/", "%", "!", "(",
+    ")", ",", "?", ":",
+];
+
+/// An expression computing a result without running a command, such as
+/// `concat($2, "/", $1)` or `if($Size > 1000, "large", "small")`, or the condition of --where.
+pub struct Expression {
+    root: Node,
+}
+
+enum Node {
+    Literal(String),
+    Reference(Template),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Arithmetic(Box<Node>, char, Box<Node>),
+    Compare(Box<Node>, Operator, Box<Node>),
+    /// The value must match the regex, or must not if negated
+    Matches(Box<Node>, Regex, bool),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    /// `condition ? then : else`
+    Choice(Box<Node>, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+/// A comparison of two values.
+#[derive(Clone, Copy)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+#[derive(Clone, Copy)]
+enum Function {
+    Concat,
+    Upper,
+    Lower,
+    Trim,
+    Len,
+    /// Of the string, from a character offset beginning at 0, with an optional length
+    Substr,
+    Replace,
+    Contains,
+    StartsWith,
+    EndsWith,
+    /// The second argument if the first one is true, else the third one or an empty string
+    If,
+    /// The first non-empty argument
+    Coalesce,
+    Round,
+    Floor,
+    Ceil,
+    Abs,
+    Min,
+    Max,
+    /// In lowercase hexadecimal, like sha256sum
+    Sha256,
+    Md5,
+    Base64,
+    UrlEncode,
+}
+
+enum Token {
+    /// A number, or a quoted string without its quotes
+    Literal(String),
+    /// As matched by the argument regex
+    Reference(String),
+    Name(String),
+    Symbol(&'static str),
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    arg_regex: &'a Regex,
+    scope: &'a Scope<'a>,
+}
+
+impl Expression {
+    /// Parses `expression`, whose references are matched by `arg_regex` and resolved in `scope`,
+    /// like the arguments of the commands. The values are strings, which are numbers for the
+    /// arithmetic operators `+`, `-`, `*`, `/`, `%`, and are compared as numbers if both are.
+    /// The comparisons, `=~` and `!~` whose right value is a quoted regex, and the boolean
+    /// operators `&&`, `||`, `!` give `true` or `false`, the false values being the empty string,
+    /// `0` and `false`. `c ? a : b` is `a` if `c` is true, and `b` otherwise.
+    pub fn parse(expression: &str, arg_regex: &Regex, scope: &Scope) -> Result<Expression> {
+        let invalid = |reason: String| anyhow!("Invalid expression {}: {}", expression, reason);
+        let tokens = tokenize(expression, arg_regex).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            arg_regex,
+            scope,
+        };
+        let root = parser.choice().map_err(invalid)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(Expression { root }),
+            Some(token) => Err(invalid(format!("unexpected {}", token))),
+        }
+    }
+
+    /// Whether the value depends on the record.
+    pub fn has_references(&self) -> bool {
+        self.root.has_references()
+    }
+
+    /// Evaluates the expression for a record of the input file at `file`, `results` being the
+    /// results of the earlier commands.
+    pub fn evaluate(
+        &self,
+        record: &StringRecord,
+        file: &str,
+        results: &[String],
+    ) -> Result<String> {
+        self.root.evaluate(&Context {
+            record,
+            file,
+            results,
+        })
+    }
+
+    /// Whether the value is true for a record of the input file at `file`, before any command.
+    pub fn holds(&self, record: &StringRecord, file: &str) -> Result<bool> {
+        Ok(truthy(&self.evaluate(record, file, &[])?))
+    }
+}
+
+struct Context<'a> {
+    record: &'a StringRecord,
+    file: &'a str,
+    results: &'a [String],
+}
+
+impl Node {
+    fn has_references(&self) -> bool {
+        match self {
+            Node::Literal(_) => false,
+            Node::Reference(template) => template.has_references(),
+            Node::Not(node) | Node::Negate(node) | Node::Matches(node, ..) => node.has_references(),
+            Node::Arithmetic(left, _, right)
+            | Node::Compare(left, _, right)
+            | Node::And(left, right)
+            | Node::Or(left, right) => left.has_references() || right.has_references(),
+            Node::Choice(condition, then, otherwise) => {
+                condition.has_references() || then.has_references() || otherwise.has_references()
+            }
+            Node::Call(_, arguments) => arguments.iter().any(Node::has_references),
+        }
+    }
+
+    fn evaluate(&self, context: &Context) -> Result<String> {
+        Ok(match self {
+            Node::Literal(value) => value.clone(),
+            Node::Reference(template) => {
+                template.expand_with_results(context.record, context.file, context.results)?
+            }
+            Node::Not(node) => boolean(!truthy(&node.evaluate(context)?)),
+            Node::Negate(node) => format_number(-number(&node.evaluate(context)?, "-")?),
+            Node::Arithmetic(left, operator, right) => {
+                let symbol = operator.to_string();
+                let left = number(&left.evaluate(context)?, &symbol)?;
+                let right = number(&right.evaluate(context)?, &symbol)?;
+                if right == 0.0 && (*operator == '/' || *operator == '%') {
+                    return Err(anyhow!("Division by zero"));
+                }
+                format_number(match operator {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    '/' => left / right,
+                    _ => left % right,
+                })
+            }
+            Node::Compare(left, operator, right) => {
+                let left = left.evaluate(context)?;
+                let right = right.evaluate(context)?;
+                boolean(operator.accepts(compare(&left, &right)))
+            }
+            Node::Matches(node, regex, negated) => {
+                boolean(regex.is_match(&node.evaluate(context)?) != *negated)
+            }
+            Node::And(left, right) => {
+                boolean(truthy(&left.evaluate(context)?) && truthy(&right.evaluate(context)?))
+            }
+            Node::Or(left, right) => {
+                boolean(truthy(&left.evaluate(context)?) || truthy(&right.evaluate(context)?))
+            }
+            Node::Choice(condition, then, otherwise) => {
+                if truthy(&condition.evaluate(context)?) {
+                    then.evaluate(context)?
+                } else {
+                    otherwise.evaluate(context)?
+                }
+            }
+            Node::Call(Function::If, arguments) => {
+                if truthy(&arguments[0].evaluate(context)?) {
+                    arguments[1].evaluate(context)?
+                } else {
+                    match arguments.get(2) {
+                        Some(otherwise) => otherwise.evaluate(context)?,
+                        None => String::new(),
+                    }
+                }
+            }
+            Node::Call(Function::Coalesce, arguments) => {
+                for argument in arguments {
+                    let value = argument.evaluate(context)?;
+                    if !value.is_empty() {
+                        return Ok(value);
+                    }
+                }
+                String::new()
+            }
+            Node::Call(function, arguments) => {
+                let values = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(context))
+                    .collect::<Result<Vec<_>>>()?;
+                function.apply(&values)?
+            }
+        })
+    }
+}
+
+impl Operator {
+    /// Parses one of `==`, `!=`, `<`, `<=`, `>`, `>=`.
+    fn parse(operator: &str) -> Operator {
+        match operator {
+            "==" => Operator::Equal,
+            "!=" => Operator::NotEqual,
+            "<" => Operator::Less,
+            "<=" => Operator::LessOrEqual,
+            ">" => Operator::Greater,
+            _ => Operator::GreaterOrEqual,
+        }
+    }
+
+    /// Whether the operator holds for the values, `None` if they cannot be compared (NaN, or a
+    /// number with a text).
+    fn accepts(self, ordering: Option<Ordering>) -> bool {
+        match (self, ordering) {
+            (Operator::NotEqual, ordering) => ordering != Some(Ordering::Equal),
+            (_, None) => false,
+            (Operator::Equal, Some(ordering)) => ordering == Ordering::Equal,
+            (Operator::Less, Some(ordering)) => ordering == Ordering::Less,
+            (Operator::LessOrEqual, Some(ordering)) => ordering != Ordering::Greater,
+            (Operator::Greater, Some(ordering)) => ordering == Ordering::Greater,
+            (Operator::GreaterOrEqual, Some(ordering)) => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl Function {
+    fn parse(name: &str) -> Option<Function> {
+        Some(match name {
+            "concat" => Function::Concat,
+            "upper" => Function::Upper,
+            "lower" => Function::Lower,
+            "trim" => Function::Trim,
+            "len" => Function::Len,
+            "substr" => Function::Substr,
+            "replace" => Function::Replace,
+            "contains" => Function::Contains,
+            "starts_with" => Function::StartsWith,
+            "ends_with" => Function::EndsWith,
+            "if" => Function::If,
+            "coalesce" => Function::Coalesce,
+            "round" => Function::Round,
+            "floor" => Function::Floor,
+            "ceil" => Function::Ceil,
+            "abs" => Function::Abs,
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "sha256" => Function::Sha256,
+            "md5" => Function::Md5,
+            "base64" => Function::Base64,
+            "urlencode" => Function::UrlEncode,
+            _ => return None,
+        })
+    }
+
+    /// Minimum and maximum numbers of arguments.
+    fn arity(self) -> (usize, Option<usize>) {
+        match self {
+            Function::Concat | Function::Coalesce => (0, None),
+            Function::Min | Function::Max => (1, None),
+            Function::Upper
+            | Function::Lower
+            | Function::Trim
+            | Function::Len
+            | Function::Floor
+            | Function::Ceil
+            | Function::Abs
+            | Function::Sha256
+            | Function::Md5
+            | Function::Base64
+            | Function::UrlEncode => (1, Some(1)),
+            Function::Round => (1, Some(2)),
+            Function::Contains | Function::StartsWith | Function::EndsWith => (2, Some(2)),
+            Function::Substr | Function::If => (2, Some(3)),
+            Function::Replace => (3, Some(3)),
+        }
+    }
+
+    fn apply(self, values: &[String]) -> Result<String> {
+        let name = self.name();
+        let numbers = || {
+            values
+                .iter()
+                .map(|value| number(value, name))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(match self {
+            Function::Concat => values.concat(),
+            Function::Upper => values[0].to_uppercase(),
+            Function::Lower => values[0].to_lowercase(),
+            Function::Trim => values[0].trim().to_string(),
+            Function::Len => values[0].chars().count().to_string(),
+            Function::Substr => {
+                let offset = count(&values[1], name)?;
+                let chars = values[0].chars().skip(offset);
+                match values.get(2) {
+                    Some(length) => chars.take(count(length, name)?).collect(),
+                    None => chars.collect(),
+                }
+            }
+            Function::Replace => values[0].replace(&values[1], &values[2]),
+            Function::Contains => boolean(values[0].contains(&values[1])),
+            Function::StartsWith => boolean(values[0].starts_with(&values[1])),
+            Function::EndsWith => boolean(values[0].ends_with(&values[1])),
+            Function::Round => {
+                let value = number(&values[0], name)?;
+                match values.get(1) {
+                    Some(digits) => format!("{:.*}", count(digits, name)?, value),
+                    None => format_number(value.round()),
+                }
+            }
+            Function::Floor => format_number(number(&values[0], name)?.floor()),
+            Function::Ceil => format_number(number(&values[0], name)?.ceil()),
+            Function::Abs => format_number(number(&values[0], name)?.abs()),
+            Function::Min => format_number(numbers()?.into_iter().fold(f64::INFINITY, f64::min)),
+            Function::Max => {
+                format_number(numbers()?.into_iter().fold(f64::NEG_INFINITY, f64::max))
+            }
+            Function::Sha256 => digest::hex(&digest::sha256(values[0].as_bytes())),
+            Function::Md5 => digest::hex(&digest::md5(values[0].as_bytes())),
+            Function::Base64 => BASE64_STANDARD.encode(&values[0]),
+            Function::UrlEncode => template::url_encode(&values[0]),
+            // Evaluated lazily
+            Function::If | Function::Coalesce => unreachable!(),
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Function::Concat => "concat",
+            Function::Upper => "upper",
+            Function::Lower => "lower",
+            Function::Trim => "trim",
+            Function::Len => "len",
+            Function::Substr => "substr",
+            Function::Replace => "replace",
+            Function::Contains => "contains",
+            Function::StartsWith => "starts_with",
+            Function::EndsWith => "ends_with",
+            Function::If => "if",
+            Function::Coalesce => "coalesce",
+            Function::Round => "round",
+            Function::Floor => "floor",
+            Function::Ceil => "ceil",
+            Function::Abs => "abs",
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Sha256 => "sha256",
+            Function::Md5 => "md5",
+            Function::Base64 => "base64",
+            Function::UrlEncode => "urlencode",
+        }
+    }
+}
+
+impl Parser<'_> {
+    fn next_if(&mut self, symbols: &[&str]) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) if symbols.contains(symbol) => {
+                self.position += 1;
+                Some(symbol)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next_if(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(self.expected(symbol)),
+        }
+    }
+
+    fn expected(&self, what: &str) -> String {
+        match self.tokens.get(self.position) {
+            Some(token) => format!("expected {} instead of {}", what, token),
+            None => format!("expected {} at the end", what),
+        }
+    }
+
+    fn choice(&mut self) -> Result<Node, String> {
+        let condition = self.or()?;
+        if self.next_if(&["?"]).is_none() {
+            return Ok(condition);
+        }
+        let then = self.choice()?;
+        self.expect(":")?;
+        let otherwise = self.choice()?;
+        Ok(Node::Choice(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.next_if(&["||"]).is_some() {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.comparison()?;
+        while self.next_if(&["&&"]).is_some() {
+            node = Node::And(Box::new(node), Box::new(self.comparison()?));
+        }
+        Ok(node)
+    }
+
+    fn comparison(&mut self) -> Result<Node, String> {
+        let node = self.sum()?;
+        match self.next_if(&["==", "!=", "<", "<=", ">", ">=", "=~", "!~"]) {
+            Some(operator) if operator.ends_with('~') => {
+                let pattern = match self.tokens.get(self.position) {
+                    Some(Token::Literal(pattern)) => pattern,
+                    _ => return Err(self.expected(&format!("a quoted regex after {}", operator))),
+                };
+                let regex = Regex::new(pattern).map_err(|error| error.to_string())?;
+                self.position += 1;
+                Ok(Node::Matches(Box::new(node), regex, operator == "!~"))
+            }
+            Some(operator) => Ok(Node::Compare(
+                Box::new(node),
+                Operator::parse(operator),
+                Box::new(self.sum()?),
+            )),
+            None => Ok(node),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Node, String> {
+        let mut node = self.product()?;
+        while let Some(operator) = self.next_if(&["+", "-"]) {
+            node = arithmetic(node, operator, self.product()?);
+        }
+        Ok(node)
+    }
+
+    fn product(&mut self) -> Result<Node, String> {
+        let mut node = self.unary()?;
+        while let Some(operator) = self.next_if(&["*", "/", "%"]) {
+            node = arithmetic(node, operator, self.unary()?);
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next_if(&["!", "-"]) {
+            Some("!") => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(_) => Ok(Node::Negate(Box::new(self.unary()?))),
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or("a value is missing at the end")?;
+        self.position += 1;
+        match token {
+            Token::Literal(value) => Ok(Node::Literal(value.clone())),
+            Token::Reference(text) => Template::compile(text, self.arg_regex, self.scope)
+                .map(Node::Reference)
+                .map_err(|error| error.to_string()),
+            Token::Symbol("(") => {
+                let node = self.choice()?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Name(name) if name == "true" || name == "false" => {
+                Ok(Node::Literal(name.clone()))
+            }
+            Token::Name(name) => {
+                let name = name.clone();
+                let function =
+                    Function::parse(&name).ok_or_else(|| format!("unknown function {}", name))?;
+                self.expect("(")?;
+                let mut arguments = vec![];
+                if self.next_if(&[")"]).is_none() {
+                    loop {
+                        arguments.push(self.choice()?);
+                        match self.next_if(&[",", ")"]) {
+                            Some(",") => {}
+                            Some(_) => break,
+                            None => return Err(self.expected(", or )")),
+                        }
+                    }
+                }
+                let (min, max) = function.arity();
+                if arguments.len() < min || max.is_some_and(|max| arguments.len() > max) {
+                    return Err(format!(
+                        "wrong number of arguments for {}: {}",
+                        name,
+                        arguments.len()
+                    ));
+                }
+                Ok(Node::Call(function, arguments))
+            }
+            Token::Symbol(symbol) => Err(format!("unexpected {}", symbol)),
+        }
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Literal(value) => write!(f, "{:?}", value),
+            Token::Reference(text) | Token::Name(text) => write!(f, "{}", text),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+fn arithmetic(left: Node, operator: &str, right: Node) -> Node {
+    let operator = operator.chars().next().unwrap();
+    Node::Arithmetic(Box::new(left), operator, Box::new(right))
+}
+
+fn tokenize(expression: &str, arg_regex: &Regex) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let length = if let Some(reference) = arg_regex.find(rest).filter(|m| m.start() == 0) {
+            tokens.push(Token::Reference(reference.as_str().to_string()));
+            reference.end()
+        } else if c == '"' || c == '\'' {
+            let (value, length) = quoted(rest)?;
+            tokens.push(Token::Literal(value));
+            length
+        } else if c.is_ascii_digit() || c == '.' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '.')
+                .unwrap_or(rest.len());
+            let text = &rest[..length];
+            if text.parse::<f64>().is_err() {
+                return Err(format!("invalid number {}", text));
+            }
+            tokens.push(Token::Literal(text.to_string()));
+            length
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..length].to_string()));
+            length
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else {
+            return Err(format!("unexpected {}", c));
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+/// The string quoted at the beginning of `text`, with the escapes \\, \", \', \n and \t, and
+/// the length of `text` it takes.
+fn quoted(text: &str) -> Result<(String, usize), String> {
+    let mut chars = text.char_indices();
+    let quote = chars.next().unwrap().1;
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c if c == quote => return Ok((value, i + 1)),
+            c => value.push(c),
+        }
+    }
+    Err("a quote is not closed".to_string())
+}
+
+/// Compares the values as numbers if both are, or as text if none is. A number cannot be compared
+/// with a text.
+fn compare(left: &str, right: &str) -> Option<Ordering> {
+    match (left.trim().parse::<f64>(), right.trim().parse::<f64>()) {
+        (Ok(left), Ok(right)) => left.partial_cmp(&right),
+        (Err(_), Err(_)) => Some(left.cmp(right)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &str) -> bool {
+    !matches!(value, "" | "0" | "false")
+}
+
+fn boolean(value: bool) -> String {
+    value.to_string()
+}
+
+fn number(value: &str, operator: &str) -> Result<f64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("Value {} must be a number for {}", value, operator))
+}
+
+/// A non-negative integer argument, such as an offset.
+fn count(value: &str, function: &str) -> Result<usize> {
+    value.trim().parse().map_err(|_| {
+        anyhow!(
+            "Value {} must be a non-negative integer for {}",
+            value,
+            function
+        )
+    })
+}
+
+/// Formats the integers without a decimal point, unless they are too large to be exact.
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,752 @@
+use crate::charset;
+use crate::compression::Compression;
+use crate::delimiter::Splitter;
+use crate::raw;
+use crate::xlsx::read_sheet;
+use crate::{parquet, sqlite};
+use anyhow::{anyhow, Context, Result};
+use csv::StringRecord;
+use encoding_rs::Encoding;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Path of the standard input, as an input file.
+pub const STDIN: &str = "-";
+
+/// The standard input of a run, which is the one of the process unless another reader is given.
+/// Its beginning read by `sample` is read again when it is opened.
+#[derive(Clone)]
+pub struct Stdin<'r>(Rc<RefCell<StdinState<'r>>>);
+
+struct StdinState<'r> {
+    sample: Vec<u8>,
+    /// Empty once it is opened
+    reader: Box<dyn Read + 'r>,
+}
+
+/// Of the inputs given by an URL.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum time without receiving any data, once connected.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: u32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    /// A JSON object per line, whose keys are the columns
+    Jsonl,
+    /// A sheet of an Excel workbook
+    Xlsx,
+    Parquet,
+    /// The result of a query on a SQLite database
+    Sqlite,
+}
+
+/// What is done with a CSV record which has not the number of fields of its header line, or else
+/// of the first record.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ragged {
+    Error,
+    Skip,
+    /// Add empty fields to the shorter records
+    Pad,
+    /// Remove the extra fields of the longer records
+    Truncate,
+}
+
+/// Records of several input files, read one after the other.
+/// The header line is the one of the first file, or the keys of its first object. The fields of
+/// the next files are moved to the columns of the same name, and the missing ones are empty.
+pub struct Input<'r> {
+    builder: csv::ReaderBuilder,
+    format: Format,
+    /// Of the workbooks, instead of the first one
+    sheet: Option<String>,
+    /// Of the databases
+    query: Option<String>,
+    /// Of the text files, unless UTF-8
+    encoding: Option<&'static Encoding>,
+    /// Of several characters, which is replaced for the CSV reader
+    delimiter: Option<Vec<u8>>,
+    quote: Option<u8>,
+    escape: Option<u8>,
+    skip_lines: usize,
+    ragged: Ragged,
+    raw: bool,
+    /// Files not opened yet
+    paths: VecDeque<String>,
+    current: Option<File<'r>>,
+    headers: Option<StringRecord>,
+    /// Whether the path of the file is added to its records
+    filename_column: bool,
+    sources: Sources,
+    /// Of the records read
+    count: usize,
+    /// Whether the errors are given with the path of the file, to tell them apart
+    several: bool,
+    stdin: Stdin<'r>,
+}
+
+struct File<'r> {
+    path: String,
+    reader: Reader<'r>,
+    /// Index in the header line of each of its columns, unless they are the same
+    columns: Option<Vec<usize>>,
+    /// Number of fields of the first record, without a header line
+    width: Option<usize>,
+}
+
+enum Reader<'r> {
+    Csv(csv::Reader<Box<dyn Read + 'r>>),
+    Jsonl(JsonLines<'r>),
+    /// Read at once, without the header line
+    Rows(VecDeque<StringRecord>),
+    /// Read by another crate, after the header line of the first file
+    Records(Box<dyn Iterator<Item = Result<StringRecord>>>),
+}
+
+struct JsonLines<'r> {
+    lines: io::Lines<BufReader<Box<dyn Read + 'r>>>,
+    /// Number of the last line read
+    number: usize,
+    /// Object read in advance, for the header line
+    pending: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+/// How the input files are read, besides the settings of the CSV reader.
+pub struct Options<'a, 'r> {
+    pub format: Format,
+    /// Of the workbooks, instead of the first one
+    pub sheet: Option<&'a str>,
+    /// Of the databases
+    pub query: Option<&'a str>,
+    /// Of the text files, unless UTF-8
+    pub encoding: Option<&'static Encoding>,
+    /// Of the CSV files, if it is of several characters, which the CSV reader does not support
+    pub delimiter: Option<&'a [u8]>,
+    /// Between which the delimiters are part of a field, with such a delimiter
+    pub quote: Option<u8>,
+    /// Before the quotes which are part of a quoted field
+    pub escape: Option<u8>,
+    /// Skipped at the beginning of each CSV file, before its header line
+    pub skip_lines: usize,
+    /// The CSV reader must be flexible, unless it is an error
+    pub ragged: Ragged,
+    /// Whether the fields of the CSV files may not be UTF-8, the other bytes being escaped by
+    /// `raw::escape`
+    pub raw: bool,
+    pub has_headers: bool,
+    /// Name of a column added to the records, which contains the path of their file
+    pub filename_column: Option<&'a str>,
+    pub stdin: Stdin<'r>,
+}
+
+impl<'r> Input<'r> {
+    /// Opens the files of `patterns`, which are either paths or glob patterns, or the standard
+    /// input without any.
+    pub fn open(
+        builder: csv::ReaderBuilder,
+        patterns: &[String],
+        options: Options<'_, 'r>,
+    ) -> Result<Input<'r>> {
+        let format = options.format;
+        let paths = if patterns.is_empty() {
+            vec![STDIN.to_string()]
+        } else {
+            expand(patterns)?
+        };
+        let mut input = Input {
+            builder,
+            format,
+            sheet: options.sheet.map(String::from),
+            query: options.query.map(String::from),
+            encoding: options.encoding,
+            delimiter: options.delimiter.map(<[u8]>::to_vec),
+            quote: options.quote,
+            escape: options.escape,
+            skip_lines: options.skip_lines,
+            ragged: options.ragged,
+            raw: options.raw,
+            several: paths.len() > 1,
+            paths: paths.into(),
+            current: None,
+            headers: None,
+            filename_column: options.filename_column.is_some(),
+            stdin: options.stdin,
+            sources: Sources::default(),
+            count: 0,
+        };
+        if options.has_headers {
+            let mut first = input.open_next()?.unwrap();
+            let mut headers = match &mut first.reader {
+                Reader::Rows(rows) => rows.pop_front().unwrap_or_default(),
+                Reader::Records(records) => records.next().transpose()?.unwrap_or_default(),
+                Reader::Csv(reader) => {
+                    input.with_path(csv_headers(reader, input.raw), &first.path)?
+                }
+                Reader::Jsonl(lines) => {
+                    lines.pending = lines.next_object(&first.path)?;
+                    let keys = lines.pending.iter().flat_map(|object| object.keys());
+                    keys.collect()
+                }
+            };
+            if let Some(name) = options.filename_column {
+                headers.push_field(name);
+            }
+            input.headers = Some(headers);
+            input.current = Some(first);
+            // The header lines of the files are checked before any record is processed, while
+            // the keys of the objects are checked as they are read
+            for path in input
+                .paths
+                .iter()
+                .filter(|path| format == Format::Csv && *path != STDIN && !is_url(path))
+            {
+                let mut reader = input.builder.from_reader(input.open_file(path)?);
+                let file_headers = input.with_path(csv_headers(&mut reader, input.raw), path)?;
+                input.columns(&file_headers, path)?;
+            }
+        }
+        Ok(input)
+    }
+
+    pub fn headers(&self) -> Option<&StringRecord> {
+        self.headers.as_ref()
+    }
+
+    pub fn sources(&self) -> Sources {
+        self.sources.clone()
+    }
+
+    /// The paths of the files which are not read yet, if they are all regular files which are
+    /// not compressed, and can thus be read twice quickly. An xlsx sheet would be parsed twice,
+    /// and a SQLite query run twice.
+    pub fn files(&self) -> Option<Vec<&str>> {
+        if self.format == Format::Xlsx || self.format == Format::Sqlite {
+            return None;
+        }
+        self.current
+            .iter()
+            .map(|file| file.path.as_str())
+            .chain(self.paths.iter().map(String::as_str))
+            .map(|path| match fs::metadata(path) {
+                Ok(metadata)
+                    if path != STDIN
+                        && metadata.is_file()
+                        && Compression::from_path(path) == Compression::None =>
+                {
+                    Some(path)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Counts the records of the file at `path`.
+    pub fn count_records(&self, path: &str) -> Result<u64> {
+        let mut count = 0;
+        if self.format == Format::Parquet {
+            return parquet::count_rows(path);
+        }
+        if self.format == Format::Jsonl {
+            for line in BufReader::new(self.open_file(path)?).lines() {
+                if !line?.trim().is_empty() {
+                    count += 1;
+                }
+            }
+            return Ok(count);
+        }
+        let mut reader = self.builder.from_reader(self.open_file(path)?);
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn open_next(&mut self) -> Result<Option<File<'r>>> {
+        let path = match self.paths.pop_front() {
+            None => return Ok(None),
+            Some(path) => path,
+        };
+        if self.format == Format::Jsonl {
+            let lines = JsonLines {
+                lines: BufReader::new(self.open_file(&path)?).lines(),
+                number: 0,
+                pending: None,
+            };
+            return Ok(Some(File {
+                path,
+                reader: Reader::Jsonl(lines),
+                columns: None,
+                width: None,
+            }));
+        }
+        if self.format != Format::Csv
+            && self.format != Format::Jsonl
+            && (path == STDIN || is_url(&path))
+        {
+            return Err(anyhow!("The input must be a file, not {}", display(&path)));
+        }
+        if self.format == Format::Parquet || self.format == Format::Sqlite {
+            let (file_headers, records) = if self.format == Format::Parquet {
+                parquet::read(&path)?
+            } else {
+                sqlite::query(&path, self.query.as_deref().unwrap_or_default())?
+            };
+            let (records, columns): (Box<dyn Iterator<Item = _>>, _) = match &self.headers {
+                Some(_) => (records, self.columns(&file_headers, &path)?),
+                None => (
+                    Box::new(std::iter::once(Ok(file_headers)).chain(records)),
+                    None,
+                ),
+            };
+            return Ok(Some(File {
+                path,
+                reader: Reader::Records(records),
+                columns,
+                width: None,
+            }));
+        }
+        if self.format == Format::Xlsx {
+            let mut rows: VecDeque<_> = read_sheet(&path, self.sheet.as_deref())?.into();
+            // The header line of the first file is read by `open`
+            let columns = match (&self.headers, rows.pop_front()) {
+                (Some(_), Some(file_headers)) => self.columns(&file_headers, &path)?,
+                (None, Some(row)) => {
+                    rows.push_front(row);
+                    None
+                }
+                (_, None) => None,
+            };
+            return Ok(Some(File {
+                path,
+                reader: Reader::Rows(rows),
+                columns,
+                width: None,
+            }));
+        }
+        let mut reader = self.builder.from_reader(self.open_file(&path)?);
+        let columns = match &self.headers {
+            Some(_) => {
+                let file_headers = self.with_path(csv_headers(&mut reader, self.raw), &path)?;
+                self.columns(&file_headers, &path)?
+            }
+            None => None,
+        };
+        Ok(Some(File {
+            path,
+            reader: Reader::Csv(reader),
+            columns,
+            width: None,
+        }))
+    }
+
+    /// Opens a text file, converted to UTF-8.
+    fn open_file(&self, path: &str) -> Result<Box<dyn Read + 'r>> {
+        let reader = open(path, &self.stdin)?;
+        let reader = match self.encoding {
+            Some(encoding) => charset::decoder(reader, encoding),
+            None => reader,
+        };
+        let reader = match self.skip_lines {
+            0 => reader,
+            lines if self.format == Format::Csv => skip_lines(reader, lines)?,
+            _ => reader,
+        };
+        Ok(match &self.delimiter {
+            Some(delimiter) if self.format == Format::Csv => {
+                Box::new(Splitter::new(reader, delimiter, self.quote, self.escape))
+            }
+            _ => reader,
+        })
+    }
+
+    /// Index in the header line of each column of the file at `path`, unless they are the same.
+    fn columns(&self, file_headers: &StringRecord, path: &str) -> Result<Option<Vec<usize>>> {
+        let headers = self.headers.as_ref().unwrap();
+        let columns = file_headers
+            .iter()
+            .map(|name| {
+                headers
+                    .iter()
+                    .position(|header| header == name)
+                    .ok_or_else(|| {
+                        anyhow!("Column {} of {} is not in the first input file", name, path)
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let unchanged = columns.iter().enumerate().all(|(i, &index)| i == index)
+            && columns.len() + usize::from(self.filename_column) == headers.len();
+        Ok(if unchanged { None } else { Some(columns) })
+    }
+
+    fn read_record(&mut self) -> Result<Option<StringRecord>> {
+        loop {
+            let file = match &mut self.current {
+                Some(file) => file,
+                None => match self.open_next()? {
+                    Some(file) => self.current.insert(file),
+                    None => return Ok(None),
+                },
+            };
+            let mut record = StringRecord::new();
+            let read = match &mut file.reader {
+                Reader::Csv(reader) if self.raw => read_escaped(reader, &mut record),
+                Reader::Csv(reader) => reader.read_record(&mut record),
+                Reader::Rows(rows) => Ok(rows.pop_front().map(|row| record = row).is_some()),
+                Reader::Records(records) => match records.next().transpose()? {
+                    Some(next) => {
+                        record = next;
+                        Ok(true)
+                    }
+                    None => Ok(false),
+                },
+                Reader::Jsonl(lines) => {
+                    let object = match lines.pending.take() {
+                        Some(object) => Some(object),
+                        None => lines.next_object(&file.path)?,
+                    };
+                    match (object, &self.headers) {
+                        (Some(object), Some(headers)) => {
+                            let columns = headers.len() - usize::from(self.filename_column);
+                            let headers = headers.iter().take(columns);
+                            record = object_record(object, headers, lines.number, &file.path)?;
+                            Ok(true)
+                        }
+                        _ => Ok(false),
+                    }
+                }
+            };
+            let path = file.path.clone();
+            if !self.with_path(read, &path)? {
+                self.current = None;
+                continue;
+            }
+            let file = self.current.as_mut().unwrap();
+            if let Reader::Csv(reader) = &mut file.reader {
+                let width = match reader.has_headers() {
+                    true => reader.byte_headers()?.len(),
+                    false => *file.width.get_or_insert(record.len()),
+                };
+                match self.ragged {
+                    _ if record.len() == width => {}
+                    Ragged::Skip => continue,
+                    Ragged::Pad if record.len() < width => {
+                        for _ in record.len()..width {
+                            record.push_field("");
+                        }
+                    }
+                    Ragged::Truncate if record.len() > width => record.truncate(width),
+                    _ => {
+                        let line = record.position().map_or(0, csv::Position::line);
+                        let error = anyhow!(
+                            "Line {} has {} fields instead of {}",
+                            line,
+                            record.len(),
+                            width
+                        );
+                        return Err(match self.several {
+                            true => error.context(format!("Failed to read {}", path)),
+                            false => error,
+                        });
+                    }
+                }
+            }
+            let file = self.current.as_ref().unwrap();
+            if let (Some(columns), Some(headers)) = (&file.columns, &self.headers) {
+                let mut fields = vec![""; headers.len()];
+                for (field, &index) in record.iter().zip(columns) {
+                    fields[index] = field;
+                }
+                fields.truncate(headers.len() - usize::from(self.filename_column));
+                record = fields.into_iter().collect();
+            }
+            if self.filename_column {
+                record.push_field(&file.path);
+            }
+            self.sources.add(self.count, &file.path);
+            self.count += 1;
+            return Ok(Some(record));
+        }
+    }
+
+    /// Adds the path of the file to the errors, if there are several files.
+    fn with_path<T>(&self, result: csv::Result<T>, path: &str) -> Result<T> {
+        if self.several {
+            result.context(format!("Failed to read {}", path))
+        } else {
+            Ok(result?)
+        }
+    }
+}
+
+/// The path of the file of each record, shared with the threads which run the commands.
+#[derive(Clone, Default)]
+pub struct Sources(Arc<Mutex<Vec<(usize, String)>>>);
+
+impl Sources {
+    /// Path of the file of the record at `index`, beginning at 0, which must be read already.
+    pub fn path(&self, index: usize) -> String {
+        let sources = self.0.lock().unwrap();
+        let next = sources.partition_point(|(first, _)| *first <= index);
+        next.checked_sub(1)
+            .map(|i| sources[i].1.clone())
+            .unwrap_or_default()
+    }
+
+    /// Records that the record at `index` and the next ones are read from `path`.
+    fn add(&self, index: usize, path: &str) {
+        let mut sources = self.0.lock().unwrap();
+        if sources.last().is_none_or(|(_, last)| last != path) {
+            sources.push((index, path.to_string()));
+        }
+    }
+}
+
+impl JsonLines<'_> {
+    /// Skips the empty lines.
+    fn next_object(
+        &mut self,
+        path: &str,
+    ) -> Result<Option<serde_json::Map<String, serde_json::Value>>> {
+        for line in &mut self.lines {
+            self.number += 1;
+            let line = line.context(format!("Failed to read {}", path))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            return match serde_json::from_str(&line) {
+                Ok(serde_json::Value::Object(object)) => Ok(Some(object)),
+                Ok(_) => Err(anyhow!(
+                    "Line {} of {} is not a JSON object",
+                    self.number,
+                    display(path)
+                )),
+                Err(error) => Err(anyhow!(
+                    "Invalid JSON on line {} of {}: {}",
+                    self.number,
+                    display(path),
+                    error
+                )),
+            };
+        }
+        Ok(None)
+    }
+}
+
+/// The values of `object` in the columns of `headers`. Strings are written as is, null as an
+/// empty field, and the other values as JSON.
+fn object_record<'a, I>(
+    object: serde_json::Map<String, serde_json::Value>,
+    headers: I,
+    number: usize,
+    path: &str,
+) -> Result<StringRecord>
+where
+    I: Iterator<Item = &'a str> + Clone,
+{
+    let mut fields = vec![String::new(); headers.clone().count()];
+    for (key, value) in object {
+        let index = headers
+            .clone()
+            .position(|header| header == key)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Key {} on line {} of {} is not in the first object",
+                    key,
+                    number,
+                    display(path)
+                )
+            })?;
+        fields[index] = match value {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(string) => string,
+            value => value.to_string(),
+        };
+    }
+    Ok(fields.into_iter().collect())
+}
+
+impl Iterator for Input<'_> {
+    type Item = Result<StringRecord>;
+
+    fn next(&mut self) -> Option<Result<StringRecord>> {
+        self.read_record().transpose()
+    }
+}
+
+/// The first `size` bytes of the first input of `patterns`, in UTF-8 and after `lines` lines,
+/// and whether it is the whole input.
+pub fn sample(
+    patterns: &[String],
+    stdin: &Stdin<'_>,
+    encoding: Option<&'static Encoding>,
+    lines: usize,
+    size: usize,
+) -> Result<(Vec<u8>, bool)> {
+    let path = match patterns.is_empty() {
+        true => STDIN.to_string(),
+        false => expand(patterns)?.remove(0),
+    };
+    let mut bytes = vec![];
+    if path == STDIN {
+        bytes = stdin
+            .read_sample(size)
+            .context("Failed to read the standard input")?;
+    } else {
+        open(&path, stdin)?
+            .take(size as u64)
+            .read_to_end(&mut bytes)
+            .context(format!("Failed to read {}", path))?;
+    }
+    let complete = bytes.len() < size;
+    if let Some(encoding) = encoding {
+        let mut decoded = vec![];
+        charset::decoder(Box::new(Cursor::new(bytes)), encoding).read_to_end(&mut decoded)?;
+        bytes = decoded;
+    }
+    for _ in 0..lines {
+        match bytes.iter().position(|&byte| byte == b'\n') {
+            Some(end) => bytes.drain(..=end),
+            None => bytes.drain(..),
+        };
+    }
+    Ok((bytes, complete))
+}
+
+fn csv_headers(
+    reader: &mut csv::Reader<Box<dyn Read + '_>>,
+    raw: bool,
+) -> csv::Result<StringRecord> {
+    match raw {
+        true => Ok(reader.byte_headers()?.iter().map(raw::escape).collect()),
+        false => reader.headers().cloned(),
+    }
+}
+
+/// Reads a record whose fields may not be UTF-8, escaped by `raw::escape`.
+fn read_escaped(
+    reader: &mut csv::Reader<Box<dyn Read + '_>>,
+    record: &mut StringRecord,
+) -> csv::Result<bool> {
+    let mut bytes = csv::ByteRecord::new();
+    if !reader.read_byte_record(&mut bytes)? {
+        return Ok(false);
+    }
+    *record = bytes.iter().map(raw::escape).collect();
+    record.set_position(bytes.position().cloned());
+    Ok(true)
+}
+
+/// Discards the first `lines` lines of `reader`.
+fn skip_lines<'a>(reader: Box<dyn Read + 'a>, lines: usize) -> io::Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    for _ in 0..lines {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+    }
+    Ok(Box::new(reader))
+}
+
+/// Opens the file, URL or standard input at `path`, decompressed according to its extension.
+pub fn open<'r>(path: &str, stdin: &Stdin<'r>) -> Result<Box<dyn Read + 'r>> {
+    let reader: Box<dyn Read + 'r> = if path == STDIN {
+        stdin.open()
+    } else if is_url(path) {
+        download(path)?
+    } else {
+        Box::new(fs::File::open(path).context(format!("Failed to open {}", path))?)
+    };
+    Compression::from_path(path)
+        .decoder(reader)
+        .context(format!("Failed to read {}", path))
+}
+
+impl<'r> Stdin<'r> {
+    pub fn new(reader: Box<dyn Read + 'r>) -> Stdin<'r> {
+        Stdin(Rc::new(RefCell::new(StdinState {
+            sample: vec![],
+            reader,
+        })))
+    }
+
+    /// Reads the first `size` bytes, or the next ones, which are read again by `open`.
+    fn read_sample(&self, size: usize) -> io::Result<Vec<u8>> {
+        let state = &mut *self.0.borrow_mut();
+        let mut bytes = vec![];
+        state
+            .reader
+            .by_ref()
+            .take(size as u64)
+            .read_to_end(&mut bytes)?;
+        state.sample.extend_from_slice(&bytes);
+        Ok(bytes)
+    }
+
+    fn open(&self) -> Box<dyn Read + 'r> {
+        let state = &mut *self.0.borrow_mut();
+        let sample = Cursor::new(std::mem::take(&mut state.sample));
+        let reader = std::mem::replace(&mut state.reader, Box::new(io::empty()));
+        Box::new(sample.chain(reader))
+    }
+}
+
+fn display(path: &str) -> &str {
+    if path == STDIN {
+        "the standard input"
+    } else {
+        path
+    }
+}
+
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Streams the content at `url`, following the redirections.
+fn download(url: &str) -> Result<Box<dyn Read>> {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .redirects(MAX_REDIRECTS)
+        .user_agent(concat!("csv-exec/", env!("CARGO_PKG_VERSION")))
+        .build();
+    match agent.get(url).call() {
+        Ok(response) => Ok(response.into_reader()),
+        Err(ureq::Error::Status(status, response)) => Err(anyhow!(
+            "Failed to download {}: HTTP status {} {}",
+            url,
+            status,
+            response.status_text()
+        )),
+        Err(error) => Err(error).context(format!("Failed to download {}", url)),
+    }
+}
+
+/// Replaces the glob patterns by the paths they match, in alphabetical order.
+fn expand(patterns: &[String]) -> Result<Vec<String>> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        if is_url(pattern) || !pattern.contains(['*', '?', '[']) {
+            paths.push(pattern.clone());
+            continue;
+        }
+        let matches = glob::glob(pattern)?
+            .map(|path| Ok(path?.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        if matches.is_empty() {
+            return Err(anyhow!("No file matches {}", pattern));
+        }
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
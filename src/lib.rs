@@ -0,0 +1,2610 @@
+//! Runs a command on each record of CSV files, and writes the records with the results of
+//! their commands: the library of the csv-exec command, whose options are set with
+//! `CsvExec::builder()`.
+
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::{IsTerminal, Seek};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+mod audit;
+mod breaker;
+mod builder;
+mod charset;
+mod checkpoint;
+mod columns;
+mod compression;
+mod delimiter;
+mod digest;
+mod exec;
+mod expression;
+mod input;
+mod output;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod partition;
+mod pool;
+mod printf;
+mod progress;
+mod projection;
+mod rate;
+mod raw;
+mod sniff;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod summary;
+mod template;
+mod unavailable;
+#[cfg(feature = "xlsx")]
+mod xlsx;
+
+#[cfg(not(feature = "parquet"))]
+use unavailable::parquet;
+#[cfg(not(feature = "sqlite"))]
+use unavailable::sqlite;
+#[cfg(not(feature = "xlsx"))]
+use unavailable::xlsx;
+
+pub use builder::{Builder, CsvExec};
+pub use columns::SplitPolicy;
+pub use csv::{QuoteStyle, Terminator, Trim};
+pub use input::Ragged;
+
+use audit::AuditLog;
+use breaker::Breaker;
+use checkpoint::{Checkpoint, CountingWriter, Position};
+use columns::{
+    json_pointer, Column, Encoding, Extract, Kind, Lines, Placement, ResultFormat, Split,
+};
+use compression::Compression;
+use exec::{Coprocesses, Execution, Invocation};
+use expression::Expression;
+use input::{Format, Input, Options};
+use output::TableWriter;
+use partition::PartitionedWriter;
+use pool::{Pool, Reorder};
+use progress::Progress;
+use projection::{Order, Projection, Selection};
+use rate::RateLimiter;
+use sqlite::SqliteWriter;
+use summary::Summary;
+use template::{column_index, Dialect, Scope, Template};
+
+/// Rows which may be pending per job, either running, queued or waiting for an earlier row.
+const REORDER_WINDOW_PER_JOB: usize = 4;
+
+/// Exit status when --max-runtime stopped the run, as timeout(1) does.
+const EXIT_MAX_RUNTIME: i32 = 124;
+
+/// Exit status with --strict-exit when a command failed, as xargs(1) does.
+const EXIT_FAILURES: i32 = 123;
+
+/// A record with the executions of its commands, in order.
+struct Row {
+    index: usize,
+    record: csv::StringRecord,
+    /// Executions of the commands which completed
+    executions: Vec<Execution>,
+    /// Why the next command did not complete, if any
+    failure: Option<Failure>,
+    /// The result, if no command was run because it was already in the record, or because the
+    /// record does not match --where
+    skipped: Option<String>,
+}
+
+/// Executions which can be reused, keyed by invocation.
+enum Cache {
+    /// The successful executions, once they are completed
+    Successful(Mutex<HashMap<Invocation, Execution>>),
+    /// All of them, including the ones in progress, with the error if they failed to run
+    All(Mutex<HashMap<Invocation, Outcome>>),
+}
+
+/// Of a run, which is `None` if it was not launched, set once by the first worker.
+type Outcome = Arc<OnceLock<Option<Result<Execution, String>>>>;
+
+enum Failure {
+    /// The command was not launched
+    Stopped,
+    /// The command failed, with its execution if it ran until its exit
+    Error(anyhow::Error, Option<Execution>),
+}
+
+/// What gives a result: a command, or an expression evaluated by csv-exec.
+#[derive(Clone)]
+enum Step<C, E> {
+    Command(C),
+    Expression(E),
+}
+
+/// A command to execute, split into its program and its arguments.
+struct CommandLine {
+    program: String,
+    args: Vec<String>,
+    /// The arguments, parsed for the substitution
+    templates: Vec<Template>,
+}
+
+/// How the records of a batch are given to the command.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BatchInput {
+    /// The arguments are repeated for each record
+    Args,
+    /// Each record is written as a CSV line to the input of the command
+    Stdin,
+}
+
+/// What is written to the input of the command of each record.
+enum Stdin {
+    /// The record as a CSV line
+    Csv,
+    /// The record as an object keyed by header names, or by positions without headers
+    Json,
+    /// The value of the column at this index
+    Field(usize),
+}
+
+/// How the whole record is written to the input of its command.
+#[derive(Clone, Copy)]
+pub enum StdinRecord {
+    Csv,
+    /// An object keyed by header names, or by positions without headers
+    Json,
+}
+
+/// What to do with a record when its command fails.
+#[derive(Clone)]
+pub enum OnError {
+    Abort,
+    SkipRow,
+    Empty,
+    Placeholder(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    /// A JSON object per line, whose keys are the columns (the ones of the first object)
+    Jsonl,
+    /// A sheet of an Excel workbook, with the xlsx feature
+    Xlsx,
+    /// With the parquet feature
+    Parquet,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Csv,
+    /// A GitHub flavored Markdown table
+    Markdown,
+    Html,
+    /// With string columns, with the parquet feature
+    Parquet,
+}
+
+/// How the output of the commands is converted to text.
+#[derive(Clone, Copy)]
+pub enum OutputEncoding {
+    /// Invalid UTF-8 is a failure
+    Strict,
+    /// Invalid UTF-8 sequences are replaced
+    Lossy,
+    /// The whole output is encoded, without processing its whitespace or lines
+    Base64,
+    Hex,
+}
+
+/// What is done with an output larger than the maximum.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputOverflow {
+    Truncate,
+    Error,
+}
+
+/// Receives the messages of a run, which the command line writes to the error output.
+type OnMessage = Arc<dyn Fn(&str) + Send + Sync>;
+
+/// Default of --arg-regex.
+pub const DEFAULT_ARG_REGEX: &str = r"\$\{([^}]*)\}|\$(-?[0-9]+|#|[A-Za-z_][A-Za-z0-9_]*)";
+
+/// The options of a run, set by the `Builder` and checked by `Builder::build`.
+#[derive(Clone)]
+struct Config {
+    input_paths: Vec<String>,
+    input_format: InputFormat,
+    sheet: Option<String>,
+    input_sqlite: Option<String>,
+    query: Option<String>,
+    output_format: OutputFormat,
+    filename_column: Option<String>,
+    output_path: Option<String>,
+    exec: Vec<Step<String, String>>,
+    no_headers: bool,
+    /// Whether the delimiter and the header line are guessed from the beginning of the input
+    sniff: bool,
+    /// Of one or several characters
+    delimiter: String,
+    out_delimiter: Option<String>,
+    quote: u8,
+    /// Instead of doubling the quotes in the quoted fields
+    escape: Option<u8>,
+    /// Which starts the lines which are skipped
+    comment: Option<u8>,
+    skip_lines: usize,
+    flexible: Ragged,
+    trim: Trim,
+    /// Whether the CSV fields may be bytes which are not UTF-8
+    bytes: bool,
+    out_quote: Option<u8>,
+    terminator: Option<Terminator>,
+    out_terminator: Option<Terminator>,
+    quote_style: Option<QuoteStyle>,
+    encoding: Option<String>,
+    out_encoding: Option<String>,
+    /// Whether the quotes are read and written, or are a character like the others
+    quoting: bool,
+    arg_regex: String,
+    /// Whether the unknown references and the missing values are errors
+    strict_refs: bool,
+    /// Whether the input and the references are checked, without running the commands
+    check: bool,
+    new_column_name: Vec<String>,
+    replace_column: Option<String>,
+    /// Separator of the fields of the result
+    split_output: Option<u8>,
+    result_headers: Option<Vec<String>>,
+    split_policy: SplitPolicy,
+    json_output: Option<Vec<String>>,
+    no_trim: bool,
+    first_line_only: bool,
+    join_lines: Option<String>,
+    output_encoding: OutputEncoding,
+    capture: Option<String>,
+    /// From 1
+    column_position: Option<usize>,
+    before: Option<String>,
+    after: Option<String>,
+    /// Column whose fields are replaced by the results, and referenced by CELL
+    map: Option<String>,
+    /// Columns of the input which are written, or are not
+    select: Option<Vec<String>>,
+    drop: Option<Vec<String>>,
+    /// Columns of the output which are written first
+    order: Option<Vec<String>>,
+    drop_unlisted: bool,
+    /// Old and new names of the output columns
+    rename: Vec<(String, String)>,
+    /// Key columns written with the new columns, or the row number if empty
+    only_result: Option<Vec<String>>,
+    jobs: usize,
+    unordered: bool,
+    shell: bool,
+    shell_path: Option<String>,
+    timeout: Option<Duration>,
+    max_runtime: Option<Duration>,
+    exit_code_column: Option<String>,
+    stderr_column: Option<String>,
+    duration_column: Option<String>,
+    started_column: Option<String>,
+    attempts_column: Option<String>,
+    on_error: Option<OnError>,
+    retries: u32,
+    retry_delay: Duration,
+    retry_backoff: f64,
+    retry_on_exit_codes: Option<Vec<i32>>,
+    /// Commands launched per second
+    rate: Option<f64>,
+    memoize: bool,
+    /// Whether each distinct invocation is run once, even when it fails
+    unique: bool,
+    coprocess: bool,
+    batch_size: Option<usize>,
+    batch_input: BatchInput,
+    stdin_record: Option<StdinRecord>,
+    stdin_field: Option<String>,
+    field_env: bool,
+    env: Vec<(String, String)>,
+    clean_env: bool,
+    pass_env: Vec<String>,
+    cwd: Option<String>,
+    max_output_bytes: Option<u64>,
+    output_overflow: OutputOverflow,
+    dry_run: bool,
+    /// Whether the records are written as is if the commands succeed, and else are skipped
+    filter: bool,
+    /// Whether the record is written for each line of the result
+    explode: bool,
+    /// Column whose fields are the keys of the groups of records given to a command
+    group_by: Option<String>,
+    /// Whether only the first record of a group is written
+    summarize: bool,
+    /// Command run once at the end, with the output on its input
+    finally: Option<String>,
+    /// Where its output is written, instead of a last record
+    finally_output: Option<String>,
+    /// Whether the progress is drawn on the error output, when it is a terminal
+    progress: bool,
+    summary: bool,
+    summary_json: Option<String>,
+    /// Receives the warnings, the errors of the skipped records and the summary
+    on_message: Option<OnMessage>,
+    audit_log: Option<String>,
+    strict_exit: bool,
+    fail_fast: bool,
+    abort_after_failures: Option<u64>,
+    max_failure_rate: Option<f64>,
+    failure_rate_warm_up: u64,
+    checkpoint: Option<String>,
+    resume: bool,
+    skip_populated: bool,
+    /// Condition on the records for which the commands are run
+    condition: Option<String>,
+    /// Result of the other records
+    otherwise: Option<String>,
+    append: bool,
+    no_clobber: bool,
+    output_template: Option<String>,
+    output_sqlite: Option<String>,
+    table: Option<String>,
+}
+
+/// The defaults of the command line, except the progress which is not drawn.
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            input_paths: vec![],
+            input_format: InputFormat::Csv,
+            sheet: None,
+            input_sqlite: None,
+            query: None,
+            output_format: OutputFormat::Csv,
+            filename_column: None,
+            output_path: None,
+            exec: vec![],
+            no_headers: false,
+            sniff: false,
+            delimiter: ",".to_string(),
+            out_delimiter: None,
+            quote: b'"',
+            escape: None,
+            comment: None,
+            skip_lines: 0,
+            flexible: Ragged::Error,
+            trim: Trim::None,
+            bytes: false,
+            out_quote: None,
+            terminator: None,
+            out_terminator: None,
+            quote_style: None,
+            encoding: None,
+            out_encoding: None,
+            quoting: true,
+            arg_regex: DEFAULT_ARG_REGEX.to_string(),
+            strict_refs: false,
+            check: false,
+            new_column_name: vec![],
+            replace_column: None,
+            split_output: None,
+            result_headers: None,
+            split_policy: SplitPolicy::Strict,
+            json_output: None,
+            no_trim: false,
+            first_line_only: false,
+            join_lines: None,
+            output_encoding: OutputEncoding::Strict,
+            capture: None,
+            column_position: None,
+            before: None,
+            after: None,
+            map: None,
+            select: None,
+            drop: None,
+            order: None,
+            drop_unlisted: false,
+            rename: vec![],
+            only_result: None,
+            jobs: 1,
+            unordered: false,
+            shell: false,
+            shell_path: None,
+            timeout: None,
+            max_runtime: None,
+            exit_code_column: None,
+            stderr_column: None,
+            duration_column: None,
+            started_column: None,
+            attempts_column: None,
+            on_error: None,
+            retries: 0,
+            retry_delay: Duration::from_secs(1),
+            retry_backoff: 2.0,
+            retry_on_exit_codes: None,
+            rate: None,
+            memoize: false,
+            unique: false,
+            coprocess: false,
+            batch_size: None,
+            batch_input: BatchInput::Args,
+            stdin_record: None,
+            stdin_field: None,
+            field_env: false,
+            env: vec![],
+            clean_env: false,
+            pass_env: vec![],
+            cwd: None,
+            max_output_bytes: None,
+            output_overflow: OutputOverflow::Truncate,
+            dry_run: false,
+            filter: false,
+            explode: false,
+            group_by: None,
+            summarize: false,
+            finally: None,
+            finally_output: None,
+            progress: false,
+            summary: false,
+            summary_json: None,
+            on_message: None,
+            audit_log: None,
+            strict_exit: false,
+            fail_fast: false,
+            abort_after_failures: None,
+            max_failure_rate: None,
+            failure_rate_warm_up: 100,
+            checkpoint: None,
+            resume: false,
+            skip_populated: false,
+            condition: None,
+            otherwise: None,
+            append: false,
+            no_clobber: false,
+            output_template: None,
+            output_sqlite: None,
+            table: None,
+        }
+    }
+}
+
+impl Config {
+    /// Checks the options which do not depend on the input, which are checked by clap on the
+    /// command line, or by `run_with`.
+    fn check(&self) -> Result<()> {
+        if self.exec.is_empty() {
+            return Err(anyhow!("No command to execute"));
+        }
+        let commands = self
+            .exec
+            .iter()
+            .filter(|step| matches!(step, Step::Command(_)))
+            .count();
+        // Whether each option is given, by the name of its method of the builder
+        let options = [
+            ("input_path", !self.input_paths.is_empty()),
+            ("input_sqlite", self.input_sqlite.is_some()),
+            ("output_path", self.output_path.is_some()),
+            ("output_template", self.output_template.is_some()),
+            ("output_sqlite", self.output_sqlite.is_some()),
+            ("append", self.append),
+            ("no_clobber", self.no_clobber),
+            ("several commands", commands > 1),
+            (
+                "expression",
+                self.exec
+                    .iter()
+                    .any(|step| matches!(step, Step::Expression(_))),
+            ),
+            ("no_headers", self.no_headers),
+            ("sniff", self.sniff),
+            ("bytes", self.bytes),
+            ("encoding", self.encoding.is_some()),
+            ("out_encoding", self.out_encoding.is_some()),
+            ("check", self.check),
+            ("new_column_name", !self.new_column_name.is_empty()),
+            ("replace_column", self.replace_column.is_some()),
+            ("map", self.map.is_some()),
+            ("skip_populated", self.skip_populated),
+            ("condition", self.condition.is_some()),
+            ("otherwise", self.otherwise.is_some()),
+            ("first_line_only", self.first_line_only),
+            ("join_lines", self.join_lines.is_some()),
+            ("explode", self.explode),
+            ("group_by", self.group_by.is_some()),
+            ("summarize", self.summarize),
+            ("finally", self.finally.is_some()),
+            ("finally_output", self.finally_output.is_some()),
+            ("split_output", self.split_output.is_some()),
+            ("json_output", self.json_output.is_some()),
+            ("capture", self.capture.is_some()),
+            ("result_headers", self.result_headers.is_some()),
+            ("column_position", self.column_position.is_some()),
+            ("before", self.before.is_some()),
+            ("after", self.after.is_some()),
+            ("select", self.select.is_some()),
+            ("drop", self.drop.is_some()),
+            ("order", self.order.is_some()),
+            ("drop_unlisted", self.drop_unlisted),
+            ("only_result", self.only_result.is_some()),
+            ("rename", !self.rename.is_empty()),
+            ("exit_code_column", self.exit_code_column.is_some()),
+            ("stderr_column", self.stderr_column.is_some()),
+            ("duration_column", self.duration_column.is_some()),
+            ("started_column", self.started_column.is_some()),
+            ("attempts_column", self.attempts_column.is_some()),
+            ("unordered", self.unordered),
+            ("timeout", self.timeout.is_some()),
+            ("coprocess", self.coprocess),
+            ("batch_size", self.batch_size.is_some()),
+            ("stdin_record", self.stdin_record.is_some()),
+            ("stdin_field", self.stdin_field.is_some()),
+            ("field_env", self.field_env),
+            ("clean_env", self.clean_env),
+            ("pass_env", !self.pass_env.is_empty()),
+            ("cwd", self.cwd.is_some()),
+            ("max_output_bytes", self.max_output_bytes.is_some()),
+            ("summary_json", self.summary_json.is_some()),
+            ("audit_log", self.audit_log.is_some()),
+            ("filter", self.filter),
+            ("dry_run", self.dry_run),
+            ("memoize", self.memoize),
+            ("unique", self.unique),
+            ("on_error", self.on_error.is_some()),
+            ("strict_exit", self.strict_exit),
+            ("fail_fast", self.fail_fast),
+            ("abort_after_failures", self.abort_after_failures.is_some()),
+            ("max_failure_rate", self.max_failure_rate.is_some()),
+            ("checkpoint", self.checkpoint.is_some()),
+            ("resume", self.resume),
+            ("retries", self.retries > 0),
+        ];
+        let given = |name: &str| options.contains(&(name, true));
+        // As checked by clap on the command line. `map` comes first, as it sets the replaced
+        // column too.
+        let conflicts: &[(&str, &[&str])] = &[
+            (
+                "map",
+                &[
+                    "split_output",
+                    "json_output",
+                    "capture",
+                    "column_position",
+                    "before",
+                    "after",
+                    "only_result",
+                    "filter",
+                ],
+            ),
+            ("input_sqlite", &["input_path"]),
+            ("output_template", &["output_path", "checkpoint"]),
+            (
+                "output_sqlite",
+                &["output_path", "output_template", "checkpoint", "append"],
+            ),
+            ("no_clobber", &["append"]),
+            ("expression", &["coprocess", "batch_size"]),
+            (
+                "bytes",
+                &[
+                    "encoding",
+                    "out_encoding",
+                    "output_template",
+                    "output_sqlite",
+                ],
+            ),
+            ("sniff", &["no_headers"]),
+            (
+                "check",
+                &[
+                    "output_path",
+                    "output_template",
+                    "output_sqlite",
+                    "checkpoint",
+                    "audit_log",
+                    "dry_run",
+                    "coprocess",
+                    "summary_json",
+                ],
+            ),
+            (
+                "skip_populated",
+                &[
+                    "split_output",
+                    "json_output",
+                    "capture",
+                    "several commands",
+                    "batch_size",
+                ],
+            ),
+            ("condition", &["batch_size", "group_by", "filter"]),
+            ("join_lines", &["first_line_only"]),
+            (
+                "explode",
+                &[
+                    "several commands",
+                    "split_output",
+                    "json_output",
+                    "capture",
+                    "join_lines",
+                    "first_line_only",
+                    "batch_size",
+                    "filter",
+                ],
+            ),
+            (
+                "group_by",
+                &[
+                    "batch_size",
+                    "stdin_record",
+                    "stdin_field",
+                    "coprocess",
+                    "explode",
+                    "checkpoint",
+                    "skip_populated",
+                ],
+            ),
+            (
+                "finally",
+                &["filter", "output_template", "output_sqlite", "checkpoint"],
+            ),
+            (
+                "split_output",
+                &["json_output", "capture", "replace_column"],
+            ),
+            ("json_output", &["capture", "replace_column"]),
+            ("capture", &["replace_column"]),
+            ("column_position", &["replace_column", "before", "after"]),
+            ("before", &["replace_column", "after"]),
+            ("after", &["replace_column"]),
+            ("drop", &["select"]),
+            (
+                "only_result",
+                &[
+                    "select",
+                    "drop",
+                    "replace_column",
+                    "column_position",
+                    "before",
+                    "after",
+                ],
+            ),
+            ("rename", &["no_headers"]),
+            ("coprocess", &["memoize", "timeout", "retries"]),
+            ("batch_size", &["coprocess"]),
+            ("stdin_record", &["coprocess", "batch_size", "stdin_field"]),
+            ("stdin_field", &["coprocess", "batch_size"]),
+            ("field_env", &["coprocess", "batch_size"]),
+            ("cwd", &["coprocess"]),
+            ("max_output_bytes", &["coprocess"]),
+            (
+                "filter",
+                &[
+                    "new_column_name",
+                    "replace_column",
+                    "split_output",
+                    "json_output",
+                    "capture",
+                    "exit_code_column",
+                    "stderr_column",
+                    "duration_column",
+                    "started_column",
+                    "attempts_column",
+                    "only_result",
+                    "skip_populated",
+                    "condition",
+                    "on_error",
+                    "strict_exit",
+                    "fail_fast",
+                    "abort_after_failures",
+                    "max_failure_rate",
+                ],
+            ),
+            ("dry_run", &["coprocess"]),
+            ("unique", &["memoize", "coprocess"]),
+            ("checkpoint", &["unordered"]),
+        ];
+        for (option, others) in conflicts {
+            if let Some(other) = others.iter().find(|other| given(option) && given(other)) {
+                return Err(anyhow!("{} cannot be used with {}", option, other));
+            }
+        }
+        // An option requires one of the others
+        let requirements: &[(&str, &[&str])] = &[
+            ("append", &["output_path"]),
+            ("no_clobber", &["output_path"]),
+            ("otherwise", &["condition"]),
+            ("summarize", &["group_by"]),
+            ("finally_output", &["finally"]),
+            ("split_output", &["result_headers"]),
+            (
+                "result_headers",
+                &["split_output", "json_output", "capture"],
+            ),
+            ("drop_unlisted", &["order"]),
+            ("pass_env", &["clean_env"]),
+            ("checkpoint", &["output_path"]),
+            ("resume", &["checkpoint"]),
+        ];
+        for (option, required) in requirements {
+            if given(option) && !required.iter().any(|required| given(required)) {
+                return Err(anyhow!("{} requires {}", option, required.join(" or ")));
+            }
+        }
+
+        if self.delimiter.is_empty() || self.out_delimiter.as_deref() == Some("") {
+            return Err(anyhow!("Missing value"));
+        }
+        let characters = [Some(self.quote), self.escape, self.comment, self.out_quote];
+        if let Some(c) = characters.iter().flatten().find(|c| !c.is_ascii()) {
+            return Err(anyhow!(
+                "Value {} must be 1 ASCII character",
+                char::from(*c)
+            ));
+        }
+        if self.jobs == 0 {
+            return Err(anyhow!("Value 0 must be a positive number of jobs"));
+        }
+        if self.batch_size == Some(0) {
+            return Err(anyhow!("Value 0 must be a positive number of records"));
+        }
+        if !(self.retry_backoff.is_finite() && self.retry_backoff >= 1.0) {
+            return Err(anyhow!(
+                "Value {} must be a factor greater or equal to 1",
+                self.retry_backoff
+            ));
+        }
+        if let Some(rate) = self.rate.filter(|rate| !(rate.is_finite() && *rate > 0.0)) {
+            return Err(anyhow!("Value {} must be a positive rate", rate));
+        }
+        self.rate.map(RateLimiter::new).transpose()?;
+        if let Some(rate) = self
+            .max_failure_rate
+            .filter(|rate| !(0.0..1.0).contains(rate))
+        {
+            return Err(anyhow!(
+                "Value {} must be a fraction greater or equal to 0 and lower than 1",
+                rate
+            ));
+        }
+        if self.abort_after_failures == Some(0) {
+            return Err(anyhow!("Value 0 must be a positive number of failures"));
+        }
+
+        let compressed = self
+            .output_path
+            .as_deref()
+            .is_some_and(|path| Compression::from_path(path) != Compression::None);
+        if self.checkpoint.is_some() && compressed {
+            return Err(anyhow!(
+                "checkpoint cannot be used with a compressed output"
+            ));
+        }
+        if self.output_sqlite.is_some() && self.output_format != OutputFormat::Csv {
+            return Err(anyhow!("output_format cannot be used with output_sqlite"));
+        }
+        // The other formats have an end, and cannot be continued
+        if self.output_format != OutputFormat::Csv
+            && (self.checkpoint.is_some() || self.append || self.output_template.is_some())
+        {
+            return Err(anyhow!(
+                "checkpoint, append and output_template can only be used with a CSV output"
+            ));
+        }
+        let (_, out_encoding) = self.encodings()?;
+        if out_encoding.is_some() && self.output_format == OutputFormat::Parquet {
+            return Err(anyhow!(
+                "The output encoding cannot be changed with the parquet output format"
+            ));
+        }
+        self.format()?;
+        self.output_format()?;
+
+        Regex::new(&self.arg_regex)?;
+        let steps = self.steps()?;
+        self.finally_command()?;
+        if steps.len() > 1 && (self.coprocess || self.batch_size.is_some()) {
+            return Err(anyhow!(
+                "Only one command can be given with coprocess or batch_size"
+            ));
+        }
+        if self.new_column_name.len() > steps.len() {
+            return Err(anyhow!(
+                "Got {} values of new_column_name for {} commands",
+                self.new_column_name.len(),
+                steps.len()
+            ));
+        }
+        if self.extract()?.is_some() && steps.len() > 1 {
+            return Err(anyhow!(
+                "Only one command can be given with split_output, json_output or capture"
+            ));
+        }
+        Ok(())
+    }
+
+    /// The encodings of the input and of the output, `None` being UTF-8.
+    fn encodings(
+        &self,
+    ) -> Result<(
+        Option<&'static encoding_rs::Encoding>,
+        Option<&'static encoding_rs::Encoding>,
+    )> {
+        let encoding = self
+            .encoding
+            .as_deref()
+            .map(charset::from_label)
+            .transpose()?
+            .flatten();
+        let out_encoding = match self.out_encoding.as_deref() {
+            Some(label) => charset::from_label(label)?,
+            None => encoding,
+        };
+        Ok((encoding, out_encoding))
+    }
+
+    fn format(&self) -> Result<Format> {
+        if self.input_sqlite.is_some() && self.input_format != InputFormat::Csv {
+            return Err(anyhow!("input_format cannot be used with input_sqlite"));
+        }
+        let format = match (&self.input_sqlite, self.input_format) {
+            (Some(_), _) => Format::Sqlite,
+            (None, InputFormat::Csv) => Format::Csv,
+            (None, InputFormat::Jsonl) => Format::Jsonl,
+            (None, InputFormat::Xlsx) => Format::Xlsx,
+            (None, InputFormat::Parquet) => Format::Parquet,
+        };
+        if self.no_headers {
+            let option = match format {
+                Format::Jsonl => Some("the jsonl input format"),
+                Format::Parquet => Some("the parquet input format"),
+                Format::Sqlite => Some("input_sqlite"),
+                _ => None,
+            };
+            if let Some(option) = option {
+                return Err(anyhow!("no_headers cannot be used with {}", option));
+            }
+        }
+        if self.sheet.is_some() && format != Format::Xlsx {
+            return Err(anyhow!("sheet can only be used with the xlsx input format"));
+        }
+        if self.skip_lines > 0 && format != Format::Csv {
+            return Err(anyhow!("skip_lines can only be used with CSV input"));
+        }
+        if self.bytes && format != Format::Csv {
+            return Err(anyhow!("bytes can only be used with CSV input"));
+        }
+        if self.sniff && format != Format::Csv {
+            return Err(anyhow!("sniff can only be used with CSV input"));
+        }
+        Ok(format)
+    }
+
+    fn output_format(&self) -> Result<output::Format> {
+        let format = match self.output_format {
+            OutputFormat::Csv => output::Format::Csv,
+            OutputFormat::Markdown => output::Format::Markdown,
+            OutputFormat::Html => output::Format::Html,
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => output::Format::Parquet,
+            #[cfg(not(feature = "parquet"))]
+            OutputFormat::Parquet => return Err(parquet::unavailable()),
+        };
+        if self.bytes && format != output::Format::Csv {
+            return Err(anyhow!("bytes can only be used with CSV output"));
+        }
+        Ok(format)
+    }
+
+    /// The names of the columns extracted from the result, and how they are extracted.
+    fn extract(&self) -> Result<Option<(Vec<String>, Extract)>> {
+        let result_headers = self.result_headers.clone();
+        Ok(if let Some(separator) = self.split_output {
+            let names = result_headers.unwrap_or_default();
+            let split = Split {
+                separator: char::from(separator),
+                count: names.len(),
+                policy: self.split_policy,
+            };
+            Some((names, Extract::Split(split)))
+        } else if let Some(paths) = &self.json_output {
+            let names = match result_headers {
+                None => paths.clone(),
+                Some(names) if names.len() == paths.len() => names,
+                Some(names) => {
+                    return Err(anyhow!(
+                        "Got {} names in result_headers for {} paths",
+                        names.len(),
+                        paths.len()
+                    ))
+                }
+            };
+            let pointers = paths.iter().map(|path| json_pointer(path)).collect();
+            Some((names, Extract::Json(pointers)))
+        } else if let Some(capture) = &self.capture {
+            let regex = Regex::new(capture)?;
+            let groups = regex
+                .capture_names()
+                .skip(1)
+                .enumerate()
+                .map(|(i, name)| match name {
+                    Some(name) => name.to_string(),
+                    None => format!("Capture{}", i + 1),
+                })
+                .collect::<Vec<_>>();
+            let names = match result_headers {
+                None => groups,
+                Some(names) if names.len() == groups.len() => names,
+                Some(names) => {
+                    return Err(anyhow!(
+                        "Got {} names in result_headers for {} capturing groups",
+                        names.len(),
+                        groups.len()
+                    ))
+                }
+            };
+            Some((names, Extract::Capture(regex)))
+        } else {
+            None
+        })
+    }
+
+    /// The commands, split into their program and their arguments, and the expressions.
+    fn steps(&self) -> Result<Vec<Step<Vec<String>, String>>> {
+        let shell = self.shell()?;
+        self.exec
+            .iter()
+            .map(|step| match step {
+                Step::Command(command) => {
+                    let cmd_and_args = command_line(command, shell.as_deref())?;
+                    if cmd_and_args.is_empty() {
+                        return Err(anyhow!("No command to execute"));
+                    }
+                    Ok(Step::Command(cmd_and_args))
+                }
+                Step::Expression(expression) => Ok(Step::Expression(expression.clone())),
+            })
+            .collect()
+    }
+
+    /// The --finally command, split into its program and its arguments.
+    fn finally_command(&self) -> Result<Option<Vec<String>>> {
+        let shell = self.shell()?;
+        let finally = self
+            .finally
+            .as_deref()
+            .map(|command| command_line(command, shell.as_deref()))
+            .transpose()?;
+        if finally.as_ref().is_some_and(Vec::is_empty) {
+            return Err(anyhow!("No command to execute with finally"));
+        }
+        Ok(finally)
+    }
+
+    /// The program and the arguments of the shell running the commands, if any.
+    fn shell(&self) -> Result<Option<Vec<String>>> {
+        if !self.shell && self.shell_path.is_none() {
+            return Ok(None);
+        }
+        let shell = shell_words::split(self.shell_path.as_deref().unwrap_or("sh"))?;
+        if shell.is_empty() {
+            return Err(anyhow!("Missing shell program"));
+        }
+        Ok(Some(shell))
+    }
+}
+
+/// Splits `command` into its program and its arguments, unless it is run by `shell`.
+fn command_line(command: &str, shell: Option<&[String]>) -> Result<Vec<String>> {
+    Ok(match shell {
+        Some(shell) => {
+            let mut cmd_and_args = shell.to_vec();
+            cmd_and_args.push("-c".to_string());
+            cmd_and_args.push(command.to_string());
+            cmd_and_args
+        }
+        None => shell_words::split(command)?,
+    })
+}
+
+/// Gives the warnings and the errors of a run to its `on_message` callback, if any.
+#[derive(Clone)]
+struct Messages(Option<OnMessage>);
+
+impl Messages {
+    fn send(&self, text: &str) {
+        if let Some(on_message) = &self.0 {
+            on_message(text);
+        }
+    }
+}
+
+/// The output of the rows, which counts the bytes written for the checkpoint.
+type Output<'a> = TableWriter<
+    charset::Encoder<CountingWriter<compression::Encoder<Box<dyn io::Write + Send + 'a>>>>,
+>;
+
+/// Where the results go in the output rows, and what the output header line is.
+struct Layout {
+    columns: Vec<Column>,
+    /// The column of the results in the input, when running again over a previous output
+    populated: Option<usize>,
+    projection: Projection,
+    output_headers: Option<csv::StringRecord>,
+    /// Whether the row number is written instead of the key columns
+    row_number: bool,
+}
+
+/// Expands the invocations of the commands for the records.
+struct Invoker {
+    sources: input::Sources,
+    headers: Option<csv::StringRecord>,
+    field_env: bool,
+    env_templates: Vec<(String, Template)>,
+    cwd_template: Option<Template>,
+    batch_input: BatchInput,
+    grouped: bool,
+    stdin: Option<Stdin>,
+    /// Of the input, for the records written as CSV lines
+    dialect: Dialect,
+    raw: bool,
+}
+
+/// Runs the commands of each batch submitted to the pool.
+struct Worker {
+    commands: Vec<Step<CommandLine, Expression>>,
+    invoker: Invoker,
+    populated: Option<usize>,
+    condition: Option<Expression>,
+    otherwise: String,
+    result_format: ResultFormat,
+    check_status: bool,
+    dry_run: bool,
+    filter: bool,
+    explode: bool,
+    batched: bool,
+    summarize: bool,
+    cache: Option<Cache>,
+    coprocesses: Option<Coprocesses>,
+    exec_options: Arc<exec::Options>,
+    audit_log: Option<AuditLog>,
+    aborted: Arc<AtomicBool>,
+    deadline: Option<Instant>,
+    breaker: Arc<Breaker>,
+}
+
+/// Writes the rows completed by the workers, and keeps the statistics of the run.
+struct RowWriter<'a> {
+    output: Output<'a>,
+    layout: Layout,
+    checkpoint: Option<Checkpoint>,
+    /// Rows done without a gap since the start of the input
+    done_rows: u64,
+    /// Whether a command was not launched, because the deadline was reached
+    stopped: bool,
+    partitioned: Option<PartitionedWriter>,
+    sqlite_writer: Option<SqliteWriter>,
+    /// The output, for the --finally command
+    final_input: Option<delimiter::Writer<Vec<u8>>>,
+    /// Of the --finally record, the fields of the input being empty
+    input_width: Option<usize>,
+    on_error: Option<OnError>,
+    filter: bool,
+    progress: Option<Progress>,
+    summary: Summary,
+    messages: Messages,
+}
+
+/// Runs csv-exec with `stdin` and `stdout` as its standard input and output.
+fn run_with<'a>(
+    mut config: Config,
+    stdin: input::Stdin,
+    stdout: Box<dyn io::Write + Send + 'a>,
+) -> Result<i32> {
+    let start = Instant::now();
+    let messages = Messages(config.on_message.clone());
+    let checkpoint = config.checkpoint.as_deref().map(Checkpoint::new);
+    let resumed = match &checkpoint {
+        Some(checkpoint) if config.resume => checkpoint.read()?,
+        _ => None,
+    };
+    let position = resumed.unwrap_or_default();
+
+    let (writer, offset) = open_output(&config, resumed, stdout)?;
+    let compression = config
+        .output_path
+        .as_deref()
+        .map_or(Compression::None, Compression::from_path);
+    let (encoding, out_encoding) = config.encodings()?;
+    let writer = charset::Encoder::new(
+        CountingWriter::new(compression.encoder(writer)?, offset),
+        out_encoding,
+    );
+
+    if config.sniff {
+        let (sample, complete) = input::sample(
+            &config.input_paths,
+            &stdin,
+            encoding,
+            config.skip_lines,
+            sniff::SAMPLE_SIZE,
+        )?;
+        let dialect = sniff::sniff(&sample, config.quote, config.comment, complete);
+        config.delimiter = char::from(dialect.delimiter).to_string();
+        config.no_headers = !dialect.has_headers;
+    }
+    let (delimiter, long_delimiter) = read_delimiters(&config.delimiter);
+    let dialect = Dialect {
+        delimiter,
+        long_delimiter,
+        quote: config.quote,
+        escape: config.escape,
+        quoting: config.quoting,
+    };
+    let (out_delimiter, long_out_delimiter) = match config.out_delimiter.as_deref() {
+        Some(value) => read_delimiters(value),
+        None => (dialect.delimiter, dialect.long_delimiter.clone()),
+    };
+
+    // A deadline beyond the range of the clock is never reached
+    let deadline = config
+        .max_runtime
+        .and_then(|max_runtime| start.checked_add(max_runtime));
+    let exec_options = Arc::new(exec_options(&config)?);
+
+    let variable_regex = Regex::new(&config.arg_regex)?;
+    // The whole match is the capturing group 0
+    if variable_regex.captures_len() == 1 {
+        messages.send(&format!(
+            "Warning: arg_regex {} has no capturing group, so its matches are removed from the commands",
+            config.arg_regex
+        ));
+    }
+    let steps = config.steps()?;
+    let finally = config.finally_command()?;
+
+    let mut input = open_input(&config, stdin, encoding, &dialect)?;
+    // The total is known when the input is made of uncompressed files, which can be read twice
+    let progress = if config.progress && io::stderr().is_terminal() {
+        let total = match input.files() {
+            Some(paths) => {
+                let mut total = 0;
+                for path in paths {
+                    total += input.count_records(path)?;
+                }
+                Some(total.saturating_sub(position.rows))
+            }
+            None => None,
+        };
+        Some(Progress::new(total))
+    } else {
+        None
+    };
+
+    let mut output = TableWriter::new(
+        config.output_format()?,
+        &writer_builder(&config, out_delimiter),
+        long_out_delimiter.as_deref(),
+        config.bytes,
+        writer,
+    );
+    let result_format = result_format(&config);
+    let result_names = (0..steps.len())
+        .map(|i| match config.new_column_name.get(i) {
+            Some(name) => name.clone(),
+            None if steps.len() == 1 => "Result".to_string(),
+            None => format!("Result{}", i + 1),
+        })
+        .collect::<Vec<_>>();
+    let headers = input.headers().cloned();
+    let layout = layout(&config, headers.as_ref(), &result_names, &result_format)?;
+    let partitioned = config
+        .output_template
+        .as_deref()
+        .map(|template| {
+            PartitionedWriter::new(
+                template,
+                layout.output_headers.clone(),
+                writer_builder(&config, out_delimiter),
+                out_encoding,
+                long_out_delimiter.as_deref(),
+            )
+        })
+        .transpose()?;
+    let sqlite_writer = match (&config.output_sqlite, &config.table) {
+        (Some(path), Some(table)) => Some(SqliteWriter::new(
+            path,
+            table,
+            layout.output_headers.as_ref(),
+        )?),
+        _ => None,
+    };
+
+    let scope = Scope {
+        headers: headers.as_ref(),
+        results: &[],
+        dialect: &dialect,
+        cell: config
+            .map
+            .as_deref()
+            .map(|column| column_index(column, headers.as_ref()))
+            .transpose()?,
+        strict: config.strict_refs || config.check,
+    };
+    let commands = compile_steps(steps, &result_names, &variable_regex, &scope)?;
+    let condition = config
+        .condition
+        .as_deref()
+        .map(|condition| Expression::parse(condition, &variable_regex, &scope))
+        .transpose()?;
+    let invoker = Invoker {
+        sources: input.sources(),
+        headers: headers.clone(),
+        field_env: config.field_env,
+        env_templates: config
+            .env
+            .iter()
+            .map(|(key, value)| {
+                let template = Template::compile(value, &variable_regex, &scope)?;
+                Ok((key.clone(), template))
+            })
+            .collect::<Result<_>>()?,
+        cwd_template: config
+            .cwd
+            .as_deref()
+            .map(|cwd| Template::compile(cwd, &variable_regex, &scope))
+            .transpose()?,
+        batch_input: config.batch_input,
+        grouped: config.group_by.is_some(),
+        stdin: match (config.stdin_record, &config.stdin_field) {
+            (Some(StdinRecord::Json), _) => Some(Stdin::Json),
+            (Some(StdinRecord::Csv), _) => Some(Stdin::Csv),
+            (None, Some(column)) => Some(Stdin::Field(column_index(column, headers.as_ref())?)),
+            (None, None) => None,
+        },
+        dialect: dialect.clone(),
+        raw: config.bytes,
+    };
+    // Likely a $ which was expanded by the shell, or a wrong --arg-regex
+    let records_given = invoker.stdin.is_some()
+        || config.batch_input == BatchInput::Stdin
+        || config.group_by.is_some()
+        || config.coprocess
+        || config.field_env;
+    if !invoker.has_references(&commands) && !records_given {
+        messages.send(
+            "Warning: the commands have no references to the records, so they are the same for each record",
+        );
+    }
+
+    // The commands are substituted for each record, but not run
+    if config.check {
+        return check_records(
+            &mut input,
+            &commands,
+            condition.as_ref(),
+            &invoker,
+            &messages,
+        );
+    }
+    // After the references and the input are checked, with --check
+    if let (Some(headers), 0, None, None) =
+        (&layout.output_headers, offset, &partitioned, &sqlite_writer)
+    {
+        output.write_header(headers)?;
+    }
+    let final_input = match &finally {
+        Some(_) => {
+            let builder = writer_builder(&config, out_delimiter);
+            let long_delimiter = long_out_delimiter.as_deref();
+            let mut writer = delimiter::Writer::new(&builder, long_delimiter, config.bytes, vec![]);
+            if let Some(headers) = &layout.output_headers {
+                writer.write_record(headers)?;
+            }
+            Some(writer)
+        }
+        None => None,
+    };
+
+    // Commands are not launched anymore once the deadline is reached or the run is aborted,
+    // including the queued ones
+    let aborted = Arc::new(AtomicBool::new(false));
+    let breaker = Arc::new(Breaker::new(
+        config.fail_fast,
+        config.abort_after_failures,
+        config.max_failure_rate,
+        config.failure_rate_warm_up,
+    ));
+    let coprocesses = match commands.first() {
+        Some(Step::Command(command)) if config.coprocess => Some(Coprocesses::new(Invocation {
+            command: command.program.clone(),
+            args: command.args.clone(),
+            stdin: None,
+            env: vec![],
+            cwd: None,
+        })),
+        _ => None,
+    };
+    let worker = Worker {
+        commands,
+        invoker,
+        populated: layout.populated,
+        condition,
+        otherwise: config.otherwise.clone().unwrap_or_default(),
+        result_format: result_format.clone(),
+        check_status: config.on_error.is_some(),
+        dry_run: config.dry_run,
+        filter: config.filter,
+        explode: config.explode,
+        batched: config.batch_size.is_some(),
+        summarize: config.summarize,
+        cache: if config.unique {
+            Some(Cache::All(Mutex::new(HashMap::new())))
+        } else if config.memoize {
+            Some(Cache::Successful(Mutex::new(HashMap::new())))
+        } else {
+            None
+        },
+        coprocesses,
+        exec_options: Arc::clone(&exec_options),
+        audit_log: config
+            .audit_log
+            .as_deref()
+            .map(|path| AuditLog::open(path, resumed.is_some()))
+            .transpose()?,
+        aborted: Arc::clone(&aborted),
+        deadline,
+        breaker: Arc::clone(&breaker),
+    };
+    let pool = Pool::new(config.jobs, move |batch| worker.run(batch));
+
+    let mut row_writer = RowWriter {
+        output,
+        layout,
+        checkpoint,
+        done_rows: position.rows,
+        stopped: false,
+        partitioned,
+        sqlite_writer,
+        final_input,
+        input_width: headers.as_ref().map(csv::StringRecord::len),
+        on_error: config.on_error.clone(),
+        filter: config.filter,
+        progress,
+        summary: Summary::default(),
+        messages: messages.clone(),
+    };
+    row_writer.save_checkpoint()?;
+    let group_column = config
+        .group_by
+        .as_deref()
+        .map(|column| column_index(column, headers.as_ref()))
+        .transpose()?;
+    let batches = Batches::new(
+        &mut input,
+        position.rows,
+        group_column,
+        config.batch_size.unwrap_or(1),
+    )?;
+    // Unless unordered, rows are written in input order: completed results wait in a buffer for
+    // the earlier rows. The number of batches submitted but not written yet is bounded, to keep
+    // memory constant.
+    let window = if config.unordered {
+        config.jobs
+    } else {
+        config.jobs * REORDER_WINDOW_PER_JOB
+    };
+    let stop = || {
+        aborted.load(Ordering::SeqCst)
+            || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    };
+    let outcome = execute_all(
+        &pool,
+        batches,
+        &mut row_writer,
+        window,
+        config.unordered,
+        stop,
+    );
+    // The position is saved even after an error, for the rows written until then
+    let saved = row_writer.save_checkpoint();
+    let all_submitted = outcome?;
+    saved?;
+    if let Some(command) = &finally {
+        let output = config.finally_output.as_deref();
+        row_writer.write_finally(command, &exec_options, output, &result_format)?;
+    }
+    let (summary, stopped) = row_writer.finish()?;
+
+    if config.summary {
+        messages.send(&summary.report(start.elapsed()));
+    }
+    if let Some(path) = &config.summary_json {
+        let json = serde_json::to_string_pretty(&summary.to_json(start.elapsed()))?;
+        fs::write(path, json + "\n").context(format!("Failed to write {}", path))?;
+    }
+    if let Some(reason) = breaker.reason() {
+        return Err(anyhow!(reason));
+    }
+    if stopped || !all_submitted {
+        messages.send("Maximum runtime reached");
+        return Ok(EXIT_MAX_RUNTIME);
+    }
+    if config.strict_exit && (summary.failures > 0 || summary.unsuccessful > 0) {
+        return Ok(EXIT_FAILURES);
+    }
+    Ok(0)
+}
+
+/// Opens the output of the rows, and returns it with the number of bytes already in it, in
+/// which case it has its header line already.
+fn open_output<'a>(
+    config: &Config,
+    resumed: Option<Position>,
+    stdout: Box<dyn io::Write + Send + 'a>,
+) -> Result<(Box<dyn io::Write + Send + 'a>, u64)> {
+    let path = match &config.output_path {
+        None => return Ok((stdout, resumed.unwrap_or_default().offset)),
+        Some(path) => path,
+    };
+    // The output written after the last save of the checkpoint is discarded
+    if let Some(position) = resumed {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context(format!("Failed to open {}", path))?;
+        file.set_len(position.offset)?;
+        file.seek(io::SeekFrom::End(0))?;
+        return Ok((Box::new(file), position.offset));
+    }
+    if config.append {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open {}", path))?;
+        let offset = file.metadata()?.len();
+        return Ok((Box::new(file), offset));
+    }
+    if config.no_clobber {
+        return match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(file) => Ok((Box::new(file), 0)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                let resolved = fs::canonicalize(path).unwrap_or_else(|_| path.into());
+                Err(anyhow!(
+                    "Output file {} already exists, and is not overwritten with no_clobber",
+                    resolved.display()
+                ))
+            }
+            Err(error) => Err(error).context(format!("Failed to create {}", path)),
+        };
+    }
+    let file = fs::File::create(path).context(format!("Failed to create {}", path))?;
+    Ok((Box::new(file), 0))
+}
+
+/// Opens the input files, or `stdin` without any, read with `dialect`.
+fn open_input<'r>(
+    config: &Config,
+    stdin: input::Stdin<'r>,
+    encoding: Option<&'static encoding_rs::Encoding>,
+    dialect: &Dialect,
+) -> Result<Input<'r>> {
+    let mut reader_builder = csv::ReaderBuilder::new();
+    reader_builder
+        .has_headers(!config.no_headers)
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .quoting(dialect.quoting)
+        .escape(dialect.escape)
+        .double_quote(dialect.escape.is_none())
+        .comment(config.comment)
+        .flexible(config.flexible != Ragged::Error)
+        .trim(config.trim);
+    if let Some(terminator) = config.terminator {
+        reader_builder.terminator(terminator);
+    }
+    let patterns = match &config.input_sqlite {
+        Some(path) => std::slice::from_ref(path),
+        None => &config.input_paths,
+    };
+    Input::open(
+        reader_builder,
+        patterns,
+        Options {
+            format: config.format()?,
+            sheet: config.sheet.as_deref(),
+            query: config.query.as_deref(),
+            encoding,
+            delimiter: dialect.long_delimiter.as_deref().map(str::as_bytes),
+            quote: Some(dialect.quote).filter(|_| dialect.quoting),
+            escape: dialect.escape,
+            skip_lines: config.skip_lines,
+            ragged: config.flexible,
+            raw: config.bytes,
+            has_headers: !config.no_headers,
+            filename_column: config.filename_column.as_deref(),
+            stdin,
+        },
+    )
+}
+
+fn exec_options(config: &Config) -> Result<exec::Options> {
+    Ok(exec::Options {
+        timeout: config.timeout,
+        retries: config.retries,
+        retry_delay: config.retry_delay,
+        retry_backoff: config.retry_backoff,
+        retry_on_exit_codes: config.retry_on_exit_codes.clone(),
+        rate_limiter: config.rate.map(RateLimiter::new).transpose()?,
+        pass_env: if config.clean_env {
+            Some(config.pass_env.clone())
+        } else {
+            None
+        },
+        max_output_bytes: config.max_output_bytes,
+        truncate_output: config.output_overflow == OutputOverflow::Truncate,
+        raw: config.bytes,
+    })
+}
+
+/// The settings of the CSV writers of the output, whose delimiter is `delimiter`.
+fn writer_builder(config: &Config, delimiter: u8) -> csv::WriterBuilder {
+    let mut builder = csv::WriterBuilder::new();
+    builder
+        .delimiter(delimiter)
+        .quote(config.out_quote.unwrap_or(config.quote))
+        .terminator(
+            config
+                .out_terminator
+                .or(config.terminator)
+                .unwrap_or(csv::Terminator::Any(b'\n')),
+        )
+        .double_quote(config.escape.is_none());
+    if let Some(escape) = config.escape {
+        builder.escape(escape);
+    }
+    builder.quote_style(match config.quote_style {
+        Some(quote_style) => quote_style,
+        None if !config.quoting => csv::QuoteStyle::Never,
+        None => csv::QuoteStyle::Necessary,
+    });
+    builder
+}
+
+fn result_format(config: &Config) -> ResultFormat {
+    ResultFormat {
+        trim: !config.no_trim,
+        lines: match &config.join_lines {
+            Some(separator) => Lines::Join(separator.clone()),
+            None if config.first_line_only => Lines::First,
+            None => Lines::All,
+        },
+        encoding: match config.output_encoding {
+            OutputEncoding::Lossy => Encoding::Lossy,
+            OutputEncoding::Base64 => Encoding::Base64,
+            OutputEncoding::Hex => Encoding::Hex,
+            OutputEncoding::Strict if config.bytes => Encoding::Raw,
+            OutputEncoding::Strict => Encoding::Strict,
+        },
+    }
+}
+
+/// The columns of the results named `result_names`, and where they are placed among the
+/// columns of the input with `headers`.
+fn layout(
+    config: &Config,
+    headers: Option<&csv::StringRecord>,
+    result_names: &[String],
+    result_format: &ResultFormat,
+) -> Result<Layout> {
+    let mut columns = match config.extract()? {
+        Some((names, extract)) => vec![Column::with_names(
+            names,
+            Kind::Fields(extract, result_format.clone()),
+        )],
+        None => result_names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Column::new(name, Kind::Result(i, result_format.clone())))
+            .collect(),
+    };
+    // The exit codes only tell which records are written
+    if config.filter {
+        columns.clear();
+    }
+    if let Some(name) = &config.exit_code_column {
+        columns.push(Column::new(name, Kind::ExitCode));
+    }
+    if let Some(name) = &config.stderr_column {
+        columns.push(Column::new(name, Kind::Stderr));
+    }
+    if let Some(name) = &config.duration_column {
+        columns.push(Column::new(name, Kind::Duration));
+    }
+    if let Some(name) = &config.started_column {
+        columns.push(Column::new(name, Kind::Started));
+    }
+    if let Some(name) = &config.attempts_column {
+        columns.push(Column::new(name, Kind::Attempts));
+    }
+
+    let populated = match (&config.replace_column, headers) {
+        _ if !config.skip_populated => None,
+        (Some(column), _) => Some(column_index(column, headers)?),
+        (None, Some(headers)) => headers.iter().position(|name| name == columns[0].names[0]),
+        (None, None) => None,
+    };
+    let placement = if let Some(index) = populated {
+        Placement::Replace(index)
+    } else if let Some(column) = &config.replace_column {
+        Placement::Replace(column_index(column, headers)?)
+    } else if let Some(position) = config.column_position {
+        Placement::Insert(read_column_position(position, headers)?)
+    } else if let Some(column) = &config.before {
+        Placement::Insert(column_index(column, headers)?)
+    } else if let Some(column) = &config.after {
+        Placement::Insert(column_index(column, headers)? + 1)
+    } else {
+        Placement::Append
+    };
+    let column_indexes = |columns: &[String]| {
+        columns
+            .iter()
+            .map(|column| column_index(column, headers))
+            .collect::<Result<Vec<_>>>()
+    };
+    let selection = match (
+        config.select.as_deref(),
+        config.drop.as_deref(),
+        config.only_result.as_deref(),
+    ) {
+        (_, _, Some([])) => Selection::Only(vec![]),
+        (Some(columns), _, _) | (_, _, Some(columns)) => Selection::Only(column_indexes(columns)?),
+        (None, Some(columns), None) => Selection::Except(column_indexes(columns)?),
+        (None, None, None) => Selection::All,
+    };
+    // Instead of the key columns
+    let row_number = config.only_result.as_ref().is_some_and(Vec::is_empty);
+    let mut projection = Projection {
+        placement,
+        selection,
+        order: None,
+    };
+    let mut output_headers = headers.map(|headers| {
+        let mut names: Vec<String> = columns
+            .iter()
+            .flat_map(|column| column.names.iter().cloned())
+            .collect();
+        // The replaced column keeps its name
+        if let Placement::Replace(index) = placement {
+            names[0] = headers[index].to_string();
+        }
+        projection.apply(headers, names)
+    });
+    if let Some(columns) = &config.order {
+        let order = Order {
+            indexes: columns
+                .iter()
+                .map(|column| column_index(column, output_headers.as_ref()))
+                .collect::<Result<_>>()?,
+            unlisted: !config.drop_unlisted,
+        };
+        output_headers = output_headers.map(|headers| order.apply(headers));
+        projection.order = Some(order);
+    }
+    if let Some(headers) = &mut output_headers {
+        let mut names: Vec<String> = headers.iter().map(String::from).collect();
+        if row_number {
+            names.insert(0, "Row".to_string());
+        }
+        for (old, new) in &config.rename {
+            let index = names
+                .iter()
+                .position(|name| name == old)
+                .ok_or_else(|| anyhow!("Column {} not found", old))?;
+            names[index] = new.clone();
+        }
+        *headers = names.into_iter().collect();
+    }
+    Ok(Layout {
+        columns,
+        populated,
+        projection,
+        output_headers,
+        row_number,
+    })
+}
+
+/// Parses the commands and the expressions in `scope`. A command can reference the results of
+/// the previous ones, named by `result_names`.
+fn compile_steps(
+    steps: Vec<Step<Vec<String>, String>>,
+    result_names: &[String],
+    variable_regex: &Regex,
+    scope: &Scope,
+) -> Result<Vec<Step<CommandLine, Expression>>> {
+    steps
+        .into_iter()
+        .enumerate()
+        .map(|(i, step)| {
+            let scope = Scope {
+                results: &result_names[..i],
+                ..*scope
+            };
+            let mut cmd_and_args = match step {
+                Step::Command(cmd_and_args) => cmd_and_args,
+                Step::Expression(expression) => {
+                    let expression = Expression::parse(&expression, variable_regex, &scope)?;
+                    return Ok(Step::Expression(expression));
+                }
+            };
+            let program = cmd_and_args.remove(0);
+            let templates = cmd_and_args
+                .iter()
+                .map(|arg| Template::compile(arg, variable_regex, &scope))
+                .collect::<Result<_>>()?;
+            Ok(Step::Command(CommandLine {
+                program,
+                args: cmd_and_args,
+                templates,
+            }))
+        })
+        .collect()
+}
+
+impl Invoker {
+    /// Whether the commands, their environment or their working directory depend on the records.
+    fn has_references(&self, commands: &[Step<CommandLine, Expression>]) -> bool {
+        commands.iter().any(|step| match step {
+            Step::Command(command) => command.templates.iter().any(Template::has_references),
+            Step::Expression(expression) => expression.has_references(),
+        }) || self
+            .env_templates
+            .iter()
+            .map(|(_, template)| template)
+            .chain(&self.cwd_template)
+            .any(Template::has_references)
+    }
+
+    /// The invocation of `command` for `batch`, after the earlier commands gave `results`. The
+    /// environment variables and the working directory are expanded from the first record.
+    fn invocation(
+        &self,
+        command: &CommandLine,
+        batch: &[(usize, csv::StringRecord)],
+        results: &[String],
+    ) -> Result<Invocation> {
+        let (index, record) = &batch[0];
+        let file = self.sources.path(*index);
+        let mut env = if self.field_env {
+            field_env(record, self.headers.as_ref())
+        } else {
+            vec![]
+        };
+        for (key, template) in &self.env_templates {
+            env.push((key.clone(), template.expand(record, &file)?));
+        }
+        let cwd = match &self.cwd_template {
+            Some(template) => Some(template.expand(record, &file)?),
+            None => None,
+        };
+        if self.batch_input == BatchInput::Stdin || self.grouped {
+            let mut stdin = vec![];
+            for (_, record) in batch {
+                stdin.extend(self.csv_line(record)?);
+            }
+            // The arguments of a group are the ones of its first record
+            let args = match self.grouped {
+                true => command
+                    .templates
+                    .iter()
+                    .map(|template| template.expand_with_results(record, &file, results))
+                    .collect::<Result<_>>()?,
+                false => command.args.clone(),
+            };
+            return Ok(Invocation {
+                command: command.program.clone(),
+                args,
+                stdin: Some(stdin),
+                env,
+                cwd,
+            });
+        }
+        // The arguments from the first to the last one with a reference are repeated
+        let templates = &command.templates;
+        let first = templates.iter().position(Template::has_references);
+        let last = templates.iter().rposition(Template::has_references);
+        let (repeated, suffix) = match (first, last) {
+            (Some(first), Some(last)) => (first..last + 1, last + 1),
+            _ => (0..0, 0),
+        };
+        let expand = |templates: &[Template], record: &csv::StringRecord, file: &str| {
+            templates
+                .iter()
+                .map(|template| template.expand_with_results(record, file, results))
+                .collect::<Result<Vec<_>>>()
+        };
+        let mut args = expand(&templates[..repeated.start], record, &file)?;
+        for (index, record) in batch {
+            args.extend(expand(
+                &templates[repeated.clone()],
+                record,
+                &self.sources.path(*index),
+            )?);
+        }
+        args.extend(expand(&templates[suffix..], record, &file)?);
+        let stdin = match &self.stdin {
+            None => None,
+            Some(Stdin::Csv) => Some(self.csv_line(record)?),
+            Some(Stdin::Json) => Some(json_line(record, self.headers.as_ref(), self.raw)?),
+            Some(Stdin::Field(index)) => {
+                let field = record.get(*index).unwrap_or_default();
+                Some(match self.raw {
+                    true => raw::unescape(field).into_owned(),
+                    false => field.into(),
+                })
+            }
+        };
+        Ok(Invocation {
+            command: command.program.clone(),
+            args,
+            stdin,
+            env,
+            cwd,
+        })
+    }
+
+    /// The record as written in the input.
+    fn csv_line(&self, record: &csv::StringRecord) -> Result<Vec<u8>> {
+        let dialect = &self.dialect;
+        let long_delimiter = dialect.long_delimiter.as_deref();
+        csv_line(
+            record,
+            dialect.delimiter,
+            long_delimiter,
+            dialect.quote,
+            self.raw,
+        )
+    }
+}
+
+/// Substitutes the commands for each record of `input`, without running them, and reports the
+/// records which cannot be run.
+fn check_records(
+    input: &mut Input,
+    commands: &[Step<CommandLine, Expression>],
+    condition: Option<&Expression>,
+    invoker: &Invoker,
+    messages: &Messages,
+) -> Result<i32> {
+    let mut records = 0;
+    let mut problems = 0;
+    let sources = input.sources();
+    for (index, record) in input.by_ref().enumerate() {
+        let batch = [(index, record?)];
+        records += 1;
+        if let Some(condition) = condition {
+            match condition.holds(&batch[0].1, &sources.path(index)) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(error) => {
+                    messages.send(&format!("Error on record {}: {:#}", index + 1, error));
+                    problems += 1;
+                    continue;
+                }
+            }
+        }
+        for (i, step) in commands.iter().enumerate() {
+            // The expressions are parsed already, and may need the results
+            let command = match step {
+                Step::Command(command) => command,
+                Step::Expression(_) => continue,
+            };
+            let results = vec![String::new(); i];
+            if let Err(error) = invoker.invocation(command, &batch, &results) {
+                messages.send(&format!("Error on record {}: {:#}", index + 1, error));
+                problems += 1;
+                break;
+            }
+        }
+    }
+    if problems > 0 {
+        return Err(anyhow!(
+            "{} of the {} records cannot be run",
+            problems,
+            records
+        ));
+    }
+    messages.send(&format!("{} records checked", records));
+    Ok(0)
+}
+
+/// The batches of records to submit, read from the input.
+struct Batches<'i, 'r> {
+    records: std::iter::Enumerate<&'i mut Input<'r>>,
+    /// The records of a group are all read before, in the order of the first one of each group
+    groups: Option<std::vec::IntoIter<Vec<(usize, csv::StringRecord)>>>,
+    size: usize,
+}
+
+impl<'i, 'r> Batches<'i, 'r> {
+    /// Reads the batches of `size` records of `input`, or its groups by `group_column`, after
+    /// the `skipped` first records which are done already.
+    fn new(
+        input: &'i mut Input<'r>,
+        skipped: u64,
+        group_column: Option<usize>,
+        size: usize,
+    ) -> Result<Batches<'i, 'r>> {
+        let mut records = input.enumerate();
+        for (_, record) in records.by_ref().take(skipped as usize) {
+            record?;
+        }
+        let groups = match group_column {
+            Some(column) => {
+                let mut groups: Vec<Vec<(usize, csv::StringRecord)>> = vec![];
+                let mut keys = HashMap::new();
+                for (index, record) in records.by_ref() {
+                    let record = record?;
+                    let key = record.get(column).unwrap_or_default().to_string();
+                    let group = *keys.entry(key).or_insert_with(|| {
+                        groups.push(vec![]);
+                        groups.len() - 1
+                    });
+                    groups[group].push((index, record));
+                }
+                Some(groups.into_iter())
+            }
+            None => None,
+        };
+        Ok(Batches {
+            records,
+            groups,
+            size,
+        })
+    }
+}
+
+impl Iterator for Batches<'_, '_> {
+    type Item = Result<Vec<(usize, csv::StringRecord)>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch = vec![];
+        if let Some(groups) = &mut self.groups {
+            batch = groups.next().unwrap_or_default();
+        }
+        for (index, record) in self.records.by_ref().take(self.size) {
+            match record {
+                Ok(record) => batch.push((index, record)),
+                Err(error) => return Some(Err(error)),
+            }
+        }
+        if batch.is_empty() {
+            return None;
+        }
+        Some(Ok(batch))
+    }
+}
+
+/// Submits the `batches` to the `pool`, and writes their rows with `row_writer`, with at most
+/// `window` batches pending. Returns whether all of them were submitted before `stop`.
+fn execute_all(
+    pool: &Pool<Vec<(usize, csv::StringRecord)>, Vec<Row>>,
+    batches: impl Iterator<Item = Result<Vec<(usize, csv::StringRecord)>>>,
+    row_writer: &mut RowWriter,
+    window: usize,
+    unordered: bool,
+    stop: impl Fn() -> bool,
+) -> Result<bool> {
+    let mut reorder = Reorder::new();
+    // Returns the number of batches received
+    let mut receive_result = || -> Result<usize> {
+        let (index, rows) = pool
+            .recv()
+            .ok_or_else(|| anyhow!("Execution workers stopped unexpectedly"))?;
+        if unordered {
+            rows.into_iter().try_for_each(|row| row_writer.write(row))?;
+            return Ok(1);
+        }
+        reorder.push(index, rows);
+        let mut received = 0;
+        while let Some((_, rows)) = reorder.pop() {
+            rows.into_iter().try_for_each(|row| row_writer.write(row))?;
+            received += 1;
+        }
+        Ok(received)
+    };
+    let mut submitted = 0;
+    let mut received = 0;
+    let mut all_submitted = true;
+    for batch in batches {
+        let batch = batch?;
+        while submitted - received >= window {
+            received += receive_result()?;
+        }
+        if stop() {
+            all_submitted = false;
+            break;
+        }
+        pool.submit(submitted, batch);
+        submitted += 1;
+    }
+    while received < submitted {
+        received += receive_result()?;
+    }
+    Ok(all_submitted)
+}
+
+impl Worker {
+    /// Runs the commands of `batch`, and returns its rows.
+    fn run(&self, batch: Vec<(usize, csv::StringRecord)>) -> Vec<Row> {
+        let (index, record) = &batch[0];
+        let skipped = |failure, result: Option<&str>| Row {
+            index: *index,
+            record: record.clone(),
+            executions: vec![],
+            failure,
+            skipped: result.map(String::from),
+        };
+        let result = self.populated.and_then(|column| record.get(column));
+        if let Some(result) = result.filter(|result| !result.is_empty()) {
+            return vec![skipped(None, Some(result))];
+        }
+        if let Some(condition) = &self.condition {
+            match condition.holds(record, &self.invoker.sources.path(*index)) {
+                Ok(true) => {}
+                Ok(false) => return vec![skipped(None, Some(self.otherwise.as_str()))],
+                Err(error) => return vec![skipped(Some(Failure::Error(error, None)), None)],
+            }
+        }
+        let (executions, failure) =
+            run_commands(self.commands.len(), self.check_status, |i, done| {
+                self.run_step(i, &batch, done)
+            });
+        // Counted here rather than when the row is written, so that the next queued rows are not
+        // launched meanwhile
+        if !matches!(failure, Some(Failure::Stopped)) {
+            // The rows rejected by --filter are not failures
+            let failed = matches!(failure, Some(Failure::Error(..)))
+                || !self.filter
+                    && executions
+                        .iter()
+                        .any(|execution| !execution.status.success());
+            if batch
+                .iter()
+                .any(|(index, _)| self.breaker.add(*index, failed))
+            {
+                self.aborted.store(true, Ordering::SeqCst);
+            }
+        }
+        if self.invoker.grouped {
+            return group_rows(batch, executions, failure, self.summarize);
+        }
+        if self.batched {
+            return split_batch(batch, executions, failure);
+        }
+        let (index, record) = batch.into_iter().next().unwrap();
+        let row = Row {
+            index,
+            record,
+            executions,
+            failure,
+            skipped: None,
+        };
+        if self.explode {
+            return explode_row(row);
+        }
+        vec![row]
+    }
+
+    /// Runs the step `i` of `batch`, after the `done` executions of the previous ones. Returns
+    /// `None` if the command is not launched.
+    fn run_step(
+        &self,
+        i: usize,
+        batch: &[(usize, csv::StringRecord)],
+        done: &[Execution],
+    ) -> Option<Result<Execution>> {
+        let results = match done
+            .iter()
+            .map(|execution| self.result_format.apply(&execution.stdout))
+            .collect::<Result<Vec<_>>>()
+        {
+            Ok(results) => results,
+            Err(error) => return Some(Err(error)),
+        };
+        let command = match &self.commands[i] {
+            Step::Command(command) => command,
+            Step::Expression(expression) => {
+                let (index, record) = &batch[0];
+                let file = self.invoker.sources.path(*index);
+                let value = expression.evaluate(record, &file, &results);
+                return Some(value.map(Execution::computed));
+            }
+        };
+        let invocation = match self.invoker.invocation(command, batch, &results) {
+            Ok(invocation) => invocation,
+            Err(error) => return Some(Err(error)),
+        };
+        if self.dry_run {
+            return Some(Ok(invocation.dry_run(batch.len())));
+        }
+        execute(&invocation, &self.cache, || {
+            if self.aborted.load(Ordering::SeqCst)
+                || self
+                    .deadline
+                    .is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return None;
+            }
+            let execution = match &self.coprocesses {
+                None => invocation.run(&self.exec_options),
+                Some(coprocesses) => self
+                    .invoker
+                    .csv_line(&batch[0].1)
+                    .and_then(|line| coprocesses.exchange(&line, &self.exec_options)),
+            };
+            if let Some(audit_log) = &self.audit_log {
+                if let Err(error) = audit_log.write(batch[0].0, &invocation, &execution) {
+                    return Some(Err(error));
+                }
+            }
+            Some(execution)
+        })
+    }
+}
+
+impl RowWriter<'_> {
+    /// Saves the position of the rows written until now, with a checkpoint.
+    fn save_checkpoint(&mut self) -> Result<()> {
+        if let Some(checkpoint) = &mut self.checkpoint {
+            self.output.flush()?;
+            checkpoint.save(Position {
+                rows: self.done_rows,
+                offset: self.output.get_ref().get_ref().count(),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, row: Row) -> Result<()> {
+        if self.checkpoint.as_ref().is_some_and(Checkpoint::is_due) {
+            self.save_checkpoint()?;
+        }
+        let columns = &self.layout.columns;
+        let (failed, values) = match row.failure {
+            Some(Failure::Stopped) => {
+                self.stopped = true;
+                return Ok(());
+            }
+            Some(Failure::Error(error, failed)) => (failed, Err(error)),
+            None => match &row.skipped {
+                Some(result) => {
+                    let values = columns
+                        .iter()
+                        .flat_map(|column| column.fallback_values(&[], None, result))
+                        .collect();
+                    (None, Ok(values))
+                }
+                None => (None, column_values(columns, &row.executions)),
+            },
+        };
+        if let Some(progress) = &mut self.progress {
+            progress.inc();
+        }
+        let executions = &row.executions;
+        let filtered_out = self.filter
+            && values.is_ok()
+            && !executions
+                .iter()
+                .all(|execution| execution.status.success());
+        if filtered_out {
+            self.summary.add_filtered(executions);
+        } else {
+            self.summary
+                .add(executions.iter().chain(&failed), values.is_ok());
+        }
+        let projection = &self.layout.projection;
+        let record = match (values, &self.on_error) {
+            (Ok(values), _) => Some(projection.apply(&row.record, values)),
+            (Err(error), None) | (Err(error), Some(OnError::Abort)) => return Err(error),
+            (Err(error), Some(policy)) => {
+                if let Some(progress) = &mut self.progress {
+                    progress.clear();
+                }
+                let text = format!("Error on record {}: {:#}", row.index + 1, error);
+                self.messages.send(&text);
+                let result = match policy {
+                    OnError::Placeholder(placeholder) => Some(placeholder.as_str()),
+                    OnError::Empty => Some(""),
+                    _ => None,
+                };
+                let record = &row.record;
+                result.map(|result| {
+                    let values = columns
+                        .iter()
+                        .flat_map(|column| {
+                            column.fallback_values(executions, failed.as_ref(), result)
+                        })
+                        .collect();
+                    projection.apply(record, values)
+                })
+            }
+        };
+        let record = match record {
+            Some(_) if filtered_out => None,
+            Some(record) if self.layout.row_number => {
+                let number = (row.index + 1).to_string();
+                Some(std::iter::once(number.as_str()).chain(&record).collect())
+            }
+            record => record,
+        };
+        self.input_width.get_or_insert(row.record.len());
+        if let (Some(record), Some(final_input)) = (&record, &mut self.final_input) {
+            final_input.write_record(record)?;
+        }
+        if let Some(record) = record {
+            match (&mut self.partitioned, &mut self.sqlite_writer) {
+                (Some(partitioned), _) => partitioned.write(&record)?,
+                (_, Some(sqlite_writer)) => sqlite_writer.write_record(&record)?,
+                (None, None) => self.output.write_record(&record)?,
+            }
+        }
+        // Only the records done without a gap since the start of the input can be skipped on
+        // resume
+        if row.index as u64 == self.done_rows {
+            self.done_rows += 1;
+        }
+        Ok(())
+    }
+
+    /// Runs the finally `command` with the output written, and writes its result to the file
+    /// at `output`, or else as a last row.
+    fn write_finally(
+        &mut self,
+        command: &[String],
+        options: &exec::Options,
+        output: Option<&str>,
+        format: &ResultFormat,
+    ) -> Result<()> {
+        let final_input = match self.final_input.take() {
+            Some(final_input) => final_input,
+            None => return Ok(()),
+        };
+        let execution = Invocation {
+            command: command[0].clone(),
+            args: command[1..].to_vec(),
+            stdin: Some(final_input.into_inner()?),
+            env: vec![],
+            cwd: None,
+        }
+        .run(options)?;
+        if !execution.status.success() {
+            return Err(anyhow!(
+                "The finally command exited with {}",
+                execution.status
+            ));
+        }
+        if let Some(path) = output {
+            return fs::write(path, &execution.stdout).context(format!("Failed to write {}", path));
+        }
+        let layout = &self.layout;
+        let count = layout.columns.iter().map(|column| column.names.len()).sum();
+        let mut values = vec![String::new(); count];
+        values[0] = format.apply(&execution.stdout)?;
+        let empty = vec![""; self.input_width.unwrap_or_default()];
+        let mut record = layout
+            .projection
+            .apply(&empty.into_iter().collect(), values);
+        if layout.row_number {
+            record = std::iter::once("").chain(&record).collect();
+        }
+        self.output.write_record(&record)
+    }
+
+    /// Ends the outputs, and returns the summary of the rows, and whether a command was not
+    /// launched because the deadline was reached.
+    fn finish(mut self) -> Result<(Summary, bool)> {
+        if let Some(progress) = &mut self.progress {
+            progress.finish();
+        }
+        self.output.finish()?.finish()?.get_mut().finish()?;
+        if let Some(partitioned) = &mut self.partitioned {
+            partitioned.finish()?;
+        }
+        if let Some(sqlite_writer) = &mut self.sqlite_writer {
+            sqlite_writer.finish()?;
+        }
+        Ok((self.summary, self.stopped))
+    }
+}
+
+/// Runs `run`, unless the cache already has an execution of `invocation`.
+fn execute<F>(invocation: &Invocation, cache: &Option<Cache>, run: F) -> Option<Result<Execution>>
+where
+    F: FnOnce() -> Option<Result<Execution>>,
+{
+    let cache = match cache {
+        Some(Cache::Successful(cache)) => cache,
+        Some(Cache::All(cache)) => {
+            let outcome = Arc::clone(cache.lock().unwrap().entry(invocation.clone()).or_default());
+            // The other workers wait for the first one which got the same invocation
+            let outcome = outcome.get_or_init(|| {
+                run().map(|execution| execution.map_err(|error| format!("{:#}", error)))
+            });
+            return outcome
+                .clone()
+                .map(|execution| execution.map_err(|error| anyhow!(error)));
+        }
+        None => return run(),
+    };
+    if let Some(execution) = cache.lock().unwrap().get(invocation) {
+        return Some(Ok(execution.clone()));
+    }
+    let execution = run();
+    if let Some(Ok(execution)) = &execution {
+        if execution.status.success() {
+            cache
+                .lock()
+                .unwrap()
+                .insert(invocation.clone(), execution.clone());
+        }
+    }
+    execution
+}
+
+/// Runs `count` commands in order, until one of them fails. `run` is given the index of the
+/// command and the executions of the previous ones.
+/// A non-zero exit status is a failure only if `check_status` is set.
+fn run_commands<F>(
+    count: usize,
+    check_status: bool,
+    mut run: F,
+) -> (Vec<Execution>, Option<Failure>)
+where
+    F: FnMut(usize, &[Execution]) -> Option<Result<Execution>>,
+{
+    let mut executions = vec![];
+    for i in 0..count {
+        let failure = match run(i, &executions) {
+            None => Failure::Stopped,
+            Some(Err(error)) => Failure::Error(error, None),
+            Some(Ok(execution)) if check_status && !execution.status.success() => Failure::Error(
+                anyhow!("Command exited with {}", execution.status),
+                Some(execution),
+            ),
+            Some(Ok(execution)) => {
+                executions.push(execution);
+                continue;
+            }
+        };
+        return (executions, Some(failure));
+    }
+    (executions, None)
+}
+
+/// Splits the result of the row into one row per line.
+fn explode_row(row: Row) -> Vec<Row> {
+    let execution = match (&row.failure, row.executions.as_slice()) {
+        (None, [execution]) => execution,
+        _ => return vec![row],
+    };
+    let stdout = execution
+        .stdout
+        .strip_suffix(b"\n")
+        .unwrap_or(&execution.stdout);
+    if stdout.is_empty() {
+        return vec![row];
+    }
+    stdout
+        .split(|&byte| byte == b'\n')
+        .map(|line| Row {
+            index: row.index,
+            record: row.record.clone(),
+            executions: vec![Execution {
+                stdout: line.to_vec(),
+                ..execution.clone()
+            }],
+            failure: None,
+            skipped: None,
+        })
+        .collect()
+}
+
+/// Gives the executions of a group to each of its records, or only to the first one if
+/// `summarize`.
+fn group_rows(
+    group: Vec<(usize, csv::StringRecord)>,
+    executions: Vec<Execution>,
+    failure: Option<Failure>,
+    summarize: bool,
+) -> Vec<Row> {
+    let count = if summarize { 1 } else { group.len() };
+    let error = match &failure {
+        Some(Failure::Error(error, _)) => format!("{:#}", error),
+        _ => String::new(),
+    };
+    group
+        .into_iter()
+        .take(count)
+        .map(|(index, record)| Row {
+            index,
+            record,
+            executions: executions.clone(),
+            failure: match &failure {
+                None => None,
+                Some(Failure::Stopped) => Some(Failure::Stopped),
+                Some(Failure::Error(_, failed)) => {
+                    Some(Failure::Error(anyhow!("{}", error), failed.clone()))
+                }
+            },
+            skipped: None,
+        })
+        .collect()
+}
+
+/// Gives each record of the batch its line of the output of the single command.
+fn split_batch(
+    batch: Vec<(usize, csv::StringRecord)>,
+    executions: Vec<Execution>,
+    failure: Option<Failure>,
+) -> Vec<Row> {
+    let (error, failed) = match (failure, executions.into_iter().next()) {
+        (Some(Failure::Stopped), _) | (None, None) => {
+            return batch
+                .into_iter()
+                .map(|(index, record)| Row {
+                    index,
+                    record,
+                    executions: vec![],
+                    failure: Some(Failure::Stopped),
+                    skipped: None,
+                })
+                .collect()
+        }
+        (Some(Failure::Error(error, failed)), _) => (format!("{:#}", error), failed),
+        (None, Some(execution)) => {
+            let stdout = execution
+                .stdout
+                .strip_suffix(b"\n")
+                .unwrap_or(&execution.stdout);
+            let lines = stdout.split(|&byte| byte == b'\n').collect::<Vec<_>>();
+            if lines.len() == batch.len() {
+                return batch
+                    .into_iter()
+                    .zip(lines)
+                    .map(|((index, record), line)| Row {
+                        index,
+                        record,
+                        executions: vec![Execution {
+                            stdout: line.to_vec(),
+                            ..execution.clone()
+                        }],
+                        failure: None,
+                        skipped: None,
+                    })
+                    .collect();
+            }
+            let error = format!(
+                "Expected {} lines of output for the batch, got {}",
+                batch.len(),
+                lines.len()
+            );
+            (error, None)
+        }
+    };
+    batch
+        .into_iter()
+        .map(|(index, record)| Row {
+            index,
+            record,
+            executions: vec![],
+            failure: Some(Failure::Error(anyhow!("{}", error), failed.clone())),
+            skipped: None,
+        })
+        .collect()
+}
+
+/// Environment variables with the fields of `record`, by position and by header name.
+fn field_env(
+    record: &csv::StringRecord,
+    headers: Option<&csv::StringRecord>,
+) -> Vec<(String, String)> {
+    let mut env = vec![];
+    for (i, value) in record.iter().enumerate() {
+        env.push((format!("CSV_{}", i + 1), value.to_string()));
+        if let Some(name) = headers.and_then(|headers| headers.get(i)) {
+            let name = name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_uppercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>();
+            env.push((format!("CSV_{}", name), value.to_string()));
+        }
+    }
+    env
+}
+
+/// Serializes `record` as a CSV line, terminated by a line feed. With `raw`, the bytes escaped by
+/// `raw::escape` are given back.
+fn csv_line(
+    record: &csv::StringRecord,
+    delimiter: u8,
+    long_delimiter: Option<&str>,
+    quote: u8,
+    raw: bool,
+) -> Result<Vec<u8>> {
+    let mut builder = csv::WriterBuilder::new();
+    builder.delimiter(delimiter).quote(quote);
+    let mut writer = delimiter::Writer::new(&builder, long_delimiter, raw, vec![]);
+    writer.write_record(record)?;
+    writer
+        .into_inner()
+        .map_err(|error| anyhow!("Failed to serialize record: {}", error))
+}
+
+/// Serializes `record` as a JSON object on one line, terminated by a line feed. With `raw`, the
+/// bytes escaped by `raw::escape` are given back, even though the line is not valid JSON then.
+fn json_line(
+    record: &csv::StringRecord,
+    headers: Option<&csv::StringRecord>,
+    raw: bool,
+) -> Result<Vec<u8>> {
+    let object = record
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let key = match headers.and_then(|headers| headers.get(i)) {
+                Some(name) => name.to_string(),
+                None => (i + 1).to_string(),
+            };
+            (key, serde_json::Value::from(value))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let line = serde_json::to_string(&object)?;
+    let mut line = match raw {
+        true => raw::unescape(&line).into_owned(),
+        false => line.into_bytes(),
+    };
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Values of the new columns, once all the commands completed.
+fn column_values(columns: &[Column], executions: &[Execution]) -> Result<Vec<String>> {
+    let mut values = vec![];
+    for column in columns {
+        values.extend(column.values(executions)?);
+    }
+    Ok(values)
+}
+
+/// Returns the index of a position of the new columns, beginning at 1. With headers, the
+/// position is at most after the last column.
+fn read_column_position(position: usize, headers: Option<&csv::StringRecord>) -> Result<usize> {
+    let max = headers.map_or(usize::MAX, |headers| headers.len() + 1);
+    match position {
+        position if position >= 1 && position <= max => Ok(position - 1),
+        _ => Err(anyhow!("Value {} must be a column position", position)),
+    }
+}
+
+/// The delimiters of several characters are replaced by a single byte for the CSV reader and
+/// writer.
+fn read_delimiters(value: &str) -> (u8, Option<String>) {
+    match value.as_bytes() {
+        [delimiter] => (*delimiter, None),
+        _ => (delimiter::SEPARATOR, Some(value.to_string())),
+    }
+}
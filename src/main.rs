@@ -1,33 +1,76 @@
-use anyhow::{anyhow, Context, Result};
-use clap::{App, Arg};
-use regex::{Captures, Regex};
-use std::{fs, io, process};
+use anyhow::{anyhow, Result};
+use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, SubCommand};
+use csv_exec::{
+    BatchInput, CsvExec, InputFormat, OnError, OutputEncoding, OutputFormat, OutputOverflow,
+    QuoteStyle, Ragged, SplitPolicy, StdinRecord, Terminator, Trim, DEFAULT_ARG_REGEX,
+};
+use std::env;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal};
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 
 include!(concat!(env!("OUT_DIR"), "/buildinfo.rs"));
 
-struct Config {
-    pub input_path: Option<String>,
-    pub output_path: Option<String>,
-    pub exec: String,
-    pub no_headers: bool,
-    pub delimiter: String,
-    pub out_delimiter: Option<String>,
-    pub quote: String,
-    pub arg_regex: String,
-    pub new_column_name: String,
-}
-
 fn main() -> Result<()> {
-    let matches = App::new("csv-exec")
+    let app = App::new("csv-exec")
         .version(BUILDINFO_VERSION)
         .author("niladic <git@nil.choron.cc>")
         .about("Execute a command on each record of a CSV.")
+        .settings(&[
+            AppSettings::SubcommandsNegateReqs,
+            AppSettings::DisableHelpSubcommand,
+            AppSettings::VersionlessSubcommands,
+        ])
         .arg(
             Arg::with_name("input")
                 .short("i")
                 .long("input")
                 .value_name("FILE")
-                .help("Input CSV file [stdin by default]")
+                .help("Input CSV file, glob pattern (e.g. 'data/*.csv') or http(s) URL, which can be given several times. The columns of the next files are matched by name with the ones of the first file. Files ending with .gz or .zst are decompressed. [stdin by default]")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("input-format")
+                .long("input-format")
+                .value_name("FORMAT")
+                .help("Format of the input: csv, jsonl for a JSON object per line, whose keys are the columns (the ones of the first object), xlsx for a sheet of an Excel workbook (with the xlsx feature), or parquet (with the parquet feature)")
+                .possible_values(&["csv", "jsonl", "xlsx", "parquet"])
+                .default_value("csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sheet")
+                .long("sheet")
+                .value_name("NAME")
+                .help("Sheet of the xlsx input to read [the first one by default]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("input-sqlite")
+                .long("input-sqlite")
+                .value_name("FILE")
+                .help("Read the input from the result of --query on this SQLite database (with the sqlite feature)")
+                .conflicts_with("input")
+                .requires("query")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("query")
+                .long("query")
+                .value_name("SQL")
+                .help("Query of the --input-sqlite database whose result is the input, e.g. 'SELECT id, url FROM pages'")
+                .requires("input-sqlite")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("add-filename-column")
+                .long("add-filename-column")
+                .value_name("STRING")
+                .help("Add a column with this name to the input, which contains the path of the file of each record")
                 .takes_value(true),
         )
         .arg(
@@ -35,17 +78,87 @@ fn main() -> Result<()> {
                 .short("o")
                 .long("output")
                 .value_name("FILE")
-                .help("Output CSV [stdout by default]")
+                .help("Output CSV file, compressed if it ends with .gz or .zst [stdout by default]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-template")
+                .long("output-template")
+                .value_name("TEMPLATE")
+                .help("Write each row to the file given by TEMPLATE, where {COLUMN} is replaced by the field of COLUMN (name or position) in the output, e.g. 'out/{Region}.csv'")
+                .conflicts_with_all(&["output", "checkpoint"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-sqlite")
+                .long("output-sqlite")
+                .value_name("FILE")
+                .help("Insert the output rows into the --table of this SQLite database, which is created with text columns if needed, instead of writing them (with the sqlite feature)")
+                .conflicts_with_all(&["output", "output-template", "checkpoint", "append"])
+                .requires("table")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("table")
+                .long("table")
+                .value_name("NAME")
+                .help("Table of the --output-sqlite database where the rows are inserted")
+                .requires("output-sqlite")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-format")
+                .long("output-format")
+                .value_name("FORMAT")
+                .help("Format of the output: csv, a markdown or html table, or parquet with string columns (with the parquet feature)")
+                .possible_values(&["csv", "markdown", "html", "parquet"])
+                .default_value("csv")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("append")
+                .long("append")
+                .help("Append to the output file, without the header line if the file is not empty")
+                .requires("output")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-clobber")
+                .long("no-clobber")
+                .help("Fail instead of overwriting an existing output file")
+                .requires("output")
+                .conflicts_with("append")
+                .takes_value(false),
+        )
         .arg(
             Arg::with_name("exec")
                 .index(1)
                 .value_name("COMMAND")
-                .required(true)
+                .required_unless_one(&["more-exec", "expr"])
                 .help("The command to execute")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("more-exec")
+                .short("e")
+                .long("exec")
+                .value_name("COMMAND")
+                .help("Another command to execute, after the previous ones, with its own result column")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("expr")
+                .long("expr")
+                .visible_alias("exec-builtin")
+                .value_name("EXPR")
+                .help("An expression computing another result column, in the order of the commands, without running a process, e.g. 'concat($2, \"/\", $1)'. It has the references of the commands, the operators + - * / % == != < <= > >= && || ! and c ? a : b, =~ and !~ matching a quoted regex, and the functions concat, upper, lower, trim, len, substr, replace, contains, starts_with, ends_with, if, coalesce, round, floor, ceil, abs, min, max, and sha256, md5, base64, urlencode")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["coprocess", "batch-size"])
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("no-headers")
                 .short("n")
@@ -59,7 +172,7 @@ fn main() -> Result<()> {
                 .long("delimiter")
                 .value_name("CHAR")
                 .default_value(",")
-                .help("CSV delimiter (\\t for tabs)")
+                .help("CSV delimiter (\\t for tabs), which can have several characters, e.g. '~|~'")
                 .takes_value(true),
         )
         .arg(
@@ -77,171 +190,1336 @@ fn main() -> Result<()> {
                 .help("CSV quote")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("escape")
+                .long("escape")
+                .value_name("CHAR")
+                .help("Escape the quotes in the quoted fields with this character, such as a backslash, instead of doubling them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("comment")
+                .long("comment")
+                .value_name("CHAR")
+                .help("Skip the input lines starting with this character, such as #")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip-lines")
+                .long("skip-lines")
+                .value_name("N")
+                .default_value("0")
+                .help("Skip the first N lines of each input file, before its header line")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("flexible")
+                .long("flexible")
+                .value_name("POLICY")
+                .default_value("error")
+                .possible_values(&["error", "skip", "pad", "truncate"])
+                .help("When a record has not the number of fields of the header line: fail, skip it, add empty fields to it when it is shorter, or remove its extra fields when it is longer")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trim")
+                .long("trim")
+                .value_name("WHAT")
+                .default_value("none")
+                .possible_values(&["none", "headers", "fields", "all"])
+                .help("Remove the whitespace around the names of the header line, the fields of the records, or both")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bytes")
+                .long("bytes")
+                .help("Read the CSV fields as bytes, which may not be UTF-8, and pass them as is to the commands and to the output")
+                .conflicts_with_all(&["encoding", "out-encoding", "output-template", "output-sqlite"]),
+        )
+        .arg(
+            Arg::with_name("out-quote")
+                .long("out-quote")
+                .value_name("CHAR")
+                .help("Output CSV quote, if different from quote")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("terminator")
+                .long("terminator")
+                .value_name("CHAR")
+                .help("CSV line terminator: \\n, \\r\\n or another character [default: any line break in the input, \\n in the output]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("out-terminator")
+                .long("out-terminator")
+                .value_name("CHAR")
+                .help("Output CSV line terminator, if different from terminator")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("crlf")
+                .long("crlf")
+                .help("End the output lines with \\r\\n, i.e. --out-terminator '\\r\\n'")
+                .conflicts_with("out-terminator"),
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .long("encoding")
+                .value_name("LABEL")
+                .help("Character encoding of the input and output text, e.g. latin1, windows-1252 or utf-16le [default: utf-8]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("out-encoding")
+                .long("out-encoding")
+                .value_name("LABEL")
+                .help("Character encoding of the output, if different from encoding")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quote-style")
+                .long("quote-style")
+                .value_name("STYLE")
+                .help("Quote the output fields: only when necessary, always, never, or non-numeric ones [default: necessary, or never with --tsv]")
+                .possible_values(&["necessary", "always", "never", "non-numeric"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tsv")
+                .long("tsv")
+                .help("Read and write tab-separated values, without quotes: the fields cannot contain tabs nor line breaks")
+                .conflicts_with_all(&["delimiter", "quote", "escape", "psv", "ssv"]),
+        )
+        .arg(
+            Arg::with_name("psv")
+                .long("psv")
+                .help("Read and write pipe-separated values, i.e. --delimiter '|'")
+                .conflicts_with_all(&["delimiter", "ssv"]),
+        )
+        .arg(
+            Arg::with_name("ssv")
+                .long("ssv")
+                .help("Read and write semicolon-separated values, as written by spreadsheets in European locales, i.e. --delimiter ';'")
+                .conflicts_with("delimiter"),
+        )
+        .arg(
+            Arg::with_name("sniff")
+                .long("sniff")
+                .help("Guess the delimiter (comma, semicolon, tab or pipe) and whether there is a header line from the beginning of the input")
+                .conflicts_with_all(&["delimiter", "tsv", "psv", "ssv", "no-headers"]),
+        )
         .arg(
             Arg::with_name("arg-regex")
                 .long("arg-regex")
                 .value_name("REGEX")
-                .default_value(r"\$([0-9]+)")
+                .default_value(DEFAULT_ARG_REGEX)
                 .help(
                     "
-Regex used to parse the column position in the command args.
-Position begins at 1.
-Only the first capturing group is used.
+Regex used to parse the column references in the command args.
+A reference is either a position (beginning at 1, or at -1 for the last field)
+or a header name, 0 is the whole record and # its number of fields.
+Only the first capturing group that matched is used.
 Syntax: https://docs.rs/regex/1.3.4/regex/index.html#syntax
 "
                     .trim_start(),
                 )
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("strict-refs")
+                .long("strict-refs")
+                .help("Fail on a reference which is not a header name or is after the last column, instead of leaving it untouched, and on a missing value without a default, instead of replacing it with an empty string"),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .help("Check the input and the references, as with --strict-refs, and substitute the commands for each record without running them. The problems are written to stderr, and nothing to stdout")
+                .conflicts_with_all(&["output", "output-template", "output-sqlite", "checkpoint", "audit-log", "dry-run", "coprocess", "summary-json"]),
+        )
         .arg(
             Arg::with_name("new-column-name")
                 .long("new-column-name")
                 .value_name("STRING")
-                .default_value("Result")
-                .help("Name of the new column which contains the results")
-                .takes_value(true),
-        )
-        .get_matches();
-
-    let config = Config {
-        input_path: matches.value_of("input").map(String::from),
-        output_path: matches.value_of("output").map(String::from),
-        // Note: required using clap
-        exec: matches
-            .value_of("exec")
-            .map(String::from)
-            .unwrap_or_else(String::new),
-        no_headers: matches.is_present("no-headers"),
-        delimiter: matches
-            .value_of("delimiter")
-            .map(String::from)
-            .unwrap_or_else(String::new),
-        out_delimiter: matches.value_of("out-delimiter").map(String::from),
-        quote: matches
-            .value_of("quote")
-            .map(String::from)
-            .unwrap_or_else(String::new),
-        arg_regex: matches
-            .value_of("arg-regex")
-            .map(String::from)
-            .unwrap_or_else(String::new),
-        new_column_name: matches
-            .value_of("new-column-name")
-            .map(String::from)
-            .unwrap_or_else(String::new),
+                .help(
+                    "
+Name of the new column which contains the results [default: Result].
+With several commands, it is given once per command, in order, the
+default names being Result1, Result2...
+"
+                    .trim(),
+                )
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("replace-column")
+                .long("replace-column")
+                .value_name("COLUMN")
+                .help("Write the results in this column (name or position), instead of a new column")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("map")
+                .long("map")
+                .value_name("COLUMN")
+                .help("Replace the fields of this column (name or position) by the results, as --replace-column, the commands referencing them as $CELL")
+                .conflicts_with_all(&["replace-column", "extract", "column-position", "before", "after", "only-result", "filter"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip-populated")
+                .long("skip-populated")
+                .help("Do not run the command for the records which already have a non-empty result, in the column named like the new column or in --replace-column. The results are written in this column.")
+                .conflicts_with_all(&["extract", "more-exec", "batch-size"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("where")
+                .long("where")
+                .value_name("EXPR")
+                .help("Run the commands only for the records for which the expression EXPR, as the ones of --expr, is true (not empty, 0 or false), e.g. '$Status == \"active\" && $Size > 10' or '$Url =~ \"^https:\"'. The other records are written with an empty result, or --else")
+                .conflicts_with_all(&["batch-size", "group-by", "filter"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("else")
+                .long("else")
+                .value_name("VALUE")
+                .help("Result of the records which do not match --where")
+                .requires("where")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-trim")
+                .long("no-trim")
+                .help("Keep the leading and trailing whitespace of the results, except the final line break")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("first-line-only")
+                .long("first-line-only")
+                .help("Keep only the first line of the output of the command")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("join-lines")
+                .long("join-lines")
+                .value_name("STRING")
+                .help("Join the lines of the output of the command with STRING (\\t for tabs)")
+                .conflicts_with("first-line-only")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("explode")
+                .long("explode")
+                .help("Write the record once for each line of the output of the command, with this line as the result. A record whose command writes nothing is written once, with an empty result")
+                .conflicts_with_all(&["more-exec", "extract", "join-lines", "first-line-only", "batch-size", "filter"]),
+        )
+        .arg(
+            Arg::with_name("group-by")
+                .long("group-by")
+                .value_name("COLUMN")
+                .help("Run the commands once for each group of records with the same value in this column (name or position), with the arguments of the first record of the group and its records as CSV lines on their input. The result is written to each record of the group, group by group, in the order of their first records")
+                .conflicts_with_all(&["batch-size", "stdin-record", "stdin-field", "coprocess", "explode", "checkpoint", "skip-populated"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("summarize")
+                .long("summarize")
+                .help("Write only the first record of each group, with the result")
+                .requires("group-by"),
+        )
+        .arg(
+            Arg::with_name("finally")
+                .long("finally")
+                .value_name("COMMAND")
+                .help("Run this command once after the other ones, with the whole output, header line included, as CSV on its input. Its result is written as a last record, in the result column, unless --finally-output is given")
+                .conflicts_with_all(&["filter", "output-template", "output-sqlite", "checkpoint"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("finally-output")
+                .long("finally-output")
+                .value_name("FILE")
+                .help("Write the output of the --finally command to this file")
+                .requires("finally")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-encoding")
+                .long("output-encoding")
+                .value_name("ENCODING")
+                .possible_values(&["strict", "lossy", "base64", "hex"])
+                .default_value("strict")
+                .help(
+                    "
+How the output of the command is read: strict fails if it is not valid
+UTF-8, lossy replaces the invalid sequences, base64 and hex encode the whole
+output as is
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("split-output")
+                .long("split-output")
+                .value_name("CHAR")
+                .help("Split the result into the columns of --result-headers, at each CHAR (\\t for tabs)")
+                .requires("result-headers")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json-output")
+                .long("json-output")
+                .value_name("PATHS")
+                .help(
+                    "
+Parse the result as JSON, and add a column for each of these comma-separated
+paths: either keys and array indexes separated by dots (items.0.id), or JSON
+pointers (/items/0/id). The columns are named after the paths, unless
+--result-headers is given.
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("capture")
+                .long("capture")
+                .value_name("REGEX")
+                .help(
+                    "
+Add a column for each capturing group of REGEX, filled from its first match
+in the result. The columns are named after the named groups, or Capture1,
+Capture2... unless --result-headers is given.
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .group(
+            ArgGroup::with_name("extract")
+                .args(&["split-output", "json-output", "capture"])
+                .conflicts_with("replace-column"),
+        )
+        .arg(
+            Arg::with_name("result-headers")
+                .long("result-headers")
+                .value_name("NAMES")
+                .help("Comma-separated names of the columns extracted from the result")
+                .requires("extract")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("split-policy")
+                .long("split-policy")
+                .value_name("POLICY")
+                .possible_values(&["strict", "pad", "truncate", "pad-truncate"])
+                .default_value("strict")
+                .help(
+                    "
+What to do when a split result does not have one field per column: strict
+fails, pad fills the missing fields with empty values, truncate drops the
+extra fields, pad-truncate does both
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("column-position")
+                .long("column-position")
+                .value_name("N")
+                .help("Insert the new columns at this position (beginning at 1), instead of the end")
+                .conflicts_with_all(&["replace-column", "before", "after"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("before")
+                .long("before")
+                .value_name("COLUMN")
+                .help("Insert the new columns before this column (name or position)")
+                .conflicts_with_all(&["replace-column", "after"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("after")
+                .long("after")
+                .value_name("COLUMN")
+                .help("Insert the new columns after this column (name or position)")
+                .conflicts_with("replace-column")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("select")
+                .long("select")
+                .value_name("COLUMNS")
+                .help("Write only these comma-separated columns of the input (names or positions), in their order in the input, and the new columns")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("drop")
+                .long("drop")
+                .value_name("COLUMNS")
+                .help("Do not write these comma-separated columns of the input (names or positions)")
+                .conflicts_with("select")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("order")
+                .long("order")
+                .value_name("COLUMNS")
+                .help("Write these comma-separated columns of the output first, in this order: names, including the new ones, or positions in the output. The other columns follow them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("drop-unlisted")
+                .long("drop-unlisted")
+                .help("Do not write the columns which are not given to --order")
+                .requires("order"),
+        )
+        .arg(
+            Arg::with_name("only-result")
+                .long("only-result")
+                .value_name("KEYS")
+                .help("Write only these comma-separated columns of the input and the new columns, given as --only-result=Id,Name, or a Row column with the number of the record, beginning at 1, without any")
+                .conflicts_with_all(&["select", "drop", "replace-column", "column-position", "before", "after"])
+                .min_values(0)
+                .max_values(1)
+                .require_equals(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rename")
+                .long("rename")
+                .value_name("OLD=NEW")
+                .help("Rename a column in the header line of the output, including the new ones")
+                .conflicts_with("no-headers")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exit-code-column")
+                .long("exit-code-column")
+                .value_name("STRING")
+                .help("Add a column with this name, which contains the exit code of the command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stderr-column")
+                .long("stderr-column")
+                .value_name("STRING")
+                .help("Add a column with this name, which contains the error output of the command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("duration-column")
+                .long("duration-column")
+                .value_name("STRING")
+                .help("Add a column with this name, which contains the duration of the command in milliseconds")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("started-column")
+                .long("started-column")
+                .value_name("STRING")
+                .help("Add a column with this name, which contains the ISO-8601 start time of the command")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attempts-column")
+                .long("attempts-column")
+                .value_name("STRING")
+                .help("Add a column with this name, which contains the number of times the command was run")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .value_name("N")
+                .default_value("1")
+                .help("Number of commands to run in parallel")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("unordered")
+                .long("unordered")
+                .help("Write the rows as soon as their command completes, instead of in input order")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("shell")
+                .long("shell")
+                .help("Run the command with sh -c, after the substitution of the column values")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("shell-path")
+                .long("shell-path")
+                .value_name("PROGRAM")
+                .help("Shell used by --shell, with its arguments (e.g. 'bash -O extglob'). Implies --shell.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECS")
+                .help("Kill the command if it runs longer than this duration")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("coprocess")
+                .long("coprocess")
+                .help(
+                    "
+Run the command once per job, without substitution. Each record is written
+as a CSV line to its input, and the command answers with one line per record.
+Its input is closed at the end, and it is killed if it has not exited 2s later.
+"
+                    .trim(),
+                )
+                .conflicts_with_all(&["memoize", "timeout", "retries"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("batch-size")
+                .long("batch-size")
+                .value_name("N")
+                .help(
+                    "
+Run the command once for N records. It must output one line per record,
+in the same order. With --batch-input args, the arguments from the first
+to the last one with a column reference are repeated for each record.
+"
+                    .trim(),
+                )
+                .conflicts_with("coprocess")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("batch-input")
+                .long("batch-input")
+                .value_name("MODE")
+                .possible_values(&["args", "stdin"])
+                .default_value("args")
+                .help(
+                    "
+How the records of a batch are given to the command: args repeats the
+arguments for each record, stdin writes each record as a CSV line to its input
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdin-record")
+                .long("stdin-record")
+                .value_name("FORMAT")
+                .possible_values(&["csv", "json"])
+                .help(
+                    "
+Write the record to the input of its command, as a CSV line, or as a JSON
+object keyed by header names (or positions with --no-headers)
+"
+                    .trim(),
+                )
+                .conflicts_with_all(&["coprocess", "batch-size", "stdin-field"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("stdin-field")
+                .long("stdin-field")
+                .value_name("COLUMN")
+                .help("Write the value of this column (name or position) to the input of the command")
+                .conflicts_with_all(&["coprocess", "batch-size"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("field-env")
+                .long("field-env")
+                .help(
+                    "
+Give the fields to the command as environment variables: CSV_1, CSV_2...
+and CSV_<HEADER>, the header being uppercased with non-alphanumeric
+characters replaced by _
+"
+                    .trim(),
+                )
+                .conflicts_with_all(&["coprocess", "batch-size"])
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .value_name("KEY=VALUE")
+                .help("Set an environment variable for the command. The value is substituted like the arguments.")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clean-env")
+                .long("clean-env")
+                .help("Do not give the environment variables of csv-exec to the command, except the ones of --pass-env")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("pass-env")
+                .long("pass-env")
+                .value_name("VAR")
+                .help("Environment variable given to the command with --clean-env")
+                .requires("clean-env")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cwd")
+                .long("cwd")
+                .value_name("TEMPLATE")
+                .help("Working directory of the command, with the same substitutions as the arguments")
+                .conflicts_with("coprocess")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-output-bytes")
+                .long("max-output-bytes")
+                .value_name("N")
+                .help("Keep at most N bytes of the output of each command, and as many of its error output")
+                .conflicts_with("coprocess")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-overflow")
+                .long("output-overflow")
+                .value_name("POLICY")
+                .possible_values(&["truncate", "error"])
+                .default_value("truncate")
+                .help("What to do with a command whose output is larger than --max-output-bytes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-progress")
+                .long("no-progress")
+                .help("Do not show the progress, which is shown when the error output is a terminal")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("summary")
+                .long("summary")
+                .help("Write a summary of the run to the error output, which is done anyway on a terminal")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("summary-json")
+                .long("summary-json")
+                .value_name("FILE")
+                .help("Write the summary of the run to FILE, as JSON")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("audit-log")
+                .long("audit-log")
+                .value_name("FILE")
+                .help(
+                    "
+Write a JSON line to FILE for each command launched: its record, arguments,
+exit code, duration, and the beginning of its output and error output
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .help("Write only the records for which the commands exit with 0, as they are, without any new column")
+                .conflicts_with_all(&[
+                    "new-column-name", "replace-column", "extract", "exit-code-column",
+                    "stderr-column", "duration-column", "started-column", "attempts-column",
+                    "only-result", "skip-populated", "where", "on-error", "strict-exit", "fail-fast",
+                    "abort-after-failures", "max-failure-rate",
+                ]),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Do not run the commands, and write their command line, once substituted, as their result")
+                .conflicts_with("coprocess")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("memoize")
+                .long("memoize")
+                .help("Run identical commands only once, and reuse the output of the first successful run")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("unique")
+                .long("unique")
+                .help("Run identical commands exactly once, even at the same time in several jobs, and reuse the outcome of this run whether it succeeded or not")
+                .conflicts_with_all(&["memoize", "coprocess"]),
+        )
+        .arg(
+            Arg::with_name("on-error")
+                .long("on-error")
+                .value_name("POLICY")
+                .help(
+                    "
+What to do when a command cannot be run, exits with a non-zero status,
+or its output is not valid UTF-8: abort, skip-row, empty, or placeholder=STRING.
+Without this option, the exit status is ignored and the other errors abort.
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strict-exit")
+                .long("strict-exit")
+                .help("Exit with status 123 if a command failed or exited with a non-zero status, once all the rows are written")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("fail-fast")
+                .long("fail-fast")
+                .help("Stop at the first command which fails or exits with a non-zero status, once the completed rows are written")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("abort-after-failures")
+                .long("abort-after-failures")
+                .value_name("N")
+                .help("Stop after N consecutive rows whose command failed or exited with a non-zero status, once the completed rows are written")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-failure-rate")
+                .long("max-failure-rate")
+                .value_name("FRACTION")
+                .help("Stop when more than this fraction (e.g. 0.05) of the rows written so far failed or exited with a non-zero status, once --failure-rate-warm-up rows are written")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("failure-rate-warm-up")
+                .long("failure-rate-warm-up")
+                .value_name("N")
+                .help("Number of rows to write before --max-failure-rate is checked")
+                .default_value("100")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .help("Save the position of the run in FILE every second, to continue it with --resume")
+                .requires("output")
+                .conflicts_with("unordered")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Continue the run saved in the --checkpoint file: the records already done are skipped, and the rest is appended to the output. Without a checkpoint yet, the run starts from the beginning.")
+                .requires("checkpoint")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("retries")
+                .long("retries")
+                .value_name("N")
+                .default_value("0")
+                .help("Run the command again when it fails, up to N times")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-delay")
+                .long("retry-delay")
+                .value_name("SECS")
+                .default_value("1")
+                .help("Delay before the first retry, randomly reduced by up to a half")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-backoff")
+                .long("retry-backoff")
+                .value_name("FACTOR")
+                .default_value("2")
+                .help("Factor applied to the delay after each retry")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retry-on-exit-codes")
+                .long("retry-on-exit-codes")
+                .value_name("CODES")
+                .help("Retry only the commands exiting with one of these comma-separated codes, besides the ones which timed out or failed to start")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate")
+                .long("rate")
+                .value_name("N/UNIT")
+                .help("Maximum rate of command launches, whatever the number of jobs (e.g. 10/s, 100/m)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-runtime")
+                .long("max-runtime")
+                .value_name("DURATION")
+                .help(
+                    "
+Stop launching commands after this duration (e.g. 90, 90s, 15m, 2h),
+write the completed rows, and exit with status 124
+"
+                    .trim(),
+                )
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("map")
+                .about("Replace each field of a column by the result of the commands, which reference it as $CELL, e.g. csv-exec map --column Url -e 'curl -s $CELL'. Same as --map COLUMN.")
+                .setting(AppSettings::AllowLeadingHyphen)
+                .arg(
+                    Arg::with_name("column")
+                        .long("column")
+                        .value_name("COLUMN")
+                        .help("The column (name or position) whose fields are replaced")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("options")
+                        .value_name("OPTIONS")
+                        .help("The other options and commands, as the ones of csv-exec")
+                        .multiple(true),
+                ),
+        );
+    let matches = app.clone().get_matches();
+    let matches = match matches.subcommand_matches("map") {
+        Some(map) => app.get_matches_from(map_args(map)),
+        None => matches,
     };
 
-    run(config)
-}
+    let mut builder = CsvExec::builder()
+        .input_format(match matches.value_of("input-format") {
+            Some("jsonl") => InputFormat::Jsonl,
+            Some("xlsx") => InputFormat::Xlsx,
+            Some("parquet") => InputFormat::Parquet,
+            _ => InputFormat::Csv,
+        })
+        .output_format(match matches.value_of("output-format") {
+            Some("markdown") => OutputFormat::Markdown,
+            Some("html") => OutputFormat::Html,
+            Some("parquet") => OutputFormat::Parquet,
+            _ => OutputFormat::Csv,
+        })
+        .append(matches.is_present("append"))
+        .no_clobber(matches.is_present("no-clobber"))
+        .no_headers(matches.is_present("no-headers"))
+        .sniff(matches.is_present("sniff"))
+        .quote(read_char(matches.value_of("quote").unwrap_or_default())?)
+        .quoting(!matches.is_present("tsv"))
+        .bytes(matches.is_present("bytes"))
+        .skip_lines(read_number(
+            matches.value_of("skip-lines").unwrap_or_default(),
+            "a number of lines",
+        )?)
+        .flexible(match matches.value_of("flexible") {
+            Some("skip") => Ragged::Skip,
+            Some("pad") => Ragged::Pad,
+            Some("truncate") => Ragged::Truncate,
+            _ => Ragged::Error,
+        })
+        .trim(match matches.value_of("trim") {
+            Some("headers") => Trim::Headers,
+            Some("fields") => Trim::Fields,
+            Some("all") => Trim::All,
+            _ => Trim::None,
+        })
+        .arg_regex(matches.value_of("arg-regex").unwrap_or_default())
+        .strict_refs(matches.is_present("strict-refs"))
+        .check(matches.is_present("check"))
+        .skip_populated(matches.is_present("skip-populated"))
+        .no_trim(matches.is_present("no-trim"))
+        .first_line_only(matches.is_present("first-line-only"))
+        .explode(matches.is_present("explode"))
+        .summarize(matches.is_present("summarize"))
+        .output_encoding(match matches.value_of("output-encoding") {
+            Some("lossy") => OutputEncoding::Lossy,
+            Some("base64") => OutputEncoding::Base64,
+            Some("hex") => OutputEncoding::Hex,
+            _ => OutputEncoding::Strict,
+        })
+        .split_policy(match matches.value_of("split-policy") {
+            Some("pad") => SplitPolicy::Pad,
+            Some("truncate") => SplitPolicy::Truncate,
+            Some("pad-truncate") => SplitPolicy::PadTruncate,
+            _ => SplitPolicy::Strict,
+        })
+        .drop_unlisted(matches.is_present("drop-unlisted"))
+        .jobs(read_positive(
+            matches.value_of("jobs").unwrap_or_default(),
+            "a positive number of jobs",
+        )?)
+        .unordered(matches.is_present("unordered"))
+        .shell(matches.is_present("shell"))
+        .coprocess(matches.is_present("coprocess"))
+        .batch_input(match matches.value_of("batch-input") {
+            Some("stdin") => BatchInput::Stdin,
+            _ => BatchInput::Args,
+        })
+        .field_env(matches.is_present("field-env"))
+        .clean_env(matches.is_present("clean-env"))
+        .output_overflow(match matches.value_of("output-overflow") {
+            Some("error") => OutputOverflow::Error,
+            _ => OutputOverflow::Truncate,
+        })
+        .progress(!matches.is_present("no-progress"))
+        // The summary is given anyway when the error output is a terminal
+        .summary(matches.is_present("summary") || io::stderr().is_terminal())
+        .on_message(|message| eprintln!("{}", message))
+        .filter(matches.is_present("filter"))
+        .dry_run(matches.is_present("dry-run"))
+        .memoize(matches.is_present("memoize"))
+        .unique(matches.is_present("unique"))
+        .strict_exit(matches.is_present("strict-exit"))
+        .fail_fast(matches.is_present("fail-fast"))
+        .failure_rate_warm_up(read_number(
+            matches.value_of("failure-rate-warm-up").unwrap_or_default(),
+            "a number of rows",
+        )?)
+        .resume(matches.is_present("resume"))
+        .retries(read_number(
+            matches.value_of("retries").unwrap_or_default(),
+            "a number of retries",
+        )?)
+        .retry_delay(match matches.value_of("retry-delay").unwrap_or_default() {
+            "0" => Duration::from_secs(0),
+            delay => read_seconds(delay)?,
+        })
+        .retry_backoff(read_factor(
+            matches.value_of("retry-backoff").unwrap_or_default(),
+        )?);
 
-fn run(config: Config) -> Result<()> {
-    let reader: Box<dyn io::Read> = match config.input_path {
-        None => Box::new(io::stdin()),
-        Some(path) => Box::new(fs::File::open(&path).context(format!("Failed to open {}", path))?),
-    };
+    // Note: at least one is required using clap. They are run in the order they are given.
+    let mut steps = indexed_values(&matches, "exec")
+        .chain(indexed_values(&matches, "more-exec"))
+        .map(|(index, command)| (index, Step::Command(command)))
+        .chain(
+            indexed_values(&matches, "expr")
+                .map(|(index, expression)| (index, Step::Expression(expression))),
+        )
+        .collect::<Vec<_>>();
+    steps.sort_by_key(|(index, _)| *index);
+    for (_, step) in steps {
+        builder = match step {
+            Step::Command(command) => builder.command(command),
+            Step::Expression(expression) => builder.expression(expression),
+        };
+    }
 
-    let writer: Box<dyn io::Write> = match config.output_path {
-        None => Box::new(io::stdout()),
-        Some(path) => {
-            Box::new(fs::File::create(&path).context(format!("Failed to create {}", path))?)
-        }
-    };
+    for path in matches.values_of("input").into_iter().flatten() {
+        builder = builder.input_path(path);
+    }
+    if let Some(name) = matches.value_of("add-filename-column") {
+        builder = builder.add_filename_column(name);
+    }
+    if let Some(sheet) = matches.value_of("sheet") {
+        builder = builder.sheet(sheet);
+    }
+    // Note: they are required together using clap
+    if let (Some(path), Some(query)) = (matches.value_of("input-sqlite"), matches.value_of("query"))
+    {
+        builder = builder.input_sqlite(path, query);
+    }
+    if let Some(path) = matches.value_of("output") {
+        builder = builder.output_path(path);
+    }
+    if let Some(template) = matches.value_of("output-template") {
+        builder = builder.output_template(template);
+    }
+    if let (Some(path), Some(table)) =
+        (matches.value_of("output-sqlite"), matches.value_of("table"))
+    {
+        builder = builder.output_sqlite(path, table);
+    }
 
-    let read_one_ascii_char = |value: &str| -> Result<u8> {
-        if value.bytes().count() > 1 {
-            return Err(anyhow!("Value {} must be 1 ASCII character", value));
-        }
-        match value.chars().next() {
-            None => Err(anyhow!("Missing value")),
-            Some(c) => {
-                if c.is_ascii() {
-                    Ok(c as u8)
-                } else {
-                    Err(anyhow!("Value {} must be 1 ASCII character", value))
-                }
-            }
-        }
+    let delimiter = if matches.is_present("tsv") {
+        "\t"
+    } else if matches.is_present("psv") {
+        "|"
+    } else if matches.is_present("ssv") {
+        ";"
+    } else {
+        matches.value_of("delimiter").unwrap_or_default()
     };
+    builder = builder.delimiter(read_separator(delimiter));
+    if let Some(delimiter) = matches.value_of("out-delimiter") {
+        builder = builder.out_delimiter(read_separator(delimiter));
+    }
+    if let Some(escape) = matches.value_of("escape") {
+        builder = builder.escape(read_char(escape)?);
+    }
+    if let Some(comment) = matches.value_of("comment") {
+        builder = builder.comment(read_char(comment)?);
+    }
+    if let Some(quote) = matches.value_of("out-quote") {
+        builder = builder.out_quote(read_char(quote)?);
+    }
+    if let Some(terminator) = matches.value_of("terminator") {
+        builder = builder.terminator(read_terminator(terminator)?);
+    }
+    if matches.is_present("crlf") {
+        builder = builder.out_terminator(Terminator::CRLF);
+    } else if let Some(terminator) = matches.value_of("out-terminator") {
+        builder = builder.out_terminator(read_terminator(terminator)?);
+    }
+    if let Some(label) = matches.value_of("encoding") {
+        builder = builder.encoding(label);
+    }
+    if let Some(label) = matches.value_of("out-encoding") {
+        builder = builder.out_encoding(label);
+    }
+    if let Some(style) = matches.value_of("quote-style") {
+        builder = builder.quote_style(match style {
+            "always" => QuoteStyle::Always,
+            "never" => QuoteStyle::Never,
+            "non-numeric" => QuoteStyle::NonNumeric,
+            _ => QuoteStyle::Necessary,
+        });
+    }
 
-    let read_delimiter = |value: &str| -> Result<u8> {
-        if value == r"\t" {
-            Ok(b'\t')
-        } else {
-            read_one_ascii_char(value)
-        }
-    };
+    for name in matches.values_of("new-column-name").into_iter().flatten() {
+        builder = builder.new_column_name(name);
+    }
+    if let Some(column) = matches.value_of("replace-column") {
+        builder = builder.replace_column(column);
+    }
+    if let Some(column) = matches.value_of("map") {
+        builder = builder.map(column);
+    }
+    if let Some(condition) = matches.value_of("where") {
+        builder = builder.condition(condition);
+    }
+    if let Some(result) = matches.value_of("else") {
+        builder = builder.otherwise(result);
+    }
+    if let Some(separator) = matches.value_of("join-lines") {
+        builder = builder.join_lines(read_separator(separator));
+    }
+    if let Some(column) = matches.value_of("group-by") {
+        builder = builder.group_by(column);
+    }
+    if let Some(command) = matches.value_of("finally") {
+        builder = builder.finally(command);
+    }
+    if let Some(path) = matches.value_of("finally-output") {
+        builder = builder.finally_output(path);
+    }
 
-    let delimiter: u8 = read_delimiter(&config.delimiter)?;
+    if let Some(separator) = matches.value_of("split-output") {
+        builder = builder.split_output(read_char(&read_separator(separator))?);
+    }
+    if let Some(paths) = matches.value_of("json-output") {
+        builder = builder.json_output(paths.split(','));
+    }
+    if let Some(regex) = matches.value_of("capture") {
+        builder = builder.capture(regex);
+    }
+    if let Some(names) = matches.value_of("result-headers") {
+        builder = builder.result_headers(names.split(','));
+    }
 
-    let out_delimiter: u8 = config
-        .out_delimiter
-        .map(|d| read_delimiter(&d))
-        .transpose()?
-        .unwrap_or(delimiter);
+    if let Some(position) = matches.value_of("column-position") {
+        builder = builder.column_position(read_number(position, "a column position")?);
+    }
+    if let Some(column) = matches.value_of("before") {
+        builder = builder.before(column);
+    }
+    if let Some(column) = matches.value_of("after") {
+        builder = builder.after(column);
+    }
+    if let Some(columns) = matches.value_of("select") {
+        builder = builder.select(columns.split(','));
+    }
+    if let Some(columns) = matches.value_of("drop") {
+        builder = builder.drop(columns.split(','));
+    }
+    if let Some(columns) = matches.value_of("order") {
+        builder = builder.order(columns.split(','));
+    }
+    if matches.is_present("only-result") {
+        builder = match matches.value_of("only-result").unwrap_or_default() {
+            // Without key columns, the rows are numbered
+            "" => builder.only_result(Vec::<String>::new()),
+            columns => builder.only_result(columns.split(',')),
+        };
+    }
+    for rename in matches.values_of("rename").into_iter().flatten() {
+        let (old, new) = rename
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Value {} must be OLD=NEW", rename))?;
+        builder = builder.rename(old, new);
+    }
+    if let Some(name) = matches.value_of("exit-code-column") {
+        builder = builder.exit_code_column(name);
+    }
+    if let Some(name) = matches.value_of("stderr-column") {
+        builder = builder.stderr_column(name);
+    }
+    if let Some(name) = matches.value_of("duration-column") {
+        builder = builder.duration_column(name);
+    }
+    if let Some(name) = matches.value_of("started-column") {
+        builder = builder.started_column(name);
+    }
+    if let Some(name) = matches.value_of("attempts-column") {
+        builder = builder.attempts_column(name);
+    }
 
-    let quote: u8 = read_one_ascii_char(&config.quote)?;
+    if let Some(shell) = matches.value_of("shell-path") {
+        builder = builder.shell_path(shell);
+    }
+    if let Some(timeout) = matches.value_of("timeout") {
+        builder = builder.timeout(read_seconds(timeout)?);
+    }
+    if let Some(max_runtime) = matches.value_of("max-runtime") {
+        builder = builder.max_runtime(read_duration(max_runtime)?);
+    }
+    if let Some(size) = matches.value_of("batch-size") {
+        builder = builder.batch_size(read_positive(size, "a positive number of records")?);
+    }
+    if let Some(format) = matches.value_of("stdin-record") {
+        builder = builder.stdin_record(match format {
+            "json" => StdinRecord::Json,
+            _ => StdinRecord::Csv,
+        });
+    }
+    if let Some(column) = matches.value_of("stdin-field") {
+        builder = builder.stdin_field(column);
+    }
+    for variable in matches.values_of("env").into_iter().flatten() {
+        let (key, value) = variable
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Value {} must be KEY=VALUE", variable))?;
+        builder = builder.env(key, value);
+    }
+    for name in matches.values_of("pass-env").into_iter().flatten() {
+        builder = builder.pass_env(name);
+    }
+    if let Some(cwd) = matches.value_of("cwd") {
+        builder = builder.cwd(cwd);
+    }
+    if let Some(bytes) = matches.value_of("max-output-bytes") {
+        builder = builder.max_output_bytes(read_number(bytes, "a number of bytes")?);
+    }
 
-    let variable_regex = Regex::new(&config.arg_regex)?;
+    if let Some(path) = matches.value_of("summary-json") {
+        builder = builder.summary_json(path);
+    }
+    if let Some(path) = matches.value_of("audit-log") {
+        builder = builder.audit_log(path);
+    }
+    if let Some(policy) = matches.value_of("on-error") {
+        builder = builder.on_error(read_on_error(policy)?);
+    }
+    if let Some(failures) = matches.value_of("abort-after-failures") {
+        builder =
+            builder.abort_after_failures(read_positive(failures, "a positive number of failures")?);
+    }
+    if let Some(rate) = matches.value_of("max-failure-rate") {
+        builder = builder.max_failure_rate(read_fraction(rate)?);
+    }
+    if let Some(path) = matches.value_of("checkpoint") {
+        builder = builder.checkpoint(path);
+    }
+    if let Some(codes) = matches.value_of("retry-on-exit-codes") {
+        builder = builder.retry_on_exit_codes(read_exit_codes(codes)?);
+    }
+    if let Some(rate) = matches.value_of("rate") {
+        builder = builder.rate(read_rate(rate)?);
+    }
 
-    let cmd_and_args: Vec<String> = shell_words::split(&config.exec)?;
+    let exit_code = builder.build()?.run(io::stdin(), io::stdout())?;
+    if exit_code != 0 {
+        process::exit(exit_code);
+    }
+    Ok(())
+}
 
-    let mut csv_reader = csv::ReaderBuilder::new()
-        .has_headers(!config.no_headers)
-        .delimiter(delimiter)
-        .quote(quote)
-        .from_reader(reader);
+/// The command line with the arguments of the map subcommand, which is replaced by --map.
+fn map_args(map: &ArgMatches) -> Vec<OsString> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let position = args
+        .iter()
+        .skip(1)
+        .position(|arg| arg == "map")
+        .unwrap_or(0);
+    args.truncate(position + 1);
+    args.push("--map".into());
+    args.extend(map.value_of_os("column").map(OsString::from));
+    args.extend(
+        map.values_of_os("options")
+            .into_iter()
+            .flatten()
+            .map(OsString::from),
+    );
+    args
+}
 
-    let mut csv_writer = csv::WriterBuilder::new()
-        .delimiter(out_delimiter)
-        .quote(quote)
-        .from_writer(writer);
+/// The values of an argument, with their indexes in the command line.
+fn indexed_values<'a>(
+    matches: &'a clap::ArgMatches,
+    name: &str,
+) -> impl Iterator<Item = (usize, &'a str)> {
+    let indices = matches.indices_of(name).into_iter().flatten();
+    indices.zip(matches.values_of(name).into_iter().flatten())
+}
 
-    if !config.no_headers {
-        let new_headers = csv_reader.headers()?.clone();
-        csv_writer.write_record(
-            new_headers
-                .iter()
-                .chain(vec![&*config.new_column_name].into_iter()),
-        )?;
+/// A command or an expression, in the order of the command line.
+enum Step<'a> {
+    Command(&'a str),
+    Expression(&'a str),
+}
+
+/// Reads a separator, which is \t for tabs.
+fn read_separator(value: &str) -> String {
+    if value == r"\t" {
+        "\t".to_string()
+    } else {
+        value.to_string()
     }
+}
 
-    for record in csv_reader.records() {
-        let mut record = record?;
-        let mut args_iter = cmd_and_args.iter();
-        let command = match args_iter.next() {
-            None => return Err(anyhow!("No command to execute")),
-            Some(command) => command,
-        };
-        let args = args_iter
-            .map(|arg| {
-                variable_regex
-                    .replace_all(arg, |caps: &Captures| {
-                        let record_value = caps
-                            .get(1)
-                            .and_then(|position| position.as_str().parse::<usize>().ok())
-                            // Column position begins at 1
-                            .and_then(|position| position.checked_sub(1))
-                            .and_then(|position| record.get(position));
-                        match record_value {
-                            None => "",
-                            Some(value) => value,
-                        }
-                    })
-                    .to_string()
-            })
-            .collect::<Vec<_>>();
-        let output = process::Command::new(command)
-            .args(&args)
-            .output()
-            .context(format!(
-                "Failed to execute command {} with args {:?}",
-                command, args
-            ))?;
-
-        let out = std::str::from_utf8(&output.stdout)?.trim();
-        record.push_field(&out);
-        csv_writer.write_record(record.iter())?;
-    }
-    csv_writer.flush()?;
-    Ok(())
+fn read_char(value: &str) -> Result<u8> {
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (None, _) => Err(anyhow!("Missing value")),
+        (Some(c), None) if c.is_ascii() => Ok(c as u8),
+        _ => Err(anyhow!("Value {} must be 1 ASCII character", value)),
+    }
+}
+
+fn read_terminator(value: &str) -> Result<Terminator> {
+    Ok(match value {
+        r"\n" => Terminator::Any(b'\n'),
+        r"\r\n" | "\r\n" => Terminator::CRLF,
+        r"\r" => Terminator::Any(b'\r'),
+        value => Terminator::Any(read_char(value)?),
+    })
+}
+
+/// Reads a number, `kind` describing it in the error.
+fn read_number<T: FromStr>(value: &str, kind: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Value {} must be {}", value, kind))
+}
+
+fn read_positive<T: FromStr + Default + PartialOrd>(value: &str, kind: &str) -> Result<T> {
+    match value.parse::<T>() {
+        Ok(number) if number > T::default() => Ok(number),
+        _ => Err(anyhow!("Value {} must be {}", value, kind)),
+    }
+}
+
+fn read_fraction(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(fraction) if (0.0..1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(anyhow!(
+            "Value {} must be a fraction greater or equal to 0 and lower than 1",
+            value
+        )),
+    }
+}
+
+fn read_factor(value: &str) -> Result<f64> {
+    match value.parse::<f64>() {
+        Ok(factor) if factor.is_finite() && factor >= 1.0 => Ok(factor),
+        _ => Err(anyhow!(
+            "Value {} must be a factor greater or equal to 1",
+            value
+        )),
+    }
+}
+
+fn read_on_error(value: &str) -> Result<OnError> {
+    match value {
+        "abort" => Ok(OnError::Abort),
+        "skip-row" => Ok(OnError::SkipRow),
+        "empty" => Ok(OnError::Empty),
+        _ => match value.strip_prefix("placeholder=") {
+            Some(placeholder) => Ok(OnError::Placeholder(placeholder.to_string())),
+            None => Err(anyhow!(
+                "Value {} must be abort, skip-row, empty or placeholder=STRING",
+                value
+            )),
+        },
+    }
+}
+
+fn read_exit_codes(value: &str) -> Result<Vec<i32>> {
+    value
+        .split(',')
+        .map(|code| {
+            code.trim()
+                .parse()
+                .map_err(|_| anyhow!("Value {} must be a list of exit codes", value))
+        })
+        .collect()
+}
+
+fn read_seconds(value: &str) -> Result<Duration> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds > 0.0 => Duration::try_from_secs_f64(seconds)
+            .map_err(|_| anyhow!("Value {} is too large", value)),
+        _ => Err(anyhow!(
+            "Value {} must be a positive number of seconds",
+            value
+        )),
+    }
+}
+
+/// Reads a number of seconds, or a number followed by a unit: s, m or h.
+fn read_duration(value: &str) -> Result<Duration> {
+    let (number, unit_secs) = match value.char_indices().last() {
+        Some((i, 's')) => (&value[..i], 1.0),
+        Some((i, 'm')) => (&value[..i], 60.0),
+        Some((i, 'h')) => (&value[..i], 3600.0),
+        _ => (value, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => {
+            Duration::try_from_secs_f64(number * unit_secs)
+                .map_err(|_| anyhow!("Value {} is too large", value))
+        }
+        _ => Err(anyhow!("Value {} must be a positive duration", value)),
+    }
+}
+
+/// Reads a rate such as 10/s, 100/m or 1000/h, and returns it per second. The unit defaults to
+/// seconds.
+fn read_rate(value: &str) -> Result<f64> {
+    let (count, unit_secs) = match value.split_once('/') {
+        None => (value, 1.0),
+        Some((count, "s")) => (count, 1.0),
+        Some((count, "m")) => (count, 60.0),
+        Some((count, "h")) => (count, 3600.0),
+        Some(_) => return Err(anyhow!("Value {} must be a rate such as 10/s", value)),
+    };
+    match count.parse::<f64>() {
+        Ok(count) if count.is_finite() && count > 0.0 => {
+            // The launches are spaced by an interval, which must be a duration
+            match Duration::try_from_secs_f64(unit_secs / count) {
+                Ok(_) => Ok(count / unit_secs),
+                Err(_) => Err(anyhow!("Value {} is too low", value)),
+            }
+        }
+        _ => Err(anyhow!("Value {} must be a rate such as 10/s", value)),
+    }
 }
@@ -0,0 +1,177 @@
+use crate::delimiter;
+#[cfg(feature = "parquet")]
+use crate::parquet::ParquetWriter;
+use anyhow::Result;
+use csv::StringRecord;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    /// A GitHub flavored Markdown table
+    Markdown,
+    Html,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Writes the output records as a table in one of the formats.
+pub enum TableWriter<W: Write + Send> {
+    Csv(Box<delimiter::Writer<W>>),
+    Markdown(Table<W>),
+    Html(Table<W>),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetWriter<W>),
+}
+
+/// A table in a markup language, which has a beginning and an end around the rows.
+pub struct Table<W: Write> {
+    writer: W,
+    started: bool,
+}
+
+impl<W: Write + Send> TableWriter<W> {
+    /// The CSV output can have a `long_delimiter` of several characters, and bytes which are not
+    /// UTF-8 with `raw`.
+    pub fn new(
+        format: Format,
+        builder: &csv::WriterBuilder,
+        long_delimiter: Option<&str>,
+        raw: bool,
+        writer: W,
+    ) -> TableWriter<W> {
+        let table = |writer| Table {
+            writer,
+            started: false,
+        };
+        match format {
+            Format::Csv => TableWriter::Csv(Box::new(delimiter::Writer::new(
+                builder,
+                long_delimiter,
+                raw,
+                writer,
+            ))),
+            Format::Markdown => TableWriter::Markdown(table(writer)),
+            Format::Html => TableWriter::Html(table(writer)),
+            #[cfg(feature = "parquet")]
+            Format::Parquet => TableWriter::Parquet(ParquetWriter::new(writer)),
+        }
+    }
+
+    pub fn write_header(&mut self, headers: &StringRecord) -> Result<()> {
+        match self {
+            TableWriter::Csv(writer) => writer.write_record(headers)?,
+            TableWriter::Markdown(table) => {
+                table.started = true;
+                write_markdown_row(&mut table.writer, headers.iter())?;
+                let separator = headers.iter().map(|_| "---");
+                write_markdown_row(&mut table.writer, separator)?;
+            }
+            TableWriter::Html(table) => {
+                table.started = true;
+                writeln!(table.writer, "<table>\n<thead>")?;
+                write_html_row(&mut table.writer, "th", headers)?;
+                writeln!(table.writer, "</thead>\n<tbody>")?;
+            }
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.write_header(headers)?,
+        }
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &StringRecord) -> Result<()> {
+        match self {
+            TableWriter::Csv(writer) => writer.write_record(record)?,
+            TableWriter::Markdown(table) => {
+                // A Markdown table always begins with a header line
+                if !table.started {
+                    table.started = true;
+                    write_markdown_row(&mut table.writer, record.iter().map(|_| ""))?;
+                    write_markdown_row(&mut table.writer, record.iter().map(|_| "---"))?;
+                }
+                write_markdown_row(&mut table.writer, record.iter())?;
+            }
+            TableWriter::Html(table) => {
+                if !table.started {
+                    table.started = true;
+                    writeln!(table.writer, "<table>\n<tbody>")?;
+                }
+                write_html_row(&mut table.writer, "td", record)?;
+            }
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.write_record(record)?,
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TableWriter::Csv(writer) => writer.flush(),
+            TableWriter::Markdown(table) | TableWriter::Html(table) => table.writer.flush(),
+            // The rows are written by row groups
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(_) => Ok(()),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self {
+            TableWriter::Csv(writer) => writer.get_ref(),
+            TableWriter::Markdown(table) | TableWriter::Html(table) => &table.writer,
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.get_ref(),
+        }
+    }
+
+    /// Ends the table, and returns the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        match self {
+            TableWriter::Csv(writer) => Ok(writer.into_inner()?),
+            TableWriter::Markdown(mut table) => {
+                table.writer.flush()?;
+                Ok(table.writer)
+            }
+            TableWriter::Html(mut table) => {
+                if table.started {
+                    writeln!(table.writer, "</tbody>\n</table>")?;
+                }
+                table.writer.flush()?;
+                Ok(table.writer)
+            }
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+fn write_markdown_row<'a, W, I>(writer: &mut W, cells: I) -> io::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = &'a str>,
+{
+    let mut line = String::from("|");
+    for cell in cells {
+        let cell = cell
+            .replace('|', "\\|")
+            .replace("\r\n", "<br>")
+            .replace('\n', "<br>");
+        line.push(' ');
+        line.push_str(&cell);
+        line.push_str(" |");
+    }
+    writeln!(writer, "{}", line)
+}
+
+fn write_html_row<W: Write>(writer: &mut W, tag: &str, record: &StringRecord) -> io::Result<()> {
+    let mut line = String::from("<tr>");
+    for cell in record {
+        let cell = cell
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;");
+        line.push_str(&format!("<{0}>{1}</{0}>", tag, cell));
+    }
+    line.push_str("</tr>");
+    writeln!(writer, "{}", line)
+}
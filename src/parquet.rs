@@ -0,0 +1,164 @@
+use ::parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use ::parquet::data_type::{ByteArray, ByteArrayType};
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::reader::{FileReader, SerializedFileReader};
+use ::parquet::file::writer::SerializedFileWriter;
+use ::parquet::record::Field;
+use ::parquet::schema::types::Type;
+use anyhow::{Context, Result};
+use csv::StringRecord;
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Rows buffered before they are written as a row group.
+const ROW_GROUP_SIZE: usize = 10_000;
+
+/// Each of the records of a file, after its header line.
+pub type Records = Box<dyn Iterator<Item = Result<StringRecord>>>;
+
+/// The names of the columns of the file at `path`, and its records.
+pub fn read(path: &str) -> Result<(StringRecord, Records)> {
+    let reader = open(path)?;
+    let headers = reader
+        .metadata()
+        .file_metadata()
+        .schema()
+        .get_fields()
+        .iter()
+        .map(|field| field.name())
+        .collect();
+    let path = path.to_string();
+    let records = reader.into_iter().map(move |row| {
+        let row = row.context(format!("Failed to read {}", path))?;
+        Ok(row
+            .get_column_iter()
+            .map(|(_, field)| value(field))
+            .collect())
+    });
+    Ok((headers, Box::new(records)))
+}
+
+pub fn count_rows(path: &str) -> Result<u64> {
+    let rows = open(path)?.metadata().file_metadata().num_rows();
+    Ok(rows.max(0) as u64)
+}
+
+fn open(path: &str) -> Result<SerializedFileReader<File>> {
+    let file = File::open(path).context(format!("Failed to open {}", path))?;
+    SerializedFileReader::new(file).context(format!("Failed to read {}", path))
+}
+
+/// Strings are written as is, null as an empty field, and the numbers without a fractional part
+/// as integers.
+fn value(field: &Field) -> String {
+    match field {
+        Field::Null => String::new(),
+        Field::Str(string) => string.clone(),
+        Field::Bytes(bytes) => String::from_utf8_lossy(bytes.data()).into_owned(),
+        Field::Float(float) => float.to_string(),
+        Field::Double(double) => double.to_string(),
+        field => field.to_string(),
+    }
+}
+
+/// Writes the records in a file, whose columns are all strings. The file is started by the
+/// header line, or by the first record without one, which gives the number of columns.
+pub struct ParquetWriter<W: Write + Send> {
+    /// Until the file is started
+    pending: Option<W>,
+    writer: Option<Box<SerializedFileWriter<W>>>,
+    /// Values of each column, not written yet
+    columns: Vec<Vec<ByteArray>>,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    pub fn new(writer: W) -> ParquetWriter<W> {
+        ParquetWriter {
+            pending: Some(writer),
+            writer: None,
+            columns: vec![],
+        }
+    }
+
+    pub fn write_header(&mut self, headers: &StringRecord) -> Result<()> {
+        self.start(headers.iter().map(String::from).collect())
+    }
+
+    pub fn write_record(&mut self, record: &StringRecord) -> Result<()> {
+        if self.writer.is_none() {
+            let names = (1..=record.len()).map(|i| format!("column{}", i)).collect();
+            self.start(names)?;
+        }
+        for (column, field) in self.columns.iter_mut().zip(record) {
+            column.push(ByteArray::from(field));
+        }
+        // Missing fields are empty, as the columns must all have the same length
+        for column in self.columns.iter_mut().skip(record.len()) {
+            column.push(ByteArray::from(""));
+        }
+        if self.columns.first().map_or(0, Vec::len) >= ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match &self.writer {
+            Some(writer) => writer.inner(),
+            None => self.pending.as_ref().unwrap(),
+        }
+    }
+
+    /// Writes the buffered rows and the footer of the file, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.writer.is_none() {
+            self.start(vec![])?;
+        }
+        self.write_row_group()?;
+        Ok(self.writer.unwrap().into_inner()?)
+    }
+
+    fn start(&mut self, names: Vec<String>) -> Result<()> {
+        let fields = names
+            .iter()
+            .map(|name| {
+                let field = Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                    .with_repetition(Repetition::REQUIRED)
+                    .with_logical_type(Some(LogicalType::String))
+                    .build()?;
+                Ok(Arc::new(field))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let schema = Type::group_type_builder("schema")
+            .with_fields(fields)
+            .build()?;
+        let properties = WriterProperties::builder().build();
+        let writer = SerializedFileWriter::new(
+            self.pending.take().unwrap(),
+            Arc::new(schema),
+            Arc::new(properties),
+        )?;
+        self.writer = Some(Box::new(writer));
+        self.columns = vec![vec![]; names.len()];
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        if self.columns.first().is_none_or(Vec::is_empty) {
+            return Ok(());
+        }
+        let mut row_group = writer.next_row_group()?;
+        for values in &mut self.columns {
+            let mut column = row_group.next_column()?.unwrap();
+            column
+                .typed::<ByteArrayType>()
+                .write_batch(values, None, None)?;
+            column.close()?;
+            values.clear();
+        }
+        row_group.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use crate::charset;
+use crate::compression::{Compression, Encoder};
+use crate::delimiter;
+use anyhow::{anyhow, Context, Result};
+use csv::StringRecord;
+use encoding_rs::Encoding;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+/// Files kept open at the same time. The least recently used one is closed beyond.
+const MAX_OPEN_FILES: usize = 64;
+
+type FileWriter = delimiter::Writer<charset::Encoder<Encoder<fs::File>>>;
+
+enum Part {
+    Literal(String),
+    /// Index of the field in the output records
+    Field(usize),
+}
+
+/// Writes each output record to a file whose path is given by a template, like
+/// `out/{Region}.csv`, with the header line at the beginning of each file.
+pub struct PartitionedWriter {
+    template: Vec<Part>,
+    headers: Option<StringRecord>,
+    builder: csv::WriterBuilder,
+    encoding: Option<&'static Encoding>,
+    /// Of several characters, written instead of the delimiter of the builder
+    delimiter: Option<String>,
+    /// With the time of their last use
+    writers: HashMap<PathBuf, (FileWriter, u64)>,
+    /// Files created during this run, which are appended to when opened again
+    created: HashSet<PathBuf>,
+    uses: u64,
+}
+
+impl PartitionedWriter {
+    /// `{NAME}` in `template` is replaced by the field of the column NAME, or at the position
+    /// NAME (beginning at 1).
+    pub fn new(
+        template: &str,
+        headers: Option<StringRecord>,
+        builder: csv::WriterBuilder,
+        encoding: Option<&'static Encoding>,
+        delimiter: Option<&str>,
+    ) -> Result<PartitionedWriter> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Missing }} in {}", template))?;
+            let name = &rest[start + 1..start + end];
+            let index = headers
+                .as_ref()
+                .and_then(|headers| headers.iter().position(|header| header == name))
+                .or_else(|| match name.parse::<usize>() {
+                    Ok(position) if position > 0 => Some(position - 1),
+                    _ => None,
+                })
+                .ok_or_else(|| anyhow!("Unknown column {} in {}", name, template))?;
+            parts.push(Part::Literal(rest[..start].to_string()));
+            parts.push(Part::Field(index));
+            rest = &rest[start + end + 1..];
+        }
+        parts.push(Part::Literal(rest.to_string()));
+        Ok(PartitionedWriter {
+            template: parts,
+            headers,
+            builder,
+            encoding,
+            delimiter: delimiter.map(String::from),
+            writers: HashMap::new(),
+            created: HashSet::new(),
+            uses: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &StringRecord) -> Result<()> {
+        let path = self.path(record);
+        self.uses += 1;
+        if !self.writers.contains_key(&path) {
+            let writer = self.open(&path)?;
+            self.writers.insert(path.clone(), (writer, 0));
+        }
+        let (writer, last_use) = self.writers.get_mut(&path).unwrap();
+        *last_use = self.uses;
+        writer
+            .write_record(record)
+            .context(format!("Failed to write {}", path.display()))
+    }
+
+    /// Closes all the files.
+    pub fn finish(&mut self) -> Result<()> {
+        for (path, (writer, _)) in self.writers.drain() {
+            close(writer).context(format!("Failed to write {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The `/` of the fields, and the fields `.` and `..`, are replaced by `_`, so that a
+    /// field is never a directory.
+    fn path(&self, record: &StringRecord) -> PathBuf {
+        let mut path = String::new();
+        for part in &self.template {
+            match part {
+                Part::Literal(literal) => path.push_str(literal),
+                Part::Field(index) => match record.get(*index).unwrap_or_default() {
+                    "." | ".." => path.push('_'),
+                    field => path.push_str(&field.replace('/', "_")),
+                },
+            }
+        }
+        PathBuf::from(path)
+    }
+
+    /// The file is compressed according to its extension.
+    fn open(&mut self, path: &PathBuf) -> Result<FileWriter> {
+        if self.writers.len() >= MAX_OPEN_FILES {
+            let least_used = self
+                .writers
+                .iter()
+                .min_by_key(|(_, (_, last_use))| *last_use)
+                .map(|(path, _)| path.clone())
+                .unwrap();
+            let (writer, _) = self.writers.remove(&least_used).unwrap();
+            close(writer).context(format!("Failed to write {}", least_used.display()))?;
+        }
+        let compression = Compression::from_path(&path.to_string_lossy());
+        if self.created.contains(path) {
+            let file = fs::OpenOptions::new()
+                .append(true)
+                .open(path)
+                .context(format!("Failed to open {}", path.display()))?;
+            return self.writer(file, compression);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Failed to create {}", parent.display()))?;
+        }
+        let file =
+            fs::File::create(path).context(format!("Failed to create {}", path.display()))?;
+        self.created.insert(path.clone());
+        let mut writer = self.writer(file, compression)?;
+        if let Some(headers) = &self.headers {
+            writer.write_record(headers)?;
+        }
+        Ok(writer)
+    }
+
+    fn writer(&self, file: fs::File, compression: Compression) -> Result<FileWriter> {
+        let encoder = charset::Encoder::new(compression.encoder(file)?, self.encoding);
+        Ok(delimiter::Writer::new(
+            &self.builder,
+            self.delimiter.as_deref(),
+            false,
+            encoder,
+        ))
+    }
+}
+
+fn close(writer: FileWriter) -> Result<()> {
+    Ok(writer.into_inner()?.finish()?.finish()?)
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A fixed number of threads applying the same function to the submitted jobs.
+/// Results are received in completion order, tagged with the index given at submission.
+pub struct Pool<J, R> {
+    jobs: Option<Sender<(usize, J)>>,
+    results: Receiver<(usize, R)>,
+    workers: Vec<thread::JoinHandle<()>>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<J: Send + 'static, R: Send + 'static> Pool<J, R> {
+    pub fn new<F>(size: usize, f: F) -> Pool<J, R>
+    where
+        F: Fn(J) -> R + Send + Sync + 'static,
+    {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<(usize, J)>();
+        let (results_tx, results_rx) = mpsc::channel();
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+        let f = Arc::new(f);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let workers = (0..size)
+            .map(|_| {
+                let jobs_rx = Arc::clone(&jobs_rx);
+                let results_tx = results_tx.clone();
+                let f = Arc::clone(&f);
+                let cancelled = Arc::clone(&cancelled);
+                thread::spawn(move || loop {
+                    // The lock is released before running the job
+                    let job = jobs_rx.lock().unwrap().recv();
+                    match job {
+                        Err(_) => break,
+                        Ok(_) if cancelled.load(Ordering::SeqCst) => break,
+                        Ok((index, job)) => {
+                            if results_tx.send((index, f(job))).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        Pool {
+            jobs: Some(jobs_tx),
+            results: results_rx,
+            workers,
+            cancelled,
+        }
+    }
+
+    pub fn submit(&self, index: usize, job: J) {
+        if let Some(jobs) = &self.jobs {
+            // Fails only if all the workers are gone, which is detected by `recv`
+            let _ = jobs.send((index, job));
+        }
+    }
+
+    /// Waits for the next completed job. Returns `None` if no worker is alive.
+    pub fn recv(&self) -> Option<(usize, R)> {
+        self.results.recv().ok()
+    }
+}
+
+impl<J, R> Drop for Pool<J, R> {
+    /// Queued jobs which are not started yet are dropped.
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Buffers results received out of order, to release them in index order.
+pub struct Reorder<R> {
+    pending: BTreeMap<usize, R>,
+    next: usize,
+}
+
+impl<R> Reorder<R> {
+    pub fn new() -> Reorder<R> {
+        Reorder {
+            pending: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    pub fn push(&mut self, index: usize, result: R) {
+        self.pending.insert(index, result);
+    }
+
+    /// Returns the next result in index order, if it has been pushed.
+    pub fn pop(&mut self) -> Option<(usize, R)> {
+        let result = self.pending.remove(&self.next)?;
+        self.next += 1;
+        Some((self.next - 1, result))
+    }
+}
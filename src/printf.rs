@@ -0,0 +1,165 @@
+use anyhow::{anyhow, Result};
+
+/// A conversion of printf, such as `%05d` or `%8.2f`.
+#[derive(Clone)]
+pub struct Spec {
+    text: String,
+    /// Left-justified, instead of right-justified
+    left: bool,
+    /// Padded with zeros instead of spaces, for the numbers
+    zeros: bool,
+    /// Sign written before the positive numbers, if any
+    sign: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+    conversion: Conversion,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Conversion {
+    /// %d or %i
+    Integer,
+    /// %x
+    LowerHex,
+    /// %X
+    UpperHex,
+    /// %o
+    Octal,
+    /// %f
+    Fixed,
+    /// %e
+    Exponent,
+    /// %s
+    String,
+}
+
+impl Spec {
+    /// Parses a whole conversion, or returns `None` if it is not one.
+    pub fn parse(text: &str) -> Option<Spec> {
+        let mut rest = text.strip_prefix('%')?;
+        let mut spec = Spec {
+            text: text.to_string(),
+            left: false,
+            zeros: false,
+            sign: None,
+            width: 0,
+            precision: None,
+            conversion: Conversion::String,
+        };
+        while let Some(flag) = rest.chars().next().filter(|c| "-0+ ".contains(*c)) {
+            match flag {
+                '-' => spec.left = true,
+                '0' => spec.zeros = true,
+                // A + takes precedence over a space
+                '+' => spec.sign = Some('+'),
+                _ => spec.sign = spec.sign.or(Some(' ')),
+            }
+            rest = &rest[1..];
+        }
+        let (width, after) = digits(rest);
+        spec.width = width.unwrap_or(0);
+        rest = after;
+        if let Some(after) = rest.strip_prefix('.') {
+            let (precision, after) = digits(after);
+            spec.precision = Some(precision.unwrap_or(0));
+            rest = after;
+        }
+        spec.conversion = match rest {
+            "d" | "i" => Conversion::Integer,
+            "x" => Conversion::LowerHex,
+            "X" => Conversion::UpperHex,
+            "o" => Conversion::Octal,
+            "f" => Conversion::Fixed,
+            "e" => Conversion::Exponent,
+            "s" => Conversion::String,
+            _ => return None,
+        };
+        Some(spec)
+    }
+
+    /// Formats `value`, which must be a number unless the conversion is %s.
+    pub fn format(&self, value: &str) -> Result<String> {
+        let number = value.trim();
+        let error = |kind| anyhow!("Value {} must be {} for {}", value, kind, self.text);
+        let (negative, digits) = match self.conversion {
+            Conversion::String => {
+                let value = match self.precision {
+                    Some(precision) => value.chars().take(precision).collect(),
+                    None => value.to_string(),
+                };
+                return Ok(self.pad(String::new(), value, false));
+            }
+            Conversion::Integer
+            | Conversion::LowerHex
+            | Conversion::UpperHex
+            | Conversion::Octal => {
+                let integer = number.parse::<i64>().map_err(|_| error("an integer"))?;
+                let magnitude = integer.unsigned_abs();
+                let digits = match self.conversion {
+                    Conversion::LowerHex => format!("{:x}", magnitude),
+                    Conversion::UpperHex => format!("{:X}", magnitude),
+                    Conversion::Octal => format!("{:o}", magnitude),
+                    _ => magnitude.to_string(),
+                };
+                // The precision is the minimum number of digits
+                let digits = match self.precision {
+                    Some(precision) => format!("{:0>1$}", digits, precision),
+                    None => digits,
+                };
+                (integer < 0, digits)
+            }
+            Conversion::Fixed | Conversion::Exponent => {
+                let float = number.parse::<f64>().map_err(|_| error("a number"))?;
+                if !float.is_finite() {
+                    return Err(error("a number"));
+                }
+                let precision = self.precision.unwrap_or(6);
+                let digits = match self.conversion {
+                    Conversion::Fixed => format!("{:.*}", precision, float.abs()),
+                    _ => exponent(float.abs(), precision),
+                };
+                (float.is_sign_negative() && float != 0.0, digits)
+            }
+        };
+        let sign = match (negative, self.sign) {
+            (true, _) => "-".to_string(),
+            (false, Some(sign)) => sign.to_string(),
+            (false, None) => String::new(),
+        };
+        // The zeros are ignored with a precision, for the integers like in C
+        let integer =
+            self.conversion != Conversion::Fixed && self.conversion != Conversion::Exponent;
+        let zeros = self.zeros && !(integer && self.precision.is_some());
+        Ok(self.pad(sign, digits, zeros))
+    }
+
+    /// Pads `sign` followed by `digits` to the width, with zeros between them if `zeros`.
+    fn pad(&self, sign: String, digits: String, zeros: bool) -> String {
+        let length = sign.chars().count() + digits.chars().count();
+        let padding = self.width.saturating_sub(length);
+        if self.left {
+            format!("{}{}{}", sign, digits, " ".repeat(padding))
+        } else if zeros {
+            format!("{}{}{}", sign, "0".repeat(padding), digits)
+        } else {
+            format!("{}{}{}", " ".repeat(padding), sign, digits)
+        }
+    }
+}
+
+/// The leading digits of `text`, if any, and the rest of it.
+fn digits(text: &str) -> (Option<usize>, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    (text[..end].parse().ok(), &text[end..])
+}
+
+/// Formats a positive number like %e in C, with an exponent of at least two digits.
+fn exponent(float: f64, precision: usize) -> String {
+    let formatted = format!("{:.*e}", precision, float);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
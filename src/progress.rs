@@ -0,0 +1,135 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Interval between two redraws of the progress line.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+const BAR_WIDTH: usize = 30;
+
+/// A progress line on the error output, redrawn as the rows are written.
+pub struct Progress {
+    /// Number of rows of the input, if known
+    total: Option<u64>,
+    done: u64,
+    start: Instant,
+    last_draw: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(total: Option<u64>) -> Progress {
+        Progress {
+            total,
+            done: 0,
+            start: Instant::now(),
+            last_draw: None,
+        }
+    }
+
+    pub fn inc(&mut self) {
+        self.done += 1;
+        if self
+            .last_draw
+            .is_none_or(|last_draw| last_draw.elapsed() >= REDRAW_INTERVAL)
+        {
+            self.draw();
+        }
+    }
+
+    /// Erases the progress line, so that a message can be written. It is drawn again on the next row.
+    pub fn clear(&mut self) {
+        eprint!("\r\x1b[K");
+        self.last_draw = None;
+    }
+
+    /// Draws the final state, and leaves it on its line.
+    pub fn finish(&mut self) {
+        self.draw();
+        eprintln!();
+    }
+
+    fn draw(&mut self) {
+        let line = self.line(self.start.elapsed().as_secs_f64());
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r\x1b[K{}", line);
+        let _ = stderr.flush();
+        self.last_draw = Some(Instant::now());
+    }
+
+    /// The progress line, `elapsed` seconds after the start.
+    fn line(&self, elapsed: f64) -> String {
+        let rate = if elapsed > 0.0 {
+            self.done as f64 / elapsed
+        } else {
+            0.0
+        };
+        match self.total {
+            Some(total) if total > 0 => {
+                let ratio = (self.done as f64 / total as f64).min(1.0);
+                let filled = (ratio * BAR_WIDTH as f64) as usize;
+                let eta = if rate > 0.0 {
+                    format_duration(total.saturating_sub(self.done) as f64 / rate)
+                } else {
+                    "?".to_string()
+                };
+                format!(
+                    "[{}{}] {}/{} rows, {:.1} rows/s, ETA {}",
+                    "#".repeat(filled),
+                    ".".repeat(BAR_WIDTH - filled),
+                    self.done,
+                    total,
+                    rate,
+                    eta
+                )
+            }
+            _ => format!("{} rows, {:.1} rows/s", self.done, rate),
+        }
+    }
+}
+
+/// Formats seconds as H:MM:SS, or M:SS below one hour.
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "0:00");
+        assert_eq!(format_duration(59.6), "1:00");
+        assert_eq!(format_duration(754.0), "12:34");
+        assert_eq!(format_duration(3600.0), "1:00:00");
+        assert_eq!(format_duration(45296.0), "12:34:56");
+    }
+
+    #[test]
+    fn test_line() {
+        let mut progress = Progress::new(Some(40));
+        progress.done = 10;
+        assert_eq!(
+            progress.line(5.0),
+            "[#######.......................] 10/40 rows, 2.0 rows/s, ETA 0:15"
+        );
+        progress.done = 50;
+        assert_eq!(
+            progress.line(5.0),
+            "[##############################] 50/40 rows, 10.0 rows/s, ETA 0:00"
+        );
+        progress.done = 0;
+        assert_eq!(
+            progress.line(0.0),
+            "[..............................] 0/40 rows, 0.0 rows/s, ETA ?"
+        );
+        let mut progress = Progress::new(None);
+        progress.done = 3;
+        assert_eq!(progress.line(2.0), "3 rows, 1.5 rows/s");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::columns::Placement;
+use csv::StringRecord;
+
+/// Which fields of the input records are written.
+pub enum Selection {
+    All,
+    /// Only the fields at these indexes
+    Only(Vec<usize>),
+    /// The fields except the ones at these indexes
+    Except(Vec<usize>),
+}
+
+impl Selection {
+    fn keeps(&self, index: usize) -> bool {
+        match self {
+            Selection::All => true,
+            Selection::Only(indexes) => indexes.contains(&index),
+            Selection::Except(indexes) => !indexes.contains(&index),
+        }
+    }
+}
+
+/// In which order the fields of the output records are written.
+pub struct Order {
+    /// Indexes of the first fields, in order
+    pub indexes: Vec<usize>,
+    /// Whether the other fields follow them, or are not written
+    pub unlisted: bool,
+}
+
+impl Order {
+    pub fn apply(&self, record: StringRecord) -> StringRecord {
+        let listed = self
+            .indexes
+            .iter()
+            .map(|&index| record.get(index).unwrap_or_default());
+        let unlisted = (0..record.len())
+            .filter(|index| self.unlisted && !self.indexes.contains(index))
+            .map(|index| &record[index]);
+        listed.chain(unlisted).collect()
+    }
+}
+
+/// How the output records are made from the input records and the values of the new columns.
+pub struct Projection {
+    pub placement: Placement,
+    /// Of the fields of the input records, the new columns being always written
+    pub selection: Selection,
+    /// Of the fields of the output records, once selected
+    pub order: Option<Order>,
+}
+
+impl Projection {
+    /// Adds `values`, which begin with the result, to the selected fields of `record`.
+    pub fn apply(&self, record: &StringRecord, values: Vec<String>) -> StringRecord {
+        let output = match self.selection {
+            Selection::All => self.placement.apply(record, values),
+            _ => {
+                let sources = self.placement.sources(record.len(), values.len());
+                let output = self.placement.apply(record, values);
+                output
+                    .iter()
+                    .zip(sources)
+                    .filter(|(_, source)| source.is_none_or(|index| self.selection.keeps(index)))
+                    .map(|(field, _)| field)
+                    .collect()
+            }
+        };
+        match &self.order {
+            Some(order) => order.apply(output),
+            None => output,
+        }
+    }
+}
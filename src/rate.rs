@@ -0,0 +1,38 @@
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Limits the rate at which commands are launched, by all the workers together.
+/// This is a token bucket holding a single token: the launches are evenly spaced.
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Launches at most `per_second` commands per second, which is positive. Fails if the
+    /// interval between two launches is beyond the range of the clock.
+    pub fn new(per_second: f64) -> Result<RateLimiter> {
+        let interval = Duration::try_from_secs_f64(1.0 / per_second)
+            .ok()
+            .filter(|interval| Instant::now().checked_add(*interval).is_some())
+            .ok_or_else(|| anyhow!("The rate of {} per second is too low", per_second))?;
+        Ok(RateLimiter {
+            interval,
+            next: Mutex::new(None),
+        })
+    }
+
+    /// Waits until a command may be launched.
+    pub fn acquire(&self) {
+        let now = Instant::now();
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = next.map_or(now, |next| next.max(now));
+            *next = Some(start + self.interval);
+            start
+        };
+        thread::sleep(start - now);
+    }
+}
@@ -0,0 +1,50 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+
+/// First of the 256 characters, at the end of the last private use plane, which stand for the
+/// bytes of the input which are not valid UTF-8.
+const ESCAPES: u32 = 0x10_ff00;
+
+/// Converts `bytes` to text, each byte which is not part of a UTF-8 character being replaced by
+/// a private use character. `unescape` gives the bytes back.
+pub fn escape(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for &byte in chunk.invalid() {
+            text.push(char::from_u32(ESCAPES + u32::from(byte)).unwrap());
+        }
+    }
+    text
+}
+
+pub fn unescape(text: &str) -> Cow<'_, [u8]> {
+    if !text
+        .chars()
+        .any(|character| u32::from(character) >= ESCAPES)
+    {
+        return Cow::Borrowed(text.as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(text.len());
+    for character in text.chars() {
+        match u32::from(character).checked_sub(ESCAPES) {
+            Some(byte) => bytes.push(byte as u8),
+            None => bytes.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+/// A command argument or an environment variable with the bytes given back, which other systems
+/// than Unix do not support.
+pub fn os_string(text: &str) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(unescape(text).into_owned())
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(text)
+    }
+}
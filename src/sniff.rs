@@ -0,0 +1,112 @@
+use csv::StringRecord;
+
+/// Size of the beginning of the input which is inspected.
+pub const SAMPLE_SIZE: usize = 16 * 1024;
+
+/// Delimiters which can be detected, in order of preference.
+const DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// The dialect guessed from the beginning of an input.
+pub struct Dialect {
+    pub delimiter: u8,
+    pub has_headers: bool,
+}
+
+/// The delimiter is the one which splits the most lines in the same number of fields, and the
+/// first line is a header line unless it looks like the next ones. `complete` tells whether the
+/// sample is the whole input, or else its last line is ignored, as it may be cut.
+pub fn sniff(sample: &[u8], quote: u8, comment: Option<u8>, complete: bool) -> Dialect {
+    let sample = match sample.iter().rposition(|&byte| byte == b'\n') {
+        Some(end) if !complete => &sample[..=end],
+        _ => sample,
+    };
+    let mut best: Option<(u8, Vec<StringRecord>, usize)> = None;
+    for &delimiter in &DELIMITERS {
+        let records = read(sample, delimiter, quote, comment);
+        let score = score(&records);
+        if score > best.as_ref().map_or(0, |(_, _, best)| *best) {
+            best = Some((delimiter, records, score));
+        }
+    }
+    match best {
+        Some((delimiter, records, _)) => Dialect {
+            delimiter,
+            has_headers: has_headers(&records),
+        },
+        // A single column
+        None => Dialect {
+            delimiter: b',',
+            has_headers: has_headers(&read(sample, b',', quote, comment)),
+        },
+    }
+}
+
+fn read(sample: &[u8], delimiter: u8, quote: u8, comment: Option<u8>) -> Vec<StringRecord> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .quote(quote)
+        .comment(comment)
+        .from_reader(sample)
+        .into_records()
+        .map_while(Result::ok)
+        .collect()
+}
+
+/// The number of records which have the most common number of fields, if it is above 1,
+/// weighted by this number.
+fn score(records: &[StringRecord]) -> usize {
+    let mut counts = std::collections::BTreeMap::new();
+    for record in records {
+        *counts.entry(record.len()).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .filter(|&(fields, _)| fields > 1)
+        .map(|(fields, records)| records * fields)
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(PartialEq)]
+enum Kind {
+    Number,
+    /// Another text, of this number of characters
+    Text(usize),
+}
+
+fn kind(field: &str) -> Kind {
+    if field.trim().parse::<f64>().is_ok() {
+        Kind::Number
+    } else {
+        Kind::Text(field.chars().count())
+    }
+}
+
+/// Each column whose fields after the first line are of the same kind, either numbers or texts
+/// of the same length, tells whether the first line is different. Without any such column, the
+/// first line is a header line, as by default.
+fn has_headers(records: &[StringRecord]) -> bool {
+    let (first, rest) = match records.split_first() {
+        Some((first, rest)) if !rest.is_empty() => (first, rest),
+        _ => return true,
+    };
+    let mut votes = 0;
+    for (column, header) in first.iter().enumerate() {
+        let mut kinds = rest.iter().map(|record| record.get(column).map(kind));
+        let column_kind = match kinds.next().flatten() {
+            Some(column_kind) => column_kind,
+            None => continue,
+        };
+        if !kinds.all(|other| other.as_ref() == Some(&column_kind)) {
+            continue;
+        }
+        if kind(header) == column_kind {
+            votes -= 1;
+        } else {
+            votes += 1;
+        }
+    }
+    votes >= 0
+}
@@ -0,0 +1,173 @@
+use anyhow::{anyhow, Context, Result};
+use csv::StringRecord;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::sync::mpsc;
+use std::thread;
+
+/// Rows read in advance from the result of the query.
+const QUERY_BUFFER_SIZE: usize = 1000;
+/// Rows inserted in each transaction.
+const COMMIT_INTERVAL: usize = 1000;
+
+/// Each of the records of a query result, after its header line.
+pub type Records = Box<dyn Iterator<Item = Result<StringRecord>>>;
+
+/// The names of the columns of the result of `sql` on the database at `path`, and its records.
+/// The query runs in its own thread, as its rows borrow the connection.
+pub fn query(path: &str, sql: &str) -> Result<(StringRecord, Records)> {
+    let connection = open_read_only(path)?;
+    let (sender, receiver) = mpsc::sync_channel(QUERY_BUFFER_SIZE);
+    let sql = sql.to_string();
+    let context = format!("Failed to query {}", path);
+    thread::spawn(move || {
+        let result = (|| -> rusqlite::Result<()> {
+            let mut statement = connection.prepare(&sql)?;
+            let headers = statement.column_names().into_iter().collect();
+            if sender.send(Ok(headers)).is_err() {
+                return Ok(());
+            }
+            let mut rows = statement.query([])?;
+            while let Some(row) = rows.next()? {
+                let record = (0..row.as_ref().column_count())
+                    .map(|i| row.get_ref(i).map(value))
+                    .collect::<rusqlite::Result<StringRecord>>()?;
+                // The receiver is dropped when the rest of the input is not read
+                if sender.send(Ok(record)).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(())
+        })();
+        if let Err(error) = result {
+            let _ = sender.send(Err(anyhow::Error::from(error).context(context)));
+        }
+    });
+    let mut records = receiver.into_iter();
+    let headers = records
+        .next()
+        .ok_or_else(|| anyhow!("Failed to query {}", path))??;
+    Ok((headers, Box::new(records)))
+}
+
+fn open_read_only(path: &str) -> Result<Connection> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    Connection::open_with_flags(path, flags).context(format!("Failed to open {}", path))
+}
+
+/// Text is written as is, null as an empty field, and blobs as text too.
+fn value(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(integer) => integer.to_string(),
+        ValueRef::Real(real) => real.to_string(),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+    }
+}
+
+/// Inserts the output records in a table, which is created with text columns if it does not
+/// exist. The rows are committed by batches, and the last ones on drop.
+pub struct SqliteWriter {
+    connection: Connection,
+    table: String,
+    /// Names of the columns, given by the header line or else by the first record
+    columns: Option<Vec<String>>,
+    uncommitted: usize,
+    path: String,
+}
+
+impl SqliteWriter {
+    pub fn new(path: &str, table: &str, headers: Option<&StringRecord>) -> Result<SqliteWriter> {
+        let connection = Connection::open(path).context(format!("Failed to open {}", path))?;
+        let mut writer = SqliteWriter {
+            connection,
+            table: table.to_string(),
+            columns: None,
+            uncommitted: 0,
+            path: path.to_string(),
+        };
+        if let Some(headers) = headers {
+            writer.create_table(headers.iter().map(String::from).collect())?;
+        }
+        Ok(writer)
+    }
+
+    pub fn write_record(&mut self, record: &StringRecord) -> Result<()> {
+        let columns = match &self.columns {
+            Some(columns) => columns,
+            None => {
+                let names = (1..=record.len()).map(|i| format!("column{}", i)).collect();
+                self.create_table(names)?;
+                self.columns.as_ref().unwrap()
+            }
+        };
+        if self.uncommitted == 0 {
+            self.connection.execute_batch("BEGIN")?;
+        }
+        let sql = format!(
+            "INSERT INTO {} ({}) VALUES ({})",
+            quote(&self.table),
+            columns
+                .iter()
+                .map(|name| quote(name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            vec!["?"; columns.len()].join(", ")
+        );
+        // Missing fields are null, and the extra ones are ignored
+        let values = (0..columns.len()).map(|i| record.get(i));
+        self.connection
+            .prepare_cached(&sql)
+            .and_then(|mut statement| statement.execute(rusqlite::params_from_iter(values)))
+            .context(format!("Failed to write {}", self.path))?;
+        self.uncommitted += 1;
+        if self.uncommitted >= COMMIT_INTERVAL {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> Result<()> {
+        self.commit()
+    }
+
+    fn create_table(&mut self, names: Vec<String>) -> Result<()> {
+        let sql = format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            quote(&self.table),
+            names
+                .iter()
+                .map(|name| format!("{} TEXT", quote(name)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        self.connection
+            .execute_batch(&sql)
+            .context(format!("Failed to write {}", self.path))?;
+        self.columns = Some(names);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if self.uncommitted > 0 {
+            self.uncommitted = 0;
+            self.connection
+                .execute_batch("COMMIT")
+                .context(format!("Failed to write {}", self.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteWriter {
+    fn drop(&mut self) {
+        let _ = self.commit();
+    }
+}
+
+/// Quotes an identifier, such as the name of a table or a column.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
@@ -0,0 +1,191 @@
+use crate::exec::Execution;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Durations below it, in milliseconds, are counted exactly, and the longer ones within 1/64.
+const EXACT_MILLIS: u64 = 1024;
+
+/// Buckets of each power of two above `EXACT_MILLIS`.
+const SUB_BUCKETS: u64 = 64;
+
+/// Statistics of a run, reported once all the rows are written.
+#[derive(Default)]
+pub struct Summary {
+    pub rows: u64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Rows rejected by --filter, which are neither successes nor failures
+    pub filtered: u64,
+    pub retries: u64,
+    /// Executions which exited with a non-zero status, whether or not it was a failure of their row
+    pub unsuccessful: u64,
+    /// Of the commands which ran until their exit
+    durations: Histogram,
+}
+
+impl Summary {
+    /// Adds a row, with the executions of its commands, including the failed one.
+    pub fn add<'a, I>(&mut self, executions: I, success: bool)
+    where
+        I: IntoIterator<Item = &'a Execution>,
+    {
+        self.rows += 1;
+        if success {
+            self.successes += 1;
+        } else {
+            self.failures += 1;
+        }
+        for execution in executions {
+            if !execution.status.success() {
+                self.unsuccessful += 1;
+            }
+            self.add_execution(execution);
+        }
+    }
+
+    /// Adds a row rejected by --filter, whose non-zero exit statuses are not failures.
+    pub fn add_filtered<'a, I>(&mut self, executions: I)
+    where
+        I: IntoIterator<Item = &'a Execution>,
+    {
+        self.rows += 1;
+        self.filtered += 1;
+        for execution in executions {
+            self.add_execution(execution);
+        }
+    }
+
+    fn add_execution(&mut self, execution: &Execution) {
+        self.retries += u64::from(execution.attempts.saturating_sub(1));
+        // Without any attempt, as with --dry-run, no command ran
+        if execution.attempts > 0 {
+            self.durations.add(execution.duration);
+        }
+    }
+
+    /// Human readable report, over several lines.
+    pub fn report(&self, wall_time: Duration) -> String {
+        let (p50, p95) = self.percentiles();
+        let filtered = match self.filtered {
+            0 => String::new(),
+            filtered => format!(", {} filtered out", filtered),
+        };
+        format!(
+            "
+Rows: {} ({} succeeded, {} failed{})
+Retries: {}
+Wall time: {:.3}s
+Command duration: p50 {}ms, p95 {}ms
+",
+            self.rows,
+            self.successes,
+            self.failures,
+            filtered,
+            self.retries,
+            wall_time.as_secs_f64(),
+            p50.as_millis(),
+            p95.as_millis()
+        )
+        .trim()
+        .to_string()
+    }
+
+    pub fn to_json(&self, wall_time: Duration) -> serde_json::Value {
+        let (p50, p95) = self.percentiles();
+        serde_json::json!({
+            "rows": self.rows,
+            "successes": self.successes,
+            "failures": self.failures,
+            "filtered": self.filtered,
+            "retries": self.retries,
+            "wall_time_ms": wall_time.as_millis() as u64,
+            "duration_p50_ms": p50.as_millis() as u64,
+            "duration_p95_ms": p95.as_millis() as u64,
+        })
+    }
+
+    /// Nearest-rank 50th and 95th percentiles of the durations, which are zero without any
+    /// execution.
+    fn percentiles(&self) -> (Duration, Duration) {
+        (self.durations.percentile(50), self.durations.percentile(95))
+    }
+}
+
+/// Counts of the durations, in buckets of a millisecond up to `EXACT_MILLIS`, and then of a
+/// fraction of each power of two, so that its size does not depend on the number of rows.
+#[derive(Default)]
+struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    fn add(&mut self, duration: Duration) {
+        let millis = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let bucket = Histogram::bucket(millis);
+        if self.counts.len() <= bucket {
+            self.counts.resize(bucket + 1, 0);
+        }
+        self.counts[bucket] += 1;
+        self.total += 1;
+    }
+
+    fn bucket(millis: u64) -> usize {
+        if millis < EXACT_MILLIS {
+            return millis as usize;
+        }
+        let power = u64::from(millis.ilog2());
+        let sub_bucket = (millis >> (power - SUB_BUCKETS.ilog2() as u64)) - SUB_BUCKETS;
+        (EXACT_MILLIS + (power - u64::from(EXACT_MILLIS.ilog2())) * SUB_BUCKETS + sub_bucket)
+            as usize
+    }
+
+    /// The shortest duration of `bucket`.
+    fn lower_bound(bucket: usize) -> Duration {
+        let bucket = bucket as u64;
+        if bucket < EXACT_MILLIS {
+            return Duration::from_millis(bucket);
+        }
+        let power = u64::from(EXACT_MILLIS.ilog2()) + (bucket - EXACT_MILLIS) / SUB_BUCKETS;
+        let sub_bucket = (bucket - EXACT_MILLIS) % SUB_BUCKETS;
+        Duration::from_millis((SUB_BUCKETS + sub_bucket) << (power - SUB_BUCKETS.ilog2() as u64))
+    }
+
+    /// Nearest-rank percentile, to the lower bound of its bucket.
+    fn percentile(&self, percent: u64) -> Duration {
+        let rank = (percent * self.total).div_ceil(100).max(1);
+        let mut count = 0;
+        for (bucket, bucket_count) in self.counts.iter().enumerate() {
+            count += bucket_count;
+            if count >= rank {
+                return Histogram::lower_bound(bucket);
+            }
+        }
+        Duration::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50), Duration::default());
+        for millis in [3, 1, 2, 1500, 36_000_000] {
+            histogram.add(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.percentile(50), Duration::from_millis(3));
+        assert_eq!(histogram.percentile(80), Duration::from_millis(1488));
+        assert_eq!(histogram.percentile(95), Duration::from_millis(35_651_584));
+
+        histogram.add(Duration::MAX);
+        assert_eq!(histogram.counts.len(), 4480);
+        for millis in [0, 1023, 1024, 1040, 65_535, u64::MAX] {
+            let bound = Histogram::lower_bound(Histogram::bucket(millis));
+            assert!(bound <= Duration::from_millis(millis));
+            assert!(Duration::from_millis(millis) - bound <= Duration::from_millis(millis / 64));
+        }
+    }
+}
@@ -0,0 +1,473 @@
+use crate::delimiter;
+use crate::printf;
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use regex::{Captures, Regex};
+
+/// A command argument, parsed once and expanded for each record.
+pub struct Template {
+    segments: Vec<Segment>,
+    /// Whether a missing value is an error, instead of being empty
+    strict: bool,
+}
+
+enum Segment {
+    Literal(String),
+    Reference(Reference),
+}
+
+/// A value of the record, or of its context.
+struct Reference {
+    /// As written in the argument
+    text: String,
+    source: Source,
+    /// Replaces an empty or missing value
+    default: Option<String>,
+    /// Offset and length of the part of the value which is kept, in characters
+    slice: Option<(usize, Option<usize>)>,
+    /// Applied in order, after the default value and the slice
+    filters: Vec<Filter>,
+}
+
+#[derive(Clone)]
+enum Filter {
+    Lower,
+    Upper,
+    /// Removes the leading and trailing whitespace
+    Trim,
+    /// Percent-encodes the characters other than the unreserved ones of URLs
+    UrlEncode,
+    /// Quotes the value for a shell, such as the one of --shell
+    Shell,
+    /// Formats the value like printf, such as %05d or %.2f
+    Format(printf::Spec),
+    /// Parses a date, and formats it with this strftime format
+    Date(String),
+}
+
+enum Source {
+    /// Column position, beginning at 1
+    Position(usize),
+    /// Column position from the end, beginning at 1 for the last field
+    FromEnd(usize),
+    /// Column index in the record, resolved from the header row
+    Column(usize),
+    /// Index of the result of an earlier command
+    Result(usize),
+    /// The whole record, as a line of the input
+    Record(Dialect),
+    /// Number of fields of the record
+    FieldCount,
+    /// Path of the input file of the record
+    File,
+    /// Environment variable of csv-exec, read at each expansion
+    Env(String),
+}
+
+/// How the input is written, for the references to whole records.
+#[derive(Clone)]
+pub struct Dialect {
+    pub delimiter: u8,
+    /// Of several characters, in which case `delimiter` is `delimiter::SEPARATOR`
+    pub long_delimiter: Option<String>,
+    pub quote: u8,
+    pub escape: Option<u8>,
+    /// Whether the quotes are written, or are a character like the others
+    pub quoting: bool,
+}
+
+/// What the references of a template can name.
+pub struct Scope<'a> {
+    pub headers: Option<&'a StringRecord>,
+    /// Names of the results of the earlier commands
+    pub results: &'a [String],
+    /// How the whole records are written
+    pub dialect: &'a Dialect,
+    /// Index of the column which CELL is, if any
+    pub cell: Option<usize>,
+    /// Whether the unknown references and the missing values are errors
+    pub strict: bool,
+}
+
+impl Template {
+    /// Parses `arg` using `arg_regex`.
+    /// The first capturing group which participates in a match is the column reference:
+    /// either a position, or a header name. Names which are not in the headers are left
+    /// untouched. Position 0 is the whole record, and the negative positions count from the end
+    /// of the record. The references can also be either RESULT<N>, N beginning at 1, or
+    /// Result:<NAME>, for the results of the earlier commands.
+    /// FILE and FILENAME are the path of the input file, and CELL the column of the scope,
+    /// unless they are header names, and env:<NAME> is an environment variable. A reference
+    /// followed by :-<DEFAULT> is replaced by DEFAULT when its value is empty or missing, and by
+    /// :<OFFSET>:<LENGTH> by a part of it. The filters |lower, |upper, |trim, |urlencode, |sh,
+    /// the printf formats such as |%05d and the date formats such as |date:%Y-%m-%d are then
+    /// applied to it.
+    /// If the scope is strict, the unknown names and the positions after the last header are
+    /// errors, and so are the missing values at expansion, unless there is a default value.
+    pub fn compile(arg: &str, arg_regex: &Regex, scope: &Scope) -> Result<Template> {
+        let strict = scope.strict;
+        let mut segments = vec![];
+        let mut last_end = 0;
+        for caps in arg_regex.captures_iter(arg) {
+            let whole = caps.get(0).unwrap();
+            if whole.start() > last_end {
+                segments.push(Segment::Literal(arg[last_end..whole.start()].to_string()));
+            }
+            last_end = whole.end();
+            let unknown = || anyhow!("Unknown reference {} in {}", whole.as_str(), arg);
+            segments.push(match reference(&caps) {
+                None => Segment::Literal(String::new()),
+                Some(reference) => match parse(reference, scope) {
+                    Some(reference) if strict && !reference.source.fits(scope.headers) => {
+                        return Err(unknown())
+                    }
+                    Some(reference) => Segment::Reference(Reference {
+                        text: whole.as_str().to_string(),
+                        ..reference
+                    }),
+                    None if strict => return Err(unknown()),
+                    None => Segment::Literal(whole.as_str().to_string()),
+                },
+            });
+        }
+        if last_end < arg.len() {
+            segments.push(Segment::Literal(arg[last_end..].to_string()));
+        }
+        Ok(Template { segments, strict })
+    }
+
+    /// Whether the expansion depends on the record.
+    pub fn has_references(&self) -> bool {
+        self.segments.iter().any(|segment| match segment {
+            Segment::Literal(_) => false,
+            Segment::Reference(reference) => !matches!(reference.source, Source::Env(_)),
+        })
+    }
+
+    /// Expands the template for a record of the input file at `file`. It fails if a value cannot
+    /// be formatted.
+    pub fn expand(&self, record: &StringRecord, file: &str) -> Result<String> {
+        self.expand_with_results(record, file, &[])
+    }
+
+    /// Expands the template, `results` being the results of the earlier commands.
+    pub fn expand_with_results(
+        &self,
+        record: &StringRecord,
+        file: &str,
+        results: &[String],
+    ) -> Result<String> {
+        let mut expanded = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => expanded.push_str(text),
+                Segment::Reference(reference) => {
+                    expanded.push_str(&reference.value(record, file, results, self.strict)?)
+                }
+            }
+        }
+        Ok(expanded)
+    }
+}
+
+impl Reference {
+    fn value(
+        &self,
+        record: &StringRecord,
+        file: &str,
+        results: &[String],
+        strict: bool,
+    ) -> Result<String> {
+        let value = self.source.value(record, file, results);
+        let value = match (&self.default, value) {
+            (None, None) if strict => return Err(anyhow!("Reference {} has no value", self.text)),
+            (Some(default), None) => default.clone(),
+            (Some(default), Some(value)) if value.is_empty() => default.clone(),
+            (_, value) => value.unwrap_or_default(),
+        };
+        let value = match self.slice {
+            Some((offset, length)) => {
+                let characters = value.chars().skip(offset);
+                characters.take(length.unwrap_or(usize::MAX)).collect()
+            }
+            None => value,
+        };
+        self.filters
+            .iter()
+            .try_fold(value, |value, filter| filter.apply(&value))
+    }
+}
+
+impl Filter {
+    fn parse(name: &str) -> Option<Filter> {
+        match name {
+            "lower" => Some(Filter::Lower),
+            "upper" => Some(Filter::Upper),
+            "trim" => Some(Filter::Trim),
+            "urlencode" => Some(Filter::UrlEncode),
+            "sh" => Some(Filter::Shell),
+            _ => match name.strip_prefix("date:") {
+                // An invalid format would fail at each expansion
+                Some(format) if !StrftimeItems::new(format).any(|item| item == Item::Error) => {
+                    Some(Filter::Date(format.to_string()))
+                }
+                Some(_) => None,
+                None => printf::Spec::parse(name).map(Filter::Format),
+            },
+        }
+    }
+
+    fn apply(&self, value: &str) -> Result<String> {
+        Ok(match self {
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::UrlEncode => url_encode(value),
+            Filter::Shell => shell_words::quote(value).into_owned(),
+            Filter::Format(spec) => return spec.format(value),
+            Filter::Date(format) => match parse_date(value.trim()) {
+                Some(date) => date.format(format).to_string(),
+                None => {
+                    return Err(anyhow!(
+                        "Value {} must be a date for date:{}",
+                        value,
+                        format
+                    ))
+                }
+            },
+        })
+    }
+}
+
+/// Percent-encodes the characters other than the unreserved ones of URLs.
+pub fn url_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(char::from(byte))
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Formats of the dates with a time and without a time zone, which is then UTC.
+const DATE_TIME_FORMATS: [&str; 5] = [
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M:%S",
+];
+
+/// Formats of the dates without a time, which is then midnight UTC.
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%Y/%m/%d", "%d.%m.%Y", "%d %b %Y"];
+
+/// Parses a date in RFC 3339, RFC 2822 or one of the common formats, or a Unix timestamp in
+/// seconds.
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(date);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+        return Some(date);
+    }
+    let utc = |date: NaiveDateTime| date.and_utc().fixed_offset();
+    if let Some(date) = DATE_TIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    {
+        return Some(utc(date));
+    }
+    if let Some(date) = DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+    {
+        return Some(utc(date.and_time(chrono::NaiveTime::MIN)));
+    }
+    let timestamp = value.parse().ok()?;
+    DateTime::from_timestamp(timestamp, 0).map(|date| date.fixed_offset())
+}
+
+impl Source {
+    /// Whether a position is one of the headers, if they are known.
+    fn fits(&self, headers: Option<&StringRecord>) -> bool {
+        match (self, headers) {
+            (Source::Position(position) | Source::FromEnd(position), Some(headers)) => {
+                *position <= headers.len()
+            }
+            _ => true,
+        }
+    }
+
+    /// The value, or `None` if it is missing.
+    fn value(&self, record: &StringRecord, file: &str, results: &[String]) -> Option<String> {
+        match self {
+            // Column position begins at 1
+            Source::Position(position) => position
+                .checked_sub(1)
+                .and_then(|i| record.get(i))
+                .map(String::from),
+            Source::FromEnd(position) => record
+                .len()
+                .checked_sub(*position)
+                .and_then(|i| record.get(i))
+                .map(String::from),
+            Source::Column(index) => record.get(*index).map(String::from),
+            Source::Result(index) => results.get(*index).cloned(),
+            Source::Record(dialect) => Some(line(record, dialect)),
+            Source::FieldCount => Some(record.len().to_string()),
+            Source::File => Some(file.to_string()),
+            // An undefined variable is missing, like in a shell
+            Source::Env(name) => std::env::var_os(name).map(|value| value.to_string_lossy().into()),
+        }
+    }
+}
+
+/// Parses a reference, which is a value followed by filters, each one after a |. A header name
+/// which contains a | is a value.
+fn parse(reference: &str, scope: &Scope) -> Option<Reference> {
+    let filtered = reference.split_once('|').and_then(|(value, filters)| {
+        let filters = filters
+            .split('|')
+            .map(Filter::parse)
+            .collect::<Option<_>>()?;
+        Some((value, filters))
+    });
+    match filtered {
+        Some((value, filters)) if source(reference, scope).is_none() => Some(Reference {
+            filters,
+            ..parse_value(value, scope)?
+        }),
+        _ => parse_value(reference, scope),
+    }
+}
+
+/// Parses a value, which is a source, or a source followed by :-<DEFAULT>, or by :<OFFSET> or
+/// :<OFFSET>:<LENGTH> to keep a part of it.
+fn parse_value(reference: &str, scope: &Scope) -> Option<Reference> {
+    let source = |name| source(name, scope);
+    let plain = |source| Reference {
+        text: reference.to_string(),
+        source,
+        default: None,
+        slice: None,
+        filters: vec![],
+    };
+    if let Some(source) = source(reference) {
+        return Some(plain(source));
+    }
+    if let Some((name, default)) = reference.split_once(":-") {
+        if let Some(source) = source(name) {
+            let default = Some(default.to_string());
+            return Some(Reference {
+                default,
+                ..plain(source)
+            });
+        }
+    }
+    let (rest, last) = reference.rsplit_once(':')?;
+    let last = last.parse().ok()?;
+    if let Some((name, offset)) = rest.rsplit_once(':') {
+        if let (Some(source), Ok(offset)) = (source(name), offset.parse()) {
+            let slice = Some((offset, Some(last)));
+            return Some(Reference {
+                slice,
+                ..plain(source)
+            });
+        }
+    }
+    let slice = Some((last, None));
+    Some(Reference {
+        slice,
+        ..plain(source(rest)?)
+    })
+}
+
+fn source(name: &str, scope: &Scope) -> Option<Source> {
+    if name == "#" {
+        return Some(Source::FieldCount);
+    }
+    match name.parse::<usize>() {
+        Ok(0) => return Some(Source::Record(scope.dialect.clone())),
+        Ok(position) => return Some(Source::Position(position)),
+        Err(_) => {}
+    }
+    let from_end = name
+        .strip_prefix('-')
+        .and_then(|position| position.parse().ok());
+    if let Some(position) = from_end.filter(|&position| position > 0) {
+        return Some(Source::FromEnd(position));
+    }
+    let headers = scope.headers;
+    if let Some(index) = headers.and_then(|h| h.iter().position(|header| header == name)) {
+        return Some(Source::Column(index));
+    }
+    if let Some(index) = result_index(name, scope.results) {
+        return Some(Source::Result(index));
+    }
+    if name == "FILE" || name == "FILENAME" {
+        return Some(Source::File);
+    }
+    if let Some(index) = scope.cell.filter(|_| name == "CELL") {
+        return Some(Source::Column(index));
+    }
+    // The names of the variables do not contain the colon which begins a default value
+    name.strip_prefix("env:")
+        .filter(|name| !name.contains(':'))
+        .map(|name| Source::Env(name.to_string()))
+}
+
+/// Writes `record` without a line terminator.
+fn line(record: &StringRecord, dialect: &Dialect) -> String {
+    let mut builder = csv::WriterBuilder::new();
+    builder
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .double_quote(dialect.escape.is_none());
+    if let Some(escape) = dialect.escape {
+        builder.escape(escape);
+    }
+    if !dialect.quoting {
+        builder.quote_style(csv::QuoteStyle::Never);
+    }
+    let long_delimiter = dialect.long_delimiter.as_deref();
+    let mut writer = delimiter::Writer::new(&builder, long_delimiter, false, vec![]);
+    // Note: writing to memory does not fail, and the fields are UTF-8
+    let _ = writer.write_record(record);
+    let line = writer.into_inner().unwrap_or_default();
+    let line = String::from_utf8(line).unwrap_or_default();
+    line.strip_suffix('\n').unwrap_or(&line).to_string()
+}
+
+/// Resolves a column given by its position (beginning at 1) or its header name,
+/// to its index in the records.
+pub fn column_index(column: &str, headers: Option<&StringRecord>) -> Result<usize> {
+    let index = match column.parse::<usize>() {
+        Ok(position) => position.checked_sub(1),
+        Err(_) => headers.and_then(|h| h.iter().position(|name| name == column)),
+    };
+    match (index, headers) {
+        (Some(index), Some(headers)) if index < headers.len() => Ok(index),
+        (Some(index), None) => Ok(index),
+        _ => Err(anyhow!("Column {} not found", column)),
+    }
+}
+
+fn result_index(reference: &str, results: &[String]) -> Option<usize> {
+    if let Some(name) = reference.strip_prefix("Result:") {
+        return results.iter().position(|result| result == name);
+    }
+    match reference.strip_prefix("RESULT")?.parse::<usize>() {
+        Ok(position) if position >= 1 && position <= results.len() => Some(position - 1),
+        _ => None,
+    }
+}
+
+fn reference<'t>(caps: &Captures<'t>) -> Option<&'t str> {
+    caps.iter().skip(1).flatten().next().map(|m| m.as_str())
+}
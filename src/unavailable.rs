@@ -0,0 +1,71 @@
+//! The modules of the optional features, when they are not enabled, which give an error as soon
+//! as they are used.
+
+#[cfg(not(feature = "parquet"))]
+pub mod parquet {
+    use anyhow::{anyhow, Result};
+    use csv::StringRecord;
+
+    pub type Records = Box<dyn Iterator<Item = Result<StringRecord>>>;
+
+    pub fn read(_path: &str) -> Result<(StringRecord, Records)> {
+        Err(unavailable())
+    }
+
+    pub fn count_rows(_path: &str) -> Result<u64> {
+        Err(unavailable())
+    }
+
+    pub fn unavailable() -> anyhow::Error {
+        anyhow!("The parquet format requires csv-exec to be built with the parquet feature")
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+pub mod sqlite {
+    use anyhow::{anyhow, Result};
+    use csv::StringRecord;
+
+    pub type Records = Box<dyn Iterator<Item = Result<StringRecord>>>;
+
+    pub fn query(_path: &str, _sql: &str) -> Result<(StringRecord, Records)> {
+        Err(unavailable())
+    }
+
+    /// Never created.
+    pub enum SqliteWriter {}
+
+    impl SqliteWriter {
+        pub fn new(
+            _path: &str,
+            _table: &str,
+            _headers: Option<&StringRecord>,
+        ) -> Result<SqliteWriter> {
+            Err(unavailable())
+        }
+
+        pub fn write_record(&mut self, _record: &StringRecord) -> Result<()> {
+            match *self {}
+        }
+
+        pub fn finish(&mut self) -> Result<()> {
+            match *self {}
+        }
+    }
+
+    fn unavailable() -> anyhow::Error {
+        anyhow!("SQLite requires csv-exec to be built with the sqlite feature")
+    }
+}
+
+#[cfg(not(feature = "xlsx"))]
+pub mod xlsx {
+    use anyhow::{anyhow, Result};
+    use csv::StringRecord;
+
+    pub fn read_sheet(_path: &str, _sheet: Option<&str>) -> Result<Vec<StringRecord>> {
+        Err(anyhow!(
+            "The xlsx input requires csv-exec to be built with the xlsx feature"
+        ))
+    }
+}
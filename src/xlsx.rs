@@ -0,0 +1,41 @@
+use anyhow::{anyhow, Context, Result};
+use calamine::{Data, Reader};
+use csv::StringRecord;
+
+/// Rows of the sheet named `sheet`, or of the first sheet, of the workbook at `path`.
+pub fn read_sheet(path: &str, sheet: Option<&str>) -> Result<Vec<StringRecord>> {
+    let mut workbook =
+        calamine::open_workbook_auto(path).context(format!("Failed to open {}", path))?;
+    let range = match sheet {
+        Some(sheet) => workbook.worksheet_range(sheet),
+        None => workbook
+            .worksheet_range_at(0)
+            .ok_or_else(|| anyhow!("No sheet in {}", path))?,
+    }
+    .context(format!("Failed to read {}", path))?;
+    Ok(range
+        .rows()
+        .map(|row| row.iter().map(cell).collect())
+        .collect())
+}
+
+/// Dates are written in ISO 8601, and the numbers without a fractional part as integers.
+fn cell(data: &Data) -> String {
+    match data {
+        Data::Empty => String::new(),
+        Data::String(string) | Data::DateTimeIso(string) | Data::DurationIso(string) => {
+            string.clone()
+        }
+        Data::Float(float) if float.fract() == 0.0 && float.abs() < 1e15 => {
+            format!("{}", *float as i64)
+        }
+        Data::DateTime(date_time) if !date_time.is_duration() => match date_time.as_datetime() {
+            Some(date_time) if date_time.time() == chrono::NaiveTime::MIN => {
+                date_time.format("%Y-%m-%d").to_string()
+            }
+            Some(date_time) => date_time.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            None => date_time.as_f64().to_string(),
+        },
+        data => data.to_string(),
+    }
+}
@@ -18,7 +18,7 @@ Id,Dir,Result
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1"])
+        .args(["echo $2/$1"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -42,7 +42,7 @@ Id,Dir,Result
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo €2/€1", "--arg-regex", "€([0-9]+)"])
+        .args(["echo €2/€1", "--arg-regex", "€([0-9]+)"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -66,7 +66,7 @@ Id;Dir;Result
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "-d", ";"])
+        .args(["echo $2/$1", "-d", ";"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -90,14 +90,14 @@ Id\tDir\tResult
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "-d", "\\t"])
+        .args(["echo $2/$1", "-d", "\\t"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "-d", "\t"])
+        .args(["echo $2/$1", "-d", "\t"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -121,7 +121,7 @@ Id;Dir;Result
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "-d", "\\t", "--out-delimiter", ";"])
+        .args(["echo $2/$1", "-d", "\\t", "--out-delimiter", ";"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -143,7 +143,7 @@ fn test_no_headers() {
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "--no-headers"])
+        .args(["echo $2/$1", "--no-headers"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
@@ -167,8 +167,3094 @@ Id,Dir,A Result
 
     Command::cargo_bin("csv-exec")
         .unwrap()
-        .args(&["echo $2/$1", "--new-column-name", "A Result"])
+        .args(["echo $2/$1", "--new-column-name", "A Result"])
         .write_stdin(input)
         .assert()
         .stdout(expected_output);
 }
+
+#[test]
+fn test_header_name_substitution() {
+    let input = r#"
+Id,Target Dir
+24,example.com/a
+68,example.com/b
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Target Dir,Result
+24,example.com/a,example.com/a/24 $Unknown
+68,example.com/b,example.com/b/68 $Unknown
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([r#"echo "${Target Dir}/$Id" $Unknown"#])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_jobs() {
+    let input = r#"
+Id,Delay
+1,0.3
+2,0.1
+3,0.2
+4,0
+"#
+    .trim_start();
+
+    let start = std::time::Instant::now();
+    let output = Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'sleep $2; echo $1'", "--jobs", "4"])
+        .write_stdin(input)
+        .output()
+        .unwrap();
+    assert!(start.elapsed() < std::time::Duration::from_millis(600));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.remove(0), "Id,Delay,Result");
+    lines.sort();
+    assert_eq!(lines, vec!["1,0.3,1", "2,0.1,2", "3,0.2,3", "4,0,4"]);
+}
+
+#[test]
+fn test_jobs_preserve_order() {
+    let input = r#"
+Id,Delay
+1,0.3
+2,0.1
+3,0.2
+4,0
+5,0.1
+6,0
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Delay,Result
+1,0.3,1
+2,0.1,2
+3,0.2,3
+4,0,4
+5,0.1,5
+6,0,6
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'sleep $2; echo $1'", "-j", "3"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_unordered() {
+    let input = r#"
+Id,Delay
+1,0.4
+2,0
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Delay,Result
+2,0,2
+1,0.4,1
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'sleep $2; echo $1'", "-j", "2", "--unordered"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_shell() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+68,example.com/b
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,example.com/a,EXAMPLE.COM/A/24
+68,example.com/b,EXAMPLE.COM/B/68
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $2/$1 | tr a-z A-Z && true", "--shell"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_shell_path() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,example.com/a,EXAMPLE.COM/A
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["d=$2; echo ${d^^}", "--shell-path", "bash --norc"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_timeout() {
+    let input = r#"
+Id,Delay
+1,0
+2,5
+"#
+    .trim_start();
+
+    let start = std::time::Instant::now();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sleep $2", "--timeout", "0.5"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("timed out"));
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sleep $2", "--timeout", "1e30"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr("Error: Value 1e30 is too large\n");
+}
+
+#[test]
+fn test_max_runtime() {
+    let input = r#"
+Id,Delay
+1,0.4
+2,0.4
+3,0.4
+4,0.4
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Delay,Result
+1,0.4,1
+2,0.4,2
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'sleep $2; echo $1'", "--max-runtime", "0.6s"])
+        .write_stdin(input)
+        .assert()
+        .code(124)
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--max-runtime", "1e30h"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr("Error: Value 1e30h is too large\n");
+
+    // Beyond the range of the clock, without a deadline
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--max-runtime", "1e19"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,1\n");
+}
+
+#[test]
+fn test_exit_code_column() {
+    let input = r#"
+Id,Code
+1,0
+2,3
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Code,Result,Status
+1,0,,0
+2,3,,3
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'exit $2'", "--exit-code-column", "Status"])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_stderr_column() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result,Errors
+24,example.com/a,24,warning: example.com/a
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'echo $1; echo warning: $2 >&2'",
+            "--stderr-column",
+            "Errors",
+        ])
+        .write_stdin(input)
+        .assert()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_duration_column() {
+    let input = r#"
+Id,Delay
+1,0.2
+"#
+    .trim_start();
+
+    let output = Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sleep $2", "--duration-column", "Duration"])
+        .write_stdin(input)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "Id,Delay,Result,Duration");
+    let duration: u64 = lines[1].strip_prefix("1,0.2,,").unwrap().parse().unwrap();
+    assert!((200..2000).contains(&duration));
+}
+
+#[test]
+fn test_started_and_attempts_columns() {
+    let input = r#"
+Id
+1
+"#
+    .trim_start();
+
+    let output = Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "true",
+            "--started-column",
+            "Started",
+            "--attempts-column",
+            "Attempts",
+        ])
+        .write_stdin(input)
+        .output()
+        .unwrap();
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "Id,Result,Started,Attempts");
+    let fields = lines[1].split(',').collect::<Vec<_>>();
+    assert_eq!(fields[3], "1");
+    // e.g. 2020-03-01T12:34:56.789Z
+    assert_eq!(fields[2].len(), 24);
+    assert!(fields[2].ends_with('Z'));
+    assert_eq!(&fields[2][10..11], "T");
+}
+
+#[test]
+fn test_on_error() {
+    let input = r#"
+Id,Code
+1,0
+2,3
+3,0
+"#
+    .trim_start();
+
+    let command = "sh -c 'echo $1; exit $2'";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([command, "--on-error", "abort"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stdout("Id,Code,Result\n1,0,1\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([command, "--on-error", "skip-row"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Code,Result\n1,0,1\n3,0,3\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            command,
+            "--on-error",
+            "empty",
+            "--exit-code-column",
+            "Status",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Code,Result,Status\n1,0,1,0\n2,3,,3\n3,0,3,0\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["missing-command-csv-exec", "--on-error", "placeholder=N/A"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Code,Result\n1,0,N/A\n2,3,N/A\n3,0,N/A\n");
+}
+
+#[test]
+fn test_retries() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-retries-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = format!("Counter\n{}/counter\n", dir.display());
+
+    // Fails until the third attempt
+    let command = "sh -c 'echo >> $1; test $(wc -l < $1) -ge 3 && echo done'";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            command,
+            "--retries",
+            "3",
+            "--retry-delay",
+            "0.01",
+            "--attempts-column",
+            "Attempts",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!(
+            "Counter,Result,Attempts\n{}/counter,done,3\n",
+            dir.display()
+        ));
+
+    // The delay of the next retry overflows, after the last one
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "false",
+            "--retries",
+            "1",
+            "--retry-delay",
+            "0.01",
+            "--retry-backoff",
+            "1e300",
+            "--attempts-column",
+            "Attempts",
+        ])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result,Attempts\n1,,2\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_retry_on_exit_codes() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-retry-codes-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = format!("Counter,Code\n{0}/a,75\n{0}/b,2\n", dir.display());
+
+    let command = "sh -c 'echo >> $1; exit $2'";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            command,
+            "--retries",
+            "2",
+            "--retry-delay",
+            "0.01",
+            "--retry-on-exit-codes",
+            "1,75",
+            "--attempts-column",
+            "Attempts",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!(
+            "Counter,Code,Result,Attempts\n{0}/a,75,,3\n{0}/b,2,,1\n",
+            dir.display()
+        ));
+
+    // The timeouts are retried too
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'echo >> $1; exec sleep 5'",
+            "--timeout",
+            "0.1",
+            "--retries",
+            "1",
+            "--retry-delay",
+            "0.01",
+            "--retry-on-exit-codes",
+            "75",
+            "--on-error",
+            "empty",
+        ])
+        .write_stdin(format!("Counter\n{}/c\n", dir.display()))
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(dir.join("c")).unwrap(), "\n\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rate() {
+    let input = r#"
+Id
+1
+2
+3
+4
+5
+"#
+    .trim_start();
+
+    let start = std::time::Instant::now();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--rate", "20/s", "--jobs", "5"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,1\n2,2\n3,3\n4,4\n5,5\n");
+    // The 5 launches are spaced by 50ms
+    assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--rate", "1e-20"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr("Error: Value 1e-20 is too low\n");
+}
+
+#[test]
+fn test_memoize() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-memoize-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let counter = dir.join("counter");
+    let input = "Key\na\nb\na\na\n";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            &format!("sh -c 'echo >> {}; echo $1$1'", counter.display()),
+            "--memoize",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Key,Result\na,aa\nb,bb\na,aa\na,aa\n");
+    assert_eq!(
+        std::fs::read_to_string(&counter).unwrap().lines().count(),
+        2
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_unique() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-unique-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let counter = dir.join("counter");
+    let input = "Key\na\nb\na\na\n";
+
+    // The identical commands run at the same time, and fail
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            &format!(
+                "sh -c 'sleep 0.2; echo >> {}; echo $1$1; exit 1'",
+                counter.display()
+            ),
+            "--unique",
+            "--jobs",
+            "4",
+            "--exit-code-column",
+            "Code",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Key,Result,Code\na,aa,1\nb,bb,1\na,aa,1\na,aa,1\n");
+    assert_eq!(
+        std::fs::read_to_string(&counter).unwrap().lines().count(),
+        2
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_coprocess() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+68,"example.com/b,c"
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,example.com/a,"got 24,example.com/a"
+68,"example.com/b,c","got 68,""example.com/b,c"""
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"sh -c 'while read -r line; do echo "got $line"; done'"#,
+            "--coprocess",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    // A coprocess which does not exit once its input is closed is killed
+    let start = std::time::Instant::now();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"sh -c 'while read -r line; do echo "got $line"; done; exec sleep 60'"#,
+            "--coprocess",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+    assert!(start.elapsed() < std::time::Duration::from_secs(30));
+}
+
+#[test]
+fn test_batch_size() {
+    let input = r#"
+Id,Dir
+1,a
+2,b
+3,c
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+1,a,a/1
+2,b,b/2
+3,c,c/3
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([r"printf '%s/%s\n' $2 $1", "--batch-size", "2"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sed s/,/-/", "--batch-size", "2", "--batch-input", "stdin"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Dir,Result\n1,a,1-a\n2,b,2-b\n3,c,3-c\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--batch-size", "2"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Expected 2 lines"));
+}
+
+#[test]
+fn test_stdin_record_csv() {
+    let input = r#"
+Id,Dir
+24,"example.com/a,b"
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,"example.com/a,b","24: 24,""example.com/a,b"""
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sed s/^/$1:\\ /", "--stdin-record", "csv"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_stdin_record_json() {
+    let input = r#"
+Id,Dir
+24,"example.com/""a"""
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,"example.com/""a""","{""Id"":""24"",""Dir"":""example.com/\""a\""""}"
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["cat", "--stdin-record", "json"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["cat", "--stdin-record", "json", "--no-headers"])
+        .write_stdin("a,b\n")
+        .assert()
+        .success()
+        .stdout(r#"a,b,"{""1"":""a"",""2"":""b""}""#.to_string() + "\n");
+}
+
+#[test]
+fn test_stdin_field() {
+    let input = r#"
+Id,Text
+1,"a
+b
+c"
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Text,Result
+1,"a
+b
+c",A B C
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'tr a-z A-Z | paste -s -d \" \"'",
+            "--stdin-field",
+            "Text",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["cat", "--stdin-field", "Missing"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Column Missing not found"));
+}
+
+#[test]
+fn test_field_env() {
+    let input = r#"
+Id,Target url
+24,example.com/a
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Target url,Result
+24,example.com/a,example.com/a/24 example.com/a
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"sh -c 'echo "$CSV_TARGET_URL/$CSV_ID $CSV_2"'"#,
+            "--field-env",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_env() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id,Dir,Result
+24,example.com/a,https://example.com/a/24
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"sh -c 'echo "$SCHEME://$TARGET"'"#,
+            "--env",
+            "SCHEME=https",
+            "--env",
+            "TARGET=$2/$Id",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+}
+
+#[test]
+fn test_clean_env() {
+    let input = r#"
+Id
+24
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"sh -c 'echo "[$SECRET] [$KEPT] [$ADDED]"'"#,
+            "--clean-env",
+            "--pass-env",
+            "KEPT",
+            "--pass-env",
+            "PATH",
+            "--env",
+            "ADDED=$1",
+        ])
+        .env("SECRET", "hidden")
+        .env("KEPT", "kept")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n24,[] [kept] [24]\n");
+}
+
+#[test]
+fn test_cwd() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-cwd-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("alpha")).unwrap();
+    std::fs::create_dir_all(dir.join("beta")).unwrap();
+    std::fs::write(dir.join("alpha/one.txt"), "").unwrap();
+    std::fs::write(dir.join("beta/two.txt"), "").unwrap();
+    let input = r#"
+Repository
+alpha
+beta
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["ls", "--cwd", &format!("{}/$1", dir.display())])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Repository,Result\nalpha,one.txt\nbeta,two.txt\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_replace_column() {
+    let input = r#"
+Name,Country
+ alice ,fr
+BOB,uk
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'echo $1 | tr A-Z a-z'",
+            "--replace-column",
+            "Name",
+            "--exit-code-column",
+            "Code",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Name,Country,Code\nalice,fr,0\nbob,uk,0\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--replace-column", "Missing"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Column Missing not found"));
+}
+
+#[test]
+fn test_column_position() {
+    let input = r#"
+Id,Name,Country
+1,alice,fr
+"#
+    .trim_start();
+
+    for args in [
+        ["--column-position", "2"],
+        ["--before", "Name"],
+        ["--after", "1"],
+    ] {
+        Command::cargo_bin("csv-exec")
+            .unwrap()
+            .args(["echo $Name", "--exit-code-column", "Code"])
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("Id,Result,Code,Name,Country\n1,alice,0,alice,fr\n");
+    }
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--column-position", "5"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Value 5 must be a column position",
+        ));
+}
+
+#[test]
+fn test_multiple_exec() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+68,example.com/b
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $1",
+            "--exec",
+            "basename $2",
+            "-e",
+            "dirname $2",
+            "--new-column-name",
+            "Copy",
+            "--new-column-name",
+            "Base",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "Id,Dir,Copy,Base,Result3\n24,example.com/a,24,a,example.com\n68,example.com/b,68,b,example.com\n",
+        );
+
+    // The next commands of a record are not run once one of them failed
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "--exec",
+            "sh -c 'exit $1'",
+            "--exec",
+            "echo run",
+            "--on-error",
+            "placeholder=ERR",
+            "--exit-code-column",
+            "Code",
+        ])
+        .write_stdin("Status\n0\n3\n")
+        .assert()
+        .success()
+        .stdout("Status,Result1,Result2,Code\n0,,run,0\n3,ERR,ERR,3\n");
+}
+
+#[test]
+fn test_chained_exec() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "basename $Dir",
+            "--exec",
+            "echo $RESULT1-$Id",
+            "--exec",
+            "echo ${Result:Base} $RESULT2 $RESULT3",
+            "--new-column-name",
+            "Base",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Dir,Base,Result2,Result3\n24,example.com/a,a,a-24,a a-24 $RESULT3\n");
+}
+
+#[test]
+fn test_split_output() {
+    let input = r#"
+City
+paris
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "printf '48.85\\t2.35\\n'",
+            "--split-output",
+            "\\t",
+            "--result-headers",
+            "Lat,Lon",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("City,Lat,Lon\nparis,48.85,2.35\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo 1,2,3",
+            "--split-output",
+            ",",
+            "--result-headers",
+            "A,B",
+            "--on-error",
+            "placeholder=ERR",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("City,A,B\nparis,ERR,ERR\n")
+        .stderr(predicates::str::contains(
+            "Expected 2 fields in the result, got 3",
+        ));
+
+    for (policy, input, expected) in [
+        ("pad", "X\n\"1,2\"\n", "X,A,B,C\n\"1,2\",1,2,\n"),
+        (
+            "truncate",
+            "X\n\"1,2,3,4\"\n",
+            "X,A,B,C\n\"1,2,3,4\",1,2,3\n",
+        ),
+    ] {
+        Command::cargo_bin("csv-exec")
+            .unwrap()
+            .args(["echo $1", "--split-output", ","])
+            .args(["--result-headers", "A,B,C", "--split-policy", policy])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+}
+
+#[test]
+fn test_json_output() {
+    let input = r#"
+Id
+24
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"echo '{"name":"a","tags":["x","y"],"size":{"kb":3},"none":null}'"#,
+            "--json-output",
+            "name,tags.1,/size,none,missing",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,name,tags.1,/size,none,missing\n24,a,y,\"{\"\"kb\"\":3}\",,\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            r#"echo '{"a":{"b":1}}'"#,
+            "--json-output",
+            "a.b",
+            "--result-headers",
+            "B",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,B\n24,1\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo not json", "--json-output", "a"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Invalid JSON in the result"));
+}
+
+#[test]
+fn test_capture() {
+    let input = r#"
+Host
+example.com
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo 64 bytes from $1: time=12.3 ms",
+            "--capture",
+            r"from (?P<Ip>[^:]+): time=([0-9.]+)",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Host,Ip,Capture2\nexample.com,example.com,12.3\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo timeout",
+            "--capture",
+            r"time=([0-9.]+)",
+            "--result-headers",
+            "Time",
+            "--on-error",
+            "empty",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Host,Time\nexample.com,\n")
+        .stderr(predicates::str::contains("The result does not match"));
+}
+
+#[test]
+fn test_result_lines() {
+    let input = r#"
+Id
+24
+"#
+    .trim_start();
+    let command = r"printf '  first \n second\n\n'";
+
+    for (args, expected) in [
+        (vec![], "Id,Result\n24,\"first \n second\"\n"),
+        (vec!["--no-trim"], "Id,Result\n24,\"  first \n second\n\"\n"),
+        (vec!["--first-line-only"], "Id,Result\n24,first\n"),
+        (vec!["--join-lines", "|"], "Id,Result\n24,first|second\n"),
+        (
+            vec!["--join-lines", "|", "--no-trim"],
+            "Id,Result\n24,  first | second\n",
+        ),
+    ] {
+        Command::cargo_bin("csv-exec")
+            .unwrap()
+            .arg(command)
+            .args(args)
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+}
+
+#[test]
+fn test_max_output_bytes() {
+    let input = r#"
+Id
+24
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        // The last character is cut by the limit
+        .args(["sh -c 'yes é | head -c 100000'", "--max-output-bytes", "7"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n24,\"é\né\"\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'yes | head -c 100000'", "--max-output-bytes", "6"])
+        .args(["--output-overflow", "error", "--on-error", "empty"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n24,\n")
+        .stderr(predicates::str::contains("wrote more than 6 bytes"));
+
+    // The error output is limited too
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'echo a; yes é | head -c 100000 >&2'",
+            "--max-output-bytes",
+            "7",
+        ])
+        .args(["--stderr-column", "Errors"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result,Errors\n24,a,\"é\né\"\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'yes | head -c 100000 >&2'",
+            "--max-output-bytes",
+            "6",
+        ])
+        .args(["--output-overflow", "error", "--on-error", "empty"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n24,\n")
+        .stderr(predicates::str::contains("wrote more than 6 bytes"));
+}
+
+#[test]
+fn test_output_encoding() {
+    let input = r#"
+Id
+24
+"#
+    .trim_start();
+    let command = r"printf 'a\377\n'";
+
+    for (encoding, expected) in [
+        ("lossy", "Id,Result\n24,a\u{fffd}\n"),
+        ("base64", "Id,Result\n24,Yf8K\n"),
+        ("hex", "Id,Result\n24,61ff0a\n"),
+    ] {
+        Command::cargo_bin("csv-exec")
+            .unwrap()
+            .args([command, "--output-encoding", encoding])
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout(expected);
+    }
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .arg(command)
+        .write_stdin(input)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_dry_run() {
+    let input = r#"
+Id,Dir
+24,example.com/a b
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["rm -r $Dir/$Id", "--exec", "echo $RESULT1", "--dry-run"])
+        .args(["--attempts-column", "Attempts"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Dir,Result1,Result2,Attempts\n24,example.com/a b,rm -r 'example.com/a b/24',echo 'rm -r '\\''example.com/a b/24'\\''',0\n");
+}
+
+#[test]
+fn test_summary() {
+    let path = std::env::temp_dir().join(format!("csv-exec-summary-{}.json", std::process::id()));
+    let input = r#"
+Code
+0
+0
+3
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'exit $1'", "--on-error", "empty", "--summary"])
+        .args(["--summary-json", path.to_str().unwrap()])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Rows: 3 (2 succeeded, 1 failed)\nRetries: 0\n",
+        ));
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(summary["rows"], 3);
+    assert_eq!(summary["successes"], 2);
+    assert_eq!(summary["failures"], 1);
+    assert!(summary["duration_p95_ms"].is_u64());
+
+    // The expressions are not commands, whose durations they would lower
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sleep 0.2",
+            "--expr",
+            "$1",
+            "--summary-json",
+            path.to_str().unwrap(),
+        ])
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(summary["duration_p50_ms"].as_u64().unwrap() >= 200);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_audit_log() {
+    let path = std::env::temp_dir().join(format!("csv-exec-audit-{}.jsonl", std::process::id()));
+    let input = r#"
+Id,Dir
+24,example.com/a
+68,example.com/b
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $Dir; echo oops >&2; exit $Id'"])
+        .args(["--audit-log", path.to_str().unwrap()])
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let entries = log
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["row"], 1);
+    assert_eq!(
+        entries[0]["argv"],
+        serde_json::json!(["sh", "-c", "echo example.com/a; echo oops >&2; exit 24"])
+    );
+    assert_eq!(entries[0]["exit_code"], 24);
+    assert_eq!(entries[0]["stdout"], "example.com/a\n");
+    assert_eq!(entries[0]["stderr"], "oops\n");
+    assert!(entries[0]["duration_ms"].is_u64());
+    assert_eq!(entries[1]["row"], 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_strict_exit() {
+    let input = r#"
+Code
+0
+3
+0
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1; exit $1'", "--strict-exit"])
+        .write_stdin(input)
+        .assert()
+        .code(123)
+        .stdout("Code,Result\n0,0\n3,3\n0,0\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'exit $1'", "--strict-exit", "--on-error", "skip-row"])
+        .write_stdin(input)
+        .assert()
+        .code(123)
+        .stdout("Code,Result\n0,\n0,\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--strict-exit"])
+        .write_stdin(input)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_fail_fast() {
+    let input = r#"
+Code
+0
+3
+0
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1; exit $1'", "--fail-fast"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stdout("Code,Result\n0,0\n3,3\n")
+        .stderr(predicates::str::contains(
+            "Stopped at the failure of record 2",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1'", "--fail-fast"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Code,Result\n0,0\n3,3\n0,0\n");
+}
+
+#[test]
+fn test_abort_after_failures() {
+    let input = r#"
+Code
+0
+1
+0
+1
+1
+0
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1; exit $1'", "--abort-after-failures", "2"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stdout("Code,Result\n0,0\n1,1\n0,0\n1,1\n1,1\n")
+        .stderr(predicates::str::contains(
+            "Stopped after 2 consecutive failures, at record 5",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1; exit $1'", "--abort-after-failures", "3"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Code,Result\n0,0\n1,1\n0,0\n1,1\n1,1\n0,0\n");
+}
+
+#[test]
+fn test_max_failure_rate() {
+    let input = r#"
+Code
+1
+0
+0
+0
+1
+1
+0
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "sh -c 'echo $1; exit $1'",
+            "--max-failure-rate",
+            "0.4",
+            "--failure-rate-warm-up",
+            "3",
+        ])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stdout("Code,Result\n1,1\n0,0\n0,0\n0,0\n1,1\n1,1\n")
+        .stderr(predicates::str::contains(
+            "Stopped as 3 of the 6 rows written failed, more than the maximum rate of 0.4",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["sh -c 'echo $1; exit $1'", "--max-failure-rate", "0.5"])
+        .write_stdin(input)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_checkpoint_resume() {
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!("csv-exec-resume-{}.csv", std::process::id()));
+    let output = dir.join(format!("csv-exec-resume-{}.out.csv", std::process::id()));
+    let checkpoint = dir.join(format!("csv-exec-resume-{}.checkpoint", std::process::id()));
+    let audit_log = dir.join(format!("csv-exec-resume-{}.jsonl", std::process::id()));
+    std::fs::write(&input, "Code\n0\n1\n2\n3\n").unwrap();
+    let args = |command: &str| {
+        vec![
+            command.to_string(),
+            "-i".to_string(),
+            input.to_str().unwrap().to_string(),
+            "-o".to_string(),
+            output.to_str().unwrap().to_string(),
+            "--checkpoint".to_string(),
+            checkpoint.to_str().unwrap().to_string(),
+            "--resume".to_string(),
+            "--audit-log".to_string(),
+            audit_log.to_str().unwrap().to_string(),
+        ]
+    };
+    let audited_rows = || {
+        std::fs::read_to_string(&audit_log)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["row"].clone())
+            .collect::<Vec<_>>()
+    };
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(args("sh -c 'echo $1; test $1 != 1'"))
+        .arg("--fail-fast")
+        .assert()
+        .failure();
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "Code,Result\n0,0\n1,1\n"
+    );
+    assert_eq!(audited_rows(), [1, 2]);
+
+    // The rows written after the checkpoint are discarded
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&output)
+        .unwrap();
+    std::io::Write::write_all(&mut file, b"partial").unwrap();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(args("echo again $1"))
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(&output).unwrap(),
+        "Code,Result\n0,0\n1,1\n2,again 2\n3,again 3\n"
+    );
+    // The entries of the interrupted run are kept
+    assert_eq!(audited_rows(), [1, 2, 3, 4]);
+
+    for path in [&input, &output, &checkpoint, &audit_log] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_skip_populated() {
+    let input = r#"
+Id,Result
+1,
+2,kept
+3,
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo new $1", "--skip-populated"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,new 1\n2,kept\n3,new 3\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo new $1", "--skip-populated", "--replace-column", "Out"])
+        .write_stdin("Id,Out\n1,\n2,kept\n")
+        .assert()
+        .success()
+        .stdout("Id,Out\n1,new 1\n2,kept\n");
+
+    // The output can be given again as input
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo new $1", "--skip-populated"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,new 1\n");
+}
+
+#[test]
+fn test_append() {
+    let path = std::env::temp_dir().join(format!("csv-exec-append-{}.csv", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    for day in ["1", "2"] {
+        Command::cargo_bin("csv-exec")
+            .unwrap()
+            .args(["echo day $1", "--append", "-o", path.to_str().unwrap()])
+            .write_stdin(format!("Day\n{}\n", day))
+            .assert()
+            .success();
+    }
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        "Day,Result\n1,day 1\n2,day 2\n"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_no_clobber() {
+    let path = std::env::temp_dir().join(format!("csv-exec-no-clobber-{}.csv", std::process::id()));
+    std::fs::write(&path, "Precious\n").unwrap();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--no-clobber", "-o", path.to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "Output file {} already exists",
+            path.canonicalize().unwrap().display()
+        )));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Precious\n");
+
+    std::fs::remove_file(&path).unwrap();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--no-clobber", "-o", path.to_str().unwrap()])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Id,Result\n1,1\n");
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_output_template() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-partitions-{}", std::process::id()));
+    let input = r#"
+Region,Id
+eu,1
+us,2
+eu,3
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $2",
+            "--output-template",
+            &format!("{}/{{Region}}.csv", dir.display()),
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("");
+    assert_eq!(
+        std::fs::read_to_string(dir.join("eu.csv")).unwrap(),
+        "Region,Id,Result\neu,1,1\neu,3,3\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(dir.join("us.csv")).unwrap(),
+        "Region,Id,Result\nus,2,2\n"
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_multiple_inputs() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-inputs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1.csv"), "Id,Name\n1,a\n").unwrap();
+    // The columns are matched by name
+    std::fs::write(dir.join("2.csv"), "Name,Id\nb,2\n").unwrap();
+    std::fs::write(dir.join("3.csv"), "Id\n3\n").unwrap();
+    let pattern = format!("{}/*.csv", dir.display());
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name$Id", "-i", &pattern])
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,a1\n2,b,b2\n3,,3\n");
+
+    let first = dir.join("1.csv");
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "-i", first.to_str().unwrap()])
+        .args(["-i", first.to_str().unwrap()])
+        .args(["--add-filename-column", "File"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "Id,Name,File,Result\n1,a,{0},1\n1,a,{0},1\n",
+            first.display()
+        ));
+
+    std::fs::write(dir.join("4.csv"), "Other\nx\n").unwrap();
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "-i", &pattern])
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicates::str::contains("is not in the first input file"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_file_reference() {
+    let dir = std::env::temp_dir().join(format!("csv-exec-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("1.csv"), "Id\n1\n").unwrap();
+    std::fs::write(dir.join("2.csv"), "Id\n2\n3\n").unwrap();
+    let first = dir.join("1.csv");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $FILE ${FILENAME}", "-i", first.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(format!("Id,Result\n1,{0} {0}\n", first.display()));
+
+    let pattern = format!("{}/*.csv", dir.display());
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $FILE", "-i", &pattern, "--new-column-name", "File"])
+        .assert()
+        .success()
+        .stdout(format!(
+            "Id,File\n1,{0}/1.csv\n2,{0}/2.csv\n3,{0}/2.csv\n",
+            dir.display()
+        ));
+
+    // The standard input is named -
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $FILE ${FILENAME}", "-i", "-"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,- -\n");
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $FILE"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,-\n");
+
+    // A column named FILE takes precedence
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $FILE"])
+        .write_stdin("Id,FILE\n1,x\n")
+        .assert()
+        .success()
+        .stdout("Id,FILE,Result\n1,x,x\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Serves `response` to the first request on a local port, and returns the URL.
+fn serve_once(response: &'static str) -> String {
+    use std::io::{BufRead, BufReader, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/data.csv", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(&stream);
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 2 {
+            line.clear();
+        }
+        (&stream).write_all(response.as_bytes()).unwrap();
+    });
+    url
+}
+
+#[test]
+fn test_url_input() {
+    let url = serve_once("HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nId\n1\n2\n3\n");
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "-i", &url])
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,1\n2,2\n3,3\n");
+
+    let url = serve_once("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "-i", &url])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "Failed to download {}: HTTP status 404 Not Found",
+            url
+        )));
+}
+
+#[test]
+fn test_compression() {
+    use std::io::{Read, Write};
+    let dir = std::env::temp_dir();
+    let input = dir.join(format!(
+        "csv-exec-compression-{}.csv.gz",
+        std::process::id()
+    ));
+    let output = dir.join(format!(
+        "csv-exec-compression-{}.csv.zst",
+        std::process::id()
+    ));
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(b"Id\n1\n2\n").unwrap();
+    std::fs::write(&input, encoder.finish().unwrap()).unwrap();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "-i", input.to_str().unwrap()])
+        .args(["-o", output.to_str().unwrap()])
+        .assert()
+        .success();
+    let mut content = String::new();
+    zstd::Decoder::new(std::fs::File::open(&output).unwrap())
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "Id,Result\n1,1\n2,2\n");
+
+    // Decompressed when read again
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo again $1", "-i", output.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("Id,Result,Result\n1,1,again 1\n2,2,again 2\n");
+
+    std::fs::remove_file(&input).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+#[test]
+fn test_jsonl_input() {
+    let input = r#"
+{"Id": 1, "Name": "a b", "Tags": ["x"]}
+
+{"Name": null, "Id": 2}
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id-$Name", "--input-format", "jsonl"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Name,Tags,Result\n1,a b,\"[\"\"x\"\"]\",1-a b\n2,,,2-\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "--input-format", "jsonl"])
+        .write_stdin("{\"Id\": 1}\n{\"Other\": 2}\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Key Other on line 2 of the standard input is not in the first object",
+        ));
+}
+
+#[test]
+fn test_output_format() {
+    let input = r#"
+Id,Name
+1,a|b
+2,<c>
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--output-format", "markdown"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "| Id | Name | Result |\n| --- | --- | --- |\n| 1 | a\\|b | 1 |\n| 2 | <c> | 2 |\n",
+        );
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--output-format", "html"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            r#"
+<table>
+<thead>
+<tr><th>Id</th><th>Name</th><th>Result</th></tr>
+</thead>
+<tbody>
+<tr><td>1</td><td>a|b</td><td>1</td></tr>
+<tr><td>2</td><td>&lt;c&gt;</td><td>2</td></tr>
+</tbody>
+</table>
+"#
+            .trim_start(),
+        );
+}
+
+/// Writes a workbook with a sheet named `name`, whose cells are inline strings or numbers.
+#[cfg(feature = "xlsx")]
+fn write_workbook(path: &std::path::Path, name: &str, rows: &[&[&str]]) {
+    use std::io::Write;
+
+    let cell = |value: &str| match value.parse::<f64>() {
+        Ok(_) => format!("<c><v>{}</v></c>", value),
+        Err(_) => format!(r#"<c t="inlineStr"><is><t>{}</t></is></c>"#, value),
+    };
+    let sheet_data: String = rows
+        .iter()
+        .map(|row| {
+            format!(
+                "<row>{}</row>",
+                row.iter().map(|&value| cell(value)).collect::<String>()
+            )
+        })
+        .collect();
+    let files = [
+        (
+            "[Content_Types].xml",
+            r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#.to_string(),
+        ),
+        (
+            "_rels/.rels",
+            r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string(),
+        ),
+        (
+            "xl/workbook.xml",
+            format!(r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#, name),
+        ),
+        (
+            "xl/_rels/workbook.xml.rels",
+            r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#.to_string(),
+        ),
+        (
+            "xl/worksheets/sheet1.xml",
+            format!(r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#, sheet_data),
+        ),
+    ];
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+    for (name, content) in &files {
+        zip.start_file(*name, zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+}
+
+#[test]
+#[cfg(feature = "xlsx")]
+fn test_xlsx_input() {
+    let path = std::env::temp_dir().join(format!("csv-exec-test-{}.xlsx", std::process::id()));
+    write_workbook(
+        &path,
+        "Data",
+        &[&["Id", "Name"], &["1", "a"], &["2.5", "b"]],
+    );
+
+    let expected_output = r#"
+Id,Name,Result
+1,a,a-1
+2.5,b,b-2.5
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $2-$1",
+            "--input-format",
+            "xlsx",
+            "--sheet",
+            "Data",
+            "-i",
+        ])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $2",
+            "--input-format",
+            "xlsx",
+            "--sheet",
+            "Other",
+            "-i",
+        ])
+        .arg(&path)
+        .assert()
+        .failure();
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "xlsx"))]
+fn test_xlsx_input() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--input-format", "xlsx", "-i", "workbook.xlsx"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("built with the xlsx feature"));
+}
+
+#[test]
+#[cfg(feature = "parquet")]
+fn test_parquet() {
+    let path = std::env::temp_dir().join(format!("csv-exec-test-{}.parquet", std::process::id()));
+    let input = r#"
+Id,Name
+1,a
+2,
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $2$1", "--output-format", "parquet", "-o"])
+        .arg(&path)
+        .write_stdin(input)
+        .assert()
+        .success();
+
+    let expected_output = r#"
+Id,Name,Result,Again
+1,a,a1,a1.1
+2,,2,2.2
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $3.$1",
+            "--new-column-name",
+            "Again",
+            "--input-format",
+            "parquet",
+            "-i",
+        ])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "parquet"))]
+fn test_parquet() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $1",
+            "--input-format",
+            "parquet",
+            "-i",
+            "input.parquet",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("built with the parquet feature"));
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_sqlite() {
+    let path = std::env::temp_dir().join(format!("csv-exec-test-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE pages (id INTEGER, url TEXT);
+            INSERT INTO pages VALUES (1, 'example.com/a'), (2, NULL), (3, 'example.com/c');",
+        )
+        .unwrap();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $url/$id", "--input-sqlite"])
+        .arg(&path)
+        .args(["--query", "SELECT id, url FROM pages WHERE id != 2"])
+        .args(["--output-sqlite"])
+        .arg(&path)
+        .args(["--table", "results"])
+        .assert()
+        .success()
+        .stdout("");
+
+    let results: Vec<(String, String, String)> = connection
+        .prepare("SELECT id, url, Result FROM results")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(
+        results,
+        [
+            ("1".into(), "example.com/a".into(), "example.com/a/1".into()),
+            ("3".into(), "example.com/c".into(), "example.com/c/3".into()),
+        ]
+    );
+
+    drop(connection);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(not(feature = "sqlite"))]
+fn test_sqlite() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $1",
+            "--input-sqlite",
+            "input.sqlite",
+            "--query",
+            "SELECT 1",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("built with the sqlite feature"));
+}
+
+#[test]
+fn test_dialect_presets() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--tsv"])
+        .write_stdin("Id\tName\n1\t\"a\n")
+        .assert()
+        .success()
+        .stdout("Id\tName\tResult\n1\t\"a\t\"a\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--psv"])
+        .write_stdin("Id|Name\n1|a b\n")
+        .assert()
+        .success()
+        .stdout("Id|Name|Result\n1|a b|a b\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--ssv"])
+        .write_stdin("Id;Name\n1;\"a;b\"\n")
+        .assert()
+        .success()
+        .stdout("Id;Name;Result\n1;\"a;b\";\"a;b\"\n");
+}
+
+#[test]
+fn test_sniff() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--sniff"])
+        .write_stdin("Id;Name\n1;\"a,b\"\n2;c\n")
+        .assert()
+        .success()
+        .stdout("Id;Name;Result\n1;a,b;a,b\n2;c;c\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $2", "--sniff"])
+        .write_stdin("1\ta\n2\tb\n")
+        .assert()
+        .success()
+        .stdout("1\ta\ta\n2\tb\tb\n");
+}
+
+#[test]
+fn test_escape() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--escape", "\\"])
+        .write_stdin("Id,Name\n1,\"a \\\"b\\\", c\"\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,\"a \\\"b\\\", c\",\"a \\\"b\\\", c\"\n");
+}
+
+#[test]
+fn test_comment() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--comment", "#"])
+        .write_stdin("# Exported by the instrument\nId,Name\n1,a\n# Calibrated\n2,\"b\n#c\"\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,a\n2,\"b\n#c\",\"b\n#c\"\n");
+}
+
+#[test]
+fn test_skip_lines() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--skip-lines", "2", "--sniff"])
+        .write_stdin("Account statement\nPeriod: \"Q1, 2024\nId;Name\n1;a\n2;b\n")
+        .assert()
+        .success()
+        .stdout("Id;Name;Result\n1;a;a\n2;b;b\n");
+}
+
+#[test]
+fn test_flexible() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $b", "--flexible", "skip"])
+        .write_stdin("a,b\n1\n2,3\n4,5,6\n")
+        .assert()
+        .success()
+        .stdout("a,b,Result\n2,3,3\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $a", "--flexible", "pad"])
+        .write_stdin("a,b\n1\n2,3\n")
+        .assert()
+        .success()
+        .stdout("a,b,Result\n1,,1\n2,3,2\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $a", "--flexible", "truncate"])
+        .write_stdin("a,b\n1\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Line 2 has 1 fields instead of 2",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $1", "--no-headers", "--flexible", "truncate"])
+        .write_stdin("1,2\n3,4,5\n")
+        .assert()
+        .success()
+        .stdout("1,2,1\n3,4,3\n");
+}
+
+#[test]
+fn test_trim() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo /$Name/", "--trim", "all"])
+        .write_stdin("Id, Name\n1, a b \n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a b,/a b/\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo /$Name/", "--trim", "headers"])
+        .write_stdin("Id, Name\n1, a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1, a,/ a/\n");
+}
+
+#[test]
+fn test_bytes() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["printf %s $Name", "--bytes"])
+        .write_stdin(&b"Id,Name,Note\n1,caf\xe9,\xff\n"[..])
+        .assert()
+        .success()
+        .stdout(&b"Id,Name,Note,Result\n1,caf\xe9,\xff,caf\xe9\n"[..]);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name"])
+        .write_stdin(&b"Id,Name\n1,caf\xe9\n"[..])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("invalid utf-8"));
+}
+
+#[test]
+fn test_whole_record() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $0", "-d", ";"])
+        .write_stdin("Id;Name\n1;\"a;b\"\n")
+        .assert()
+        .success()
+        .stdout("Id;Name;Result\n1;\"a;b\";\"1;\"\"a;b\"\"\"\n");
+}
+
+#[test]
+fn test_field_count() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $# ${#}", "--no-headers"])
+        .write_stdin("a,b\nc,d\n")
+        .assert()
+        .success()
+        .stdout("a,b,2 2\nc,d,2 2\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $#", "--add-filename-column", "File"])
+        .write_stdin("a,b\nc,d\n")
+        .assert()
+        .success()
+        .stdout("a,b,File,Result\nc,d,-,3\n");
+}
+
+#[test]
+fn test_env_reference() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${env:CSV_EXEC_TOKEN}/$Id/${env:CSV_EXEC_UNDEFINED}"])
+        .env("CSV_EXEC_TOKEN", "secret")
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,secret/1/\n");
+}
+
+#[test]
+fn test_default_value() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${Name:-N/A} ${3:-none} ${env:CSV_EXEC_UNDEFINED:-unset}"])
+        .write_stdin("Id,Name\n1,a\n2,\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,a none unset\n2,,N/A none unset\n");
+}
+
+#[test]
+fn test_slice() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${Time:0:10}/${2:11:2}/${Time:17}/${3:0:1}"])
+        .write_stdin("Id,Time\n1,2024-01-02T10:00:00\n2,été\n")
+        .assert()
+        .success()
+        .stdout("Id,Time,Result\n1,2024-01-02T10:00:00,2024-01-02/10/00/\n2,été,été///\n");
+}
+
+#[test]
+fn test_filters() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${Name|trim|urlencode}/${Name|upper|trim}/${Name:-N/A|lower}/${Id|nope}"])
+        .write_stdin("Id,Name\n1, Café & co\n2,\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1, Café & co,Caf%C3%A9%20%26%20co/CAFÉ & CO/ café & co/${Id|nope}\n2,,//n/a/${Id|nope}\n");
+}
+
+#[test]
+fn test_where() {
+    let input = "Name,Status,Size\na,active,5\nb,inactive,20\nc,active,30\n";
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name",
+            "--where",
+            "$Status == 'active' && $Size > 10 || $Name == \"a\"",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Name,Status,Size,Result\na,active,5,a\nb,inactive,20,\nc,active,30,c\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--where", "${Status} !~ '^in'", "--else", "-"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Name,Status,Size,Result\na,active,5,a\nb,inactive,20,-\nc,active,30,c\n");
+
+    // With the functions of the expressions, and the truthiness of a value
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name",
+            "--where",
+            "starts_with($Status, 'in') || $Size - 5",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Name,Status,Size,Result\na,active,5,\nb,inactive,20,b\nc,active,30,c\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--where", "$Status =~ in"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid expression $Status =~ in: expected a quoted regex after =~ instead of in",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--where", "$Size >"])
+        .write_stdin(input)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid expression $Size >: a value is missing at the end",
+        ));
+}
+
+#[test]
+fn test_shell_filter() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--shell", "printf '%s|' ${Name|sh} $Id"])
+        .write_stdin("Id,Name\n1,a b; echo 'injected'\n2,\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a b; echo 'injected',a b; echo 'injected'|1|\n2,,|2|\n");
+}
+
+#[test]
+fn test_format_filter() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${1|%05d} ${2|%08.2f} ${2|%+.1e} ${1|%x}"])
+        .write_stdin("Id,Price\n42,3.14159\n-7,-0.5\n")
+        .assert()
+        .success()
+        .stdout("Id,Price,Result\n42,3.14159,00042 00003.14 +3.1e+00 2a\n-7,-0.5,-0007 -0000.50 -5.0e-01 -7\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${1|%d}"])
+        .write_stdin("Id\nabc\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Value abc must be an integer for %d",
+        ));
+}
+
+#[test]
+fn test_date_filter() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${1|date:%Y-%m-%d} ${1|date:%H:%M%z}"])
+        .write_stdin("Date\n2024-03-05T10:20:30+02:00\n2024/03/05\n1700000000\n")
+        .assert()
+        .success()
+        .stdout(
+            "Date,Result\n2024-03-05T10:20:30+02:00,2024-03-05 10:20+0200\n\
+             2024/03/05,2024-03-05 00:00+0000\n1700000000,2023-11-14 22:13+0000\n",
+        );
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${1|date:%Y}"])
+        .write_stdin("Date\nsoon\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Value soon must be a date"));
+}
+
+#[test]
+fn test_negative_position() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $-1 ${-2} ${-3:-none}"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,a 1 none\n");
+}
+
+#[test]
+fn test_strict_refs() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Nmae", "--strict-refs"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicates::str::contains("Unknown reference $Nmae"));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $12", "--strict-refs"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown reference $12"));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $2", "--strict-refs", "--no-headers"])
+        .write_stdin("1\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Reference $2 has no value"));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name ${env:CSV_EXEC_UNDEFINED:-none}",
+            "--strict-refs",
+        ])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,a none\n");
+}
+
+#[test]
+fn test_check() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["touch should-not-exist ${Id|%d}", "--check"])
+        .write_stdin("Id\n1\n2\n")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr("2 records checked\n");
+    assert!(!std::path::Path::new("should-not-exist").exists());
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo ${Id|%d}", "--check"])
+        .write_stdin("Id\n1\nx\n")
+        .assert()
+        .failure()
+        .stdout("")
+        .stderr(predicates::str::contains(
+            "Error on record 2: Value x must be an integer",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Nmae", "--check"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown reference $Nmae"));
+}
+
+#[test]
+fn test_no_reference_warning() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo hello"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,hello\n")
+        .stderr(predicates::str::contains("the commands have no references"));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "--arg-regex", r"\$\w+"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("has no capturing group"));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .success()
+        .stderr("");
+}
+
+#[test]
+fn test_select_drop() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name",
+            "--select",
+            "3,Id",
+            "--exit-code-column",
+            "Code",
+        ])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Id,Dir,Result,Code\n1,x,a,0\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--drop", "Name", "--before", "Dir"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Id,Result,Dir\n1,a,x\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "--select", "Missing"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Column Missing not found"));
+}
+
+#[test]
+fn test_order() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--order", "Result,Dir"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Result,Dir,Id,Name\na,x,1,a\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $2",
+            "--no-headers",
+            "--order",
+            "4,1",
+            "--drop-unlisted",
+        ])
+        .write_stdin("1,a,x\n")
+        .assert()
+        .success()
+        .stdout("a,1\n");
+}
+
+#[test]
+fn test_rename() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name",
+            "--rename",
+            "Name=name",
+            "--rename",
+            "Result=upper",
+        ])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("Id,name,upper\n1,a,a\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--rename", "Nmae=name"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Column Nmae not found"));
+}
+
+#[test]
+fn test_only_result() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--only-result", "echo $Name"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n2,b,y\n")
+        .assert()
+        .success()
+        .stdout("Row,Result\n1,a\n2,b\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--only-result=Id"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,a\n");
+}
+
+#[test]
+fn test_filter() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--filter", "test $Name = a"])
+        .write_stdin("Id,Name\n1,a\n2,b\n3,a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name\n1,a\n3,a\n");
+
+    // The rejected rows are not failures
+    let path = std::env::temp_dir().join(format!("csv-exec-filter-{}.json", std::process::id()));
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--filter", "test $Name = a", "--summary"])
+        .args(["--summary-json", path.to_str().unwrap()])
+        .write_stdin("Id,Name\n1,a\n2,b\n3,a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name\n1,a\n3,a\n")
+        .stderr(predicates::str::contains(
+            "Rows: 3 (2 succeeded, 0 failed, 1 filtered out)\n",
+        ));
+    let summary: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(summary["failures"], 0);
+    assert_eq!(summary["filtered"], 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_map() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--map", "Name", "echo ${CELL|upper}-$Id"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Dir\n1,A-1,x\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["map", "--column", "2", "-e", "echo ${CELL|upper}-$Id"])
+        .write_stdin("Id,Name,Dir\n1,a,x\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Dir\n1,A-1,x\n");
+    // With options before and after the subcommand
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "-d",
+            ";",
+            "map",
+            "-e",
+            "echo $CELL$CELL",
+            "--column",
+            "Dir",
+            "-j",
+            "2",
+        ])
+        .write_stdin("Id;Name;Dir\n1;a;x\n")
+        .assert()
+        .success()
+        .stdout("Id;Name;Dir\n1;a;xx\n");
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["map", "-e", "echo $CELL"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--column <COLUMN>"));
+}
+
+#[test]
+fn test_explode() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--explode", "--shell", "printf '%s\\n' $Names"])
+        .write_stdin("Id,Names\n1,a b\n2,\n3,c\n")
+        .assert()
+        .success()
+        .stdout("Id,Names,Result\n1,a b,a\n1,a b,b\n2,,\n3,c,c\n");
+}
+
+#[test]
+fn test_group_by() {
+    let input = "Customer,Amount\na,1\nb,2\na,3\n";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "--group-by",
+            "Customer",
+            "--shell",
+            "echo $Customer:; cut -d, -f2",
+        ])
+        .args(["--join-lines", " "])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Customer,Amount,Result\na,1,a: 1 3\na,3,a: 1 3\nb,2,b: 2\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--group-by", "1", "--summarize", "--only-result=Customer"])
+        .args(["--shell", "cut -d, -f2 | tr '\\n' +"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Customer,Result\na,1+3+\nb,2+\n");
+}
+
+#[test]
+fn test_finally() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "--finally", "wc -l"])
+        .write_stdin("Id,Name\n1,a\n2,b\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,a,1\n2,b,2\n,,3\n");
+
+    let file = std::env::temp_dir().join(format!("csv-exec-finally-{}", std::process::id()));
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--only-result=Id", "--finally", "cat"])
+        .args(["--finally-output", file.to_str().unwrap()])
+        .write_stdin("Id,Name\n1,a\n2,b\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,a\n2,b\n");
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "Id,Result\n1,a\n2,b\n"
+    );
+    std::fs::remove_file(&file).unwrap();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Id", "--finally", "false"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("The finally command exited"));
+}
+
+#[test]
+fn test_expr() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "--expr",
+            "concat($2, \"/\", upper($1))",
+            "--exec",
+            "echo $RESULT1",
+        ])
+        .args(["--expr", "$Id * 2 > 10 ? round($Id / 3, 2) : 'small'"])
+        .write_stdin("Id,Name\n4,a\n10,b\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result1,Result2,Result3\n4,a,a/4,a/4,small\n10,b,b/10,b/10,3.33\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--expr", "concat($1"])
+        .write_stdin("Id\n1\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Invalid expression concat($1: expected , or ) at the end",
+        ));
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--expr", "$Id + 1", "--on-error", "skip-row"])
+        .write_stdin("Id\n1\nx\n")
+        .assert()
+        .success()
+        .stdout("Id,Result\n1,2\n");
+
+    // A number is neither lower nor greater than a text, which is compared with the texts only
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--expr", "$Id > 9", "--expr", "$Id == 9 || $Id != 9"])
+        .args(["--expr", "$Id >= 'a'"])
+        .write_stdin("Id\nabc\n10\n")
+        .assert()
+        .success()
+        .stdout("Id,Result1,Result2,Result3\nabc,false,true,true\n10,true,true,false\n");
+}
+
+#[test]
+fn test_builtins() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["--exec-builtin", "sha256($1)", "--expr", "md5($1)"])
+        .args([
+            "--expr",
+            "base64($1)",
+            "--expr",
+            "urlencode(concat($1, ' /é'))",
+        ])
+        .write_stdin("Name\nhello\n")
+        .assert()
+        .success()
+        .stdout(concat!(
+            "Name,Result1,Result2,Result3,Result4\n",
+            "hello,2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824,",
+            "5d41402abc4b2a76b9719d911017c592,aGVsbG8=,hello%20%2F%C3%A9\n",
+        ));
+}
+
+#[test]
+fn test_output_dialect() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "-d", ";", "--out-delimiter", ","])
+        .args(["--out-quote", "'", "--out-terminator", "\\r\\n"])
+        .write_stdin("Id;Name\n1;\"a,b\"\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\r\n1,'a,b','a,b'\r\n");
+}
+
+#[test]
+fn test_quote_style() {
+    let input = "Id,Name\n1,a\n";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--quote-style", "always"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("\"Id\",\"Name\",\"Result\"\n\"1\",\"a\",\"a\"\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--quote-style", "non-numeric"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("\"Id\",\"Name\",\"Result\"\n1,\"a\",\"a\"\n");
+}
+
+#[test]
+fn test_terminator() {
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--crlf"])
+        .write_stdin("Id,Name\n1,a\n")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\r\n1,a,a\r\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--terminator", ";"])
+        .write_stdin("Id,Name;1,a b;")
+        .assert()
+        .success()
+        .stdout("Id,Name,Result;1,a b,a b;");
+}
+
+#[test]
+fn test_encoding() {
+    let input: &[u8] = b"Id,Name\n1,caf\xe9\n";
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--encoding", "latin1"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(&b"Id,Name,Result\n1,caf\xe9,caf\xe9\n"[..]);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args([
+            "echo $Name",
+            "--encoding",
+            "latin1",
+            "--out-encoding",
+            "utf-8",
+        ])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,café,café\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "--out-encoding", "latin1"])
+        .write_stdin("Id,Name\n1,\u{1F600}\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "cannot be written in windows-1252",
+        ));
+}
+
+#[test]
+fn test_long_delimiter() {
+    let input = r#"
+Id~|~Name
+1~|~a,b
+2~|~"c~|~d"
+"#
+    .trim_start();
+
+    let expected_output = r#"
+Id~|~Name~|~Result
+1~|~a,b~|~a,b
+2~|~"c~|~d"~|~"c~|~d"
+"#
+    .trim_start();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "-d", "~|~"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(expected_output);
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "-d", "~|~", "--out-delimiter", ","])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("Id,Name,Result\n1,\"a,b\",\"a,b\"\n2,c~|~d,c~|~d\n");
+}
+
+#[test]
+fn test_long_delimiter_quoting() {
+    // The fields with a byte of the delimiter are quoted, so that they are read back the same
+    let output = Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["printf '%s|' $Name", "-d", "||"])
+        .write_stdin("Id||Name\n1||x\n2||\"|\"\n")
+        .assert()
+        .success()
+        .stdout("Id||Name||Result\n1||x||\"x|\"\n2||\"|\"||\"||\"\n")
+        .get_output()
+        .stdout
+        .clone();
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Result", "-d", "||", "--out-delimiter", ","])
+        .write_stdin(output)
+        .assert()
+        .success()
+        .stdout("Id,Name,Result,Result\n1,x,x|,x|\n2,|,||,||\n");
+
+    // The byte standing for the delimiter is quoted too, and cannot be read out of quotes
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "-d", "||"])
+        .write_stdin("Id||Name\n1||\"a\x1fb\"\n")
+        .assert()
+        .success()
+        .stdout("Id||Name||Result\n1||\"a\x1fb\"||\"a\x1fb\"\n");
+
+    Command::cargo_bin("csv-exec")
+        .unwrap()
+        .args(["echo $Name", "-d", "||"])
+        .write_stdin("Id||Name\n1||a\x1fb\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "The byte 0x1F cannot be read out of quotes with a delimiter of several characters",
+        ));
+}
@@ -0,0 +1,401 @@
+use csv_exec::{Builder, CsvExec, OnError, StdinRecord};
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[test]
+fn test_builder() {
+    let input = r#"
+Id,Dir
+24,example.com/a
+68,
+"#
+    .trim_start()
+    .to_string();
+
+    let expected_output = r#"
+Id,Dir,Check,Length
+24,example.com/a,,2
+68,,error,error
+"#
+    .trim_start();
+
+    let csv_exec = CsvExec::builder()
+        .command("test -n $Dir")
+        .expression("len($Id)")
+        .new_column_name("Check")
+        .new_column_name("Length")
+        .on_error(OnError::Placeholder("error".to_string()))
+        .build()
+        .unwrap();
+    // The input is borrowed
+    let mut output = vec![];
+    let status = csv_exec.run(input.as_bytes(), &mut output).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(String::from_utf8(output).unwrap(), expected_output);
+
+    assert!(CsvExec::builder().build().is_err());
+}
+
+#[test]
+fn test_builder_validation() {
+    let error = |builder: csv_exec::Builder| builder.build().err().unwrap().to_string();
+
+    assert_eq!(error(CsvExec::builder()), "No command to execute");
+    assert_eq!(
+        error(CsvExec::builder().command("echo").jobs(0)),
+        "Value 0 must be a positive number of jobs"
+    );
+    assert_eq!(
+        error(CsvExec::builder().command("echo").quote(0xE9)),
+        "Value é must be 1 ASCII character"
+    );
+    assert_eq!(
+        error(CsvExec::builder().command("echo").arg_regex("(")),
+        "regex parse error:\n    (\n    ^\nerror: unclosed group"
+    );
+}
+
+/// Gives the option of the builder named `option`, with a valid value.
+fn given(builder: Builder, option: &str) -> Builder {
+    match option {
+        "input_path" => builder.input_path("input.csv"),
+        "input_sqlite" => builder.input_sqlite("input.db", "SELECT 1"),
+        "output_path" => builder.output_path("output.csv"),
+        "output_template" => builder.output_template("{Id}.csv"),
+        "output_sqlite" => builder.output_sqlite("output.db", "rows"),
+        "append" => builder.append(true),
+        "no_clobber" => builder.no_clobber(true),
+        "several commands" => builder.command("echo"),
+        "expression" => builder.expression("1"),
+        "no_headers" => builder.no_headers(true),
+        "sniff" => builder.sniff(true),
+        "bytes" => builder.bytes(true),
+        "encoding" => builder.encoding("latin1"),
+        "out_encoding" => builder.out_encoding("latin1"),
+        "check" => builder.check(true),
+        "new_column_name" => builder.new_column_name("Result"),
+        "replace_column" => builder.replace_column("Id"),
+        "map" => builder.map("Id"),
+        "skip_populated" => builder.skip_populated(true),
+        "condition" => builder.condition("1"),
+        "otherwise" => builder.otherwise("-"),
+        "first_line_only" => builder.first_line_only(true),
+        "join_lines" => builder.join_lines(","),
+        "explode" => builder.explode(true),
+        "group_by" => builder.group_by("Id"),
+        "summarize" => builder.summarize(true),
+        "finally" => builder.finally("cat"),
+        "finally_output" => builder.finally_output("final.txt"),
+        "split_output" => builder.split_output(b':'),
+        "json_output" => builder.json_output(["id"]),
+        "capture" => builder.capture("(.)"),
+        "result_headers" => builder.result_headers(["Key"]),
+        "column_position" => builder.column_position(1),
+        "before" => builder.before("Id"),
+        "after" => builder.after("Id"),
+        "select" => builder.select(["Id"]),
+        "drop" => builder.drop(["Id"]),
+        "order" => builder.order(["Id"]),
+        "drop_unlisted" => builder.drop_unlisted(true),
+        "only_result" => builder.only_result(["Id"]),
+        "rename" => builder.rename("Id", "Key"),
+        "exit_code_column" => builder.exit_code_column("Code"),
+        "stderr_column" => builder.stderr_column("Errors"),
+        "duration_column" => builder.duration_column("Duration"),
+        "started_column" => builder.started_column("Started"),
+        "attempts_column" => builder.attempts_column("Attempts"),
+        "unordered" => builder.unordered(true),
+        "timeout" => builder.timeout(Duration::from_secs(1)),
+        "coprocess" => builder.coprocess(true),
+        "batch_size" => builder.batch_size(2),
+        "stdin_record" => builder.stdin_record(StdinRecord::Csv),
+        "stdin_field" => builder.stdin_field("Id"),
+        "field_env" => builder.field_env(true),
+        "clean_env" => builder.clean_env(true),
+        "pass_env" => builder.pass_env("PATH"),
+        "cwd" => builder.cwd("/"),
+        "max_output_bytes" => builder.max_output_bytes(10),
+        "summary_json" => builder.summary_json("summary.json"),
+        "audit_log" => builder.audit_log("audit.jsonl"),
+        "filter" => builder.filter(true),
+        "dry_run" => builder.dry_run(true),
+        "memoize" => builder.memoize(true),
+        "unique" => builder.unique(true),
+        "on_error" => builder.on_error(OnError::Empty),
+        "strict_exit" => builder.strict_exit(true),
+        "fail_fast" => builder.fail_fast(true),
+        "abort_after_failures" => builder.abort_after_failures(1),
+        "max_failure_rate" => builder.max_failure_rate(0.5),
+        "checkpoint" => builder.checkpoint("checkpoint.json"),
+        "resume" => builder.resume(true),
+        "retries" => builder.retries(1),
+        _ => panic!("Unknown option {}", option),
+    }
+}
+
+#[test]
+fn test_builder_conflicts() {
+    let error = |options: &[&str]| {
+        let builder = options
+            .iter()
+            .fold(CsvExec::builder().command("echo"), |builder, option| {
+                given(builder, option)
+            });
+        builder.build().err().map(|error| error.to_string())
+    };
+
+    // As rejected on the command line
+    let conflicts: &[(&str, &[&str])] = &[
+        ("input_sqlite", &["input_path"]),
+        ("output_template", &["output_path", "checkpoint"]),
+        (
+            "output_sqlite",
+            &["output_path", "output_template", "checkpoint", "append"],
+        ),
+        ("no_clobber", &["append"]),
+        ("expression", &["coprocess", "batch_size"]),
+        (
+            "bytes",
+            &[
+                "encoding",
+                "out_encoding",
+                "output_template",
+                "output_sqlite",
+            ],
+        ),
+        ("sniff", &["no_headers"]),
+        (
+            "check",
+            &[
+                "output_path",
+                "output_template",
+                "output_sqlite",
+                "checkpoint",
+                "audit_log",
+                "dry_run",
+                "coprocess",
+                "summary_json",
+            ],
+        ),
+        (
+            "map",
+            &[
+                "split_output",
+                "json_output",
+                "capture",
+                "column_position",
+                "before",
+                "after",
+                "only_result",
+                "filter",
+            ],
+        ),
+        (
+            "skip_populated",
+            &[
+                "split_output",
+                "json_output",
+                "capture",
+                "several commands",
+                "batch_size",
+            ],
+        ),
+        ("condition", &["batch_size", "group_by", "filter"]),
+        ("join_lines", &["first_line_only"]),
+        (
+            "explode",
+            &[
+                "several commands",
+                "split_output",
+                "json_output",
+                "capture",
+                "join_lines",
+                "first_line_only",
+                "batch_size",
+                "filter",
+            ],
+        ),
+        (
+            "group_by",
+            &[
+                "batch_size",
+                "stdin_record",
+                "stdin_field",
+                "coprocess",
+                "explode",
+                "checkpoint",
+                "skip_populated",
+            ],
+        ),
+        (
+            "finally",
+            &["filter", "output_template", "output_sqlite", "checkpoint"],
+        ),
+        (
+            "split_output",
+            &["json_output", "capture", "replace_column"],
+        ),
+        ("json_output", &["capture", "replace_column"]),
+        ("capture", &["replace_column"]),
+        ("column_position", &["replace_column", "before", "after"]),
+        ("before", &["replace_column", "after"]),
+        ("after", &["replace_column"]),
+        ("drop", &["select"]),
+        (
+            "only_result",
+            &[
+                "select",
+                "drop",
+                "replace_column",
+                "column_position",
+                "before",
+                "after",
+            ],
+        ),
+        ("rename", &["no_headers"]),
+        ("coprocess", &["memoize", "timeout", "retries"]),
+        ("batch_size", &["coprocess"]),
+        ("stdin_record", &["coprocess", "batch_size", "stdin_field"]),
+        ("stdin_field", &["coprocess", "batch_size"]),
+        ("field_env", &["coprocess", "batch_size"]),
+        ("cwd", &["coprocess"]),
+        ("max_output_bytes", &["coprocess"]),
+        (
+            "filter",
+            &[
+                "new_column_name",
+                "replace_column",
+                "split_output",
+                "json_output",
+                "capture",
+                "exit_code_column",
+                "stderr_column",
+                "duration_column",
+                "started_column",
+                "attempts_column",
+                "only_result",
+                "skip_populated",
+                "condition",
+                "on_error",
+                "strict_exit",
+                "fail_fast",
+                "abort_after_failures",
+                "max_failure_rate",
+            ],
+        ),
+        ("dry_run", &["coprocess"]),
+        ("unique", &["memoize", "coprocess"]),
+        ("checkpoint", &["unordered"]),
+    ];
+    for (option, others) in conflicts {
+        for other in others.iter() {
+            let error = error(&[option, other]);
+            let expected = [
+                format!("{} cannot be used with {}", option, other),
+                format!("{} cannot be used with {}", other, option),
+            ];
+            assert!(
+                error.as_ref().is_some_and(|error| expected.contains(error)),
+                "{} with {}: {:?}",
+                option,
+                other,
+                error
+            );
+        }
+    }
+
+    let requirements = [
+        ("append", "output_path"),
+        ("no_clobber", "output_path"),
+        ("otherwise", "condition"),
+        ("summarize", "group_by"),
+        ("finally_output", "finally"),
+        ("split_output", "result_headers"),
+        ("result_headers", "split_output or json_output or capture"),
+        ("drop_unlisted", "order"),
+        ("pass_env", "clean_env"),
+        ("checkpoint", "output_path"),
+        ("resume", "checkpoint"),
+    ];
+    for (option, requirement) in requirements {
+        assert_eq!(
+            error(&[option]),
+            Some(format!("{} requires {}", option, requirement))
+        );
+    }
+}
+
+#[test]
+fn test_builder_typed_options() {
+    let input = "Id,Name\n1,a\n2,b\n";
+
+    let prefix = String::from("id");
+    let csv_exec = CsvExec::builder()
+        .command("echo $PREFIX-$Id:$Name")
+        .shell(true)
+        .env("PREFIX", prefix)
+        .env(String::from("SUFFIX"), "unused")
+        .split_output(b':')
+        .result_headers(vec!["Key", "Value"])
+        .select(["Id"])
+        .timeout(Duration::from_secs(5))
+        .jobs(2)
+        .build()
+        .unwrap();
+    let mut output = vec![];
+    let status = csv_exec.run(Cursor::new(input), &mut output).unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Id,Key,Value\n1,id-1,a\n2,id-2,b\n"
+    );
+
+    let csv_exec = CsvExec::builder()
+        .command("sleep $Id")
+        .timeout(Duration::from_millis(50))
+        .on_error(OnError::Empty)
+        .exit_code_column("Code")
+        .only_result(Vec::<String>::new())
+        .build()
+        .unwrap();
+    let mut output = vec![];
+    csv_exec.run(Cursor::new(input), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Row,Result,Code\n1,,\n2,,\n"
+    );
+}
+
+#[test]
+fn test_builder_messages() {
+    let input = "Id\n1\n2\n";
+    let run = |builder: csv_exec::Builder| {
+        let messages = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&messages);
+        let csv_exec = builder
+            .on_message(move |message| sink.lock().unwrap().push(message.to_string()))
+            .build()
+            .unwrap();
+        csv_exec.run(Cursor::new(input), vec![]).unwrap();
+        let messages = messages.lock().unwrap().clone();
+        messages
+    };
+
+    // Without the summary, even when the error output is a terminal
+    assert_eq!(
+        run(CsvExec::builder().command("true")),
+        ["Warning: the commands have no references to the records, so they are the same for each record"]
+    );
+
+    let messages = run(CsvExec::builder()
+        .command("./missing $Id")
+        .on_error(OnError::SkipRow)
+        .summary(true));
+    assert_eq!(messages.len(), 3);
+    assert_eq!(
+        messages[1],
+        "Error on record 2: Failed to execute command ./missing with args [\"2\"]: No such file or directory (os error 2)"
+    );
+    assert!(messages[2].starts_with("Rows: 2 (0 succeeded, 2 failed)\n"));
+}